@@ -0,0 +1,852 @@
+use crate::{
+    components::AnimationsType,
+    raylib::prelude::*,
+    serial::ReadExt,
+    world::units::{AtlasPos, TILE_SIZE},
+};
+use flate2::read::ZlibDecoder;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+/// magic number of an aseprite file header
+const HEADER_MAGIC: u16 = 0xa5e0;
+/// magic number of an aseprite frame header
+const FRAME_MAGIC: u16 = 0xf1fa;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+const CHUNK_SLICE: u16 = 0x2022;
+const CHUNK_TILESET: u16 = 0x2023;
+
+/// rgba pixel
+pub type Pixel = [u8; 4];
+
+/// a parsed aseprite file, everything not needed to flatten frames is skipped
+#[derive(Debug, Default, Clone)]
+pub struct Aseprite {
+    pub width: usize,
+    pub height: usize,
+    /// bits per pixel, 32 for rgba, 16 for grayscale and 8 for indexed
+    pub depth: u16,
+    pub transparent_index: u8,
+    pub palette: Vec<Pixel>,
+    pub layers: Vec<AseLayer>,
+    pub frames: Vec<AseFrame>,
+    pub tags: Vec<AseTag>,
+    pub slices: Vec<AseSlice>,
+    pub tilesets: Vec<AseTileset>,
+}
+#[derive(Debug, Default, Clone)]
+pub struct AseLayer {
+    pub name: String,
+    pub visible: bool,
+    /// 0 normal, 1 group, 2 tilemap
+    pub kind: u16,
+    pub child_level: u16,
+    pub opacity: u8,
+    pub tileset: usize,
+}
+#[derive(Debug, Default, Clone)]
+pub struct AseFrame {
+    /// duration in milliseconds
+    pub duration: u16,
+    pub cels: Vec<AseCel>,
+}
+#[derive(Debug, Clone)]
+pub struct AseCel {
+    pub layer: usize,
+    pub x: i32,
+    pub y: i32,
+    pub opacity: u8,
+    pub z_index: i16,
+    pub content: AseCelContent,
+}
+#[derive(Debug, Clone)]
+pub enum AseCelContent {
+    Image {
+        width: usize,
+        height: usize,
+        pixels: Vec<Pixel>,
+    },
+    /// uses the cel of the same layer in another frame
+    Linked(usize),
+    Tilemap {
+        width: usize,
+        height: usize,
+        tiles: Vec<u32>,
+        /// bits of a tile that hold the tile index
+        mask: u32,
+    },
+}
+#[derive(Debug, Default, Clone)]
+pub struct AseTag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+    /// 0 forward, 1 reverse, 2 ping pong, 3 ping pong reverse
+    pub direction: u8,
+}
+#[derive(Debug, Default, Clone)]
+pub struct AseSlice {
+    pub name: String,
+    /// first frame and bounds, valid until the next key
+    pub keys: Vec<(usize, Rectangle)>,
+}
+#[derive(Debug, Default, Clone)]
+pub struct AseTileset {
+    pub id: u32,
+    pub tile_width: usize,
+    pub tile_height: usize,
+    /// tiles stacked from top to bottom
+    pub pixels: Vec<Pixel>,
+}
+
+/// flattened frames of an `Aseprite` laid out from left to right,
+/// identical frames share their place
+#[derive(Debug, Default, Clone)]
+pub struct AseAtlas {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Pixel>,
+    /// atlas x of every frame in tiles
+    pub frame_x: Vec<usize>,
+}
+
+impl Aseprite {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn read(r: &mut impl Read) -> io::Result<Self> {
+        let mut header = [0; 128];
+        r.read_exact(&mut header)?;
+        let word = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        if word(4) != HEADER_MAGIC {
+            return Err(invalid("not an aseprite file"));
+        }
+        let mut ase = Aseprite {
+            width: word(8) as usize,
+            height: word(10) as usize,
+            depth: word(12),
+            transparent_index: header[28],
+            ..Default::default()
+        };
+        if !matches!(ase.depth, 8 | 16 | 32) {
+            return Err(invalid(format!("unsupported color depth {}", ase.depth)));
+        }
+        for _ in 0..word(6) {
+            ase.read_frame(r)?;
+        }
+        Ok(ase)
+    }
+    fn read_frame(&mut self, r: &mut impl Read) -> io::Result<()> {
+        let _size = r.read_u32()?;
+        if r.read_u16()? != FRAME_MAGIC {
+            return Err(invalid("invalid frame header"));
+        }
+        let old_chunks = r.read_u16()?;
+        let mut frame = AseFrame {
+            duration: r.read_u16()?,
+            cels: Vec::new(),
+        };
+        skip(r, 2)?;
+        let chunks = match r.read_u32()? {
+            0 => old_chunks as u32,
+            chunks => chunks,
+        };
+        for _ in 0..chunks {
+            let size = r.read_u32()? as usize;
+            let kind = r.read_u16()?;
+            let mut data = vec![0; size.saturating_sub(6)];
+            r.read_exact(&mut data)?;
+            let c = &mut data.as_slice();
+            match kind {
+                CHUNK_OLD_PALETTE if self.palette.is_empty() => self.read_old_palette(c)?,
+                CHUNK_PALETTE => self.read_palette(c)?,
+                CHUNK_LAYER => self.read_layer(c)?,
+                CHUNK_CEL => frame.cels.push(self.read_cel(c)?),
+                CHUNK_TAGS => self.read_tags(c)?,
+                CHUNK_SLICE => self.read_slice(c)?,
+                CHUNK_TILESET => self.read_tileset(c)?,
+                _ => {}
+            }
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+    fn read_old_palette(&mut self, c: &mut &[u8]) -> io::Result<()> {
+        let mut idx = 0;
+        for _ in 0..c.read_u16()? {
+            idx += c.read_u8()? as usize;
+            let count = match c.read_u8()? {
+                0 => 256,
+                count => count as usize,
+            };
+            for _ in 0..count {
+                let color = [c.read_u8()?, c.read_u8()?, c.read_u8()?, 255];
+                self.set_palette(idx, color);
+                idx += 1;
+            }
+        }
+        Ok(())
+    }
+    fn read_palette(&mut self, c: &mut &[u8]) -> io::Result<()> {
+        let _size = c.read_u32()?;
+        let first = c.read_u32()? as usize;
+        let last = c.read_u32()? as usize;
+        skip(c, 8)?;
+        for idx in first..=last {
+            let flags = c.read_u16()?;
+            let color = [c.read_u8()?, c.read_u8()?, c.read_u8()?, c.read_u8()?];
+            if flags & 1 != 0 {
+                read_string(c)?;
+            }
+            self.set_palette(idx, color);
+        }
+        Ok(())
+    }
+    fn set_palette(&mut self, idx: usize, color: Pixel) {
+        if self.palette.len() <= idx {
+            self.palette.resize(idx + 1, [0; 4]);
+        }
+        self.palette[idx] = color;
+    }
+    fn read_layer(&mut self, c: &mut &[u8]) -> io::Result<()> {
+        let flags = c.read_u16()?;
+        let kind = c.read_u16()?;
+        let child_level = c.read_u16()?;
+        skip(c, 6)?;
+        let opacity = c.read_u8()?;
+        skip(c, 3)?;
+        let name = read_string(c)?;
+        let tileset = if kind == 2 { c.read_u32()? as usize } else { 0 };
+        self.layers.push(AseLayer {
+            name,
+            visible: flags & 1 != 0,
+            kind,
+            child_level,
+            opacity,
+            tileset,
+        });
+        Ok(())
+    }
+    fn read_cel(&self, c: &mut &[u8]) -> io::Result<AseCel> {
+        let layer = c.read_u16()? as usize;
+        let x = c.read_u16()? as i16 as i32;
+        let y = c.read_u16()? as i16 as i32;
+        let opacity = c.read_u8()?;
+        let kind = c.read_u16()?;
+        let z_index = c.read_u16()? as i16;
+        skip(c, 5)?;
+        let content = match kind {
+            0 | 2 => {
+                let width = c.read_u16()? as usize;
+                let height = c.read_u16()? as usize;
+                let mut raw = Vec::new();
+                if kind == 0 {
+                    c.read_to_end(&mut raw)?;
+                } else {
+                    ZlibDecoder::new(*c).read_to_end(&mut raw)?;
+                }
+                AseCelContent::Image {
+                    width,
+                    height,
+                    pixels: self.pixels(&raw, width * height)?,
+                }
+            }
+            1 => AseCelContent::Linked(c.read_u16()? as usize),
+            3 => {
+                let width = c.read_u16()? as usize;
+                let height = c.read_u16()? as usize;
+                let bits = c.read_u16()?;
+                let mask = c.read_u32()?;
+                skip(c, 22)?;
+                let mut raw = Vec::new();
+                ZlibDecoder::new(*c).read_to_end(&mut raw)?;
+                let bytes = (bits / 8).max(1) as usize;
+                if raw.len() < width * height * bytes {
+                    return Err(invalid("tilemap cel is too short"));
+                }
+                let tiles = raw
+                    .chunks_exact(bytes)
+                    .take(width * height)
+                    .map(|b| {
+                        let mut v = [0; 4];
+                        v[..bytes.min(4)].copy_from_slice(&b[..bytes.min(4)]);
+                        u32::from_le_bytes(v)
+                    })
+                    .collect();
+                AseCelContent::Tilemap {
+                    width,
+                    height,
+                    tiles,
+                    mask,
+                }
+            }
+            kind => return Err(invalid(format!("unknown cel type {kind}"))),
+        };
+        Ok(AseCel {
+            layer,
+            x,
+            y,
+            opacity,
+            z_index,
+            content,
+        })
+    }
+    fn read_tags(&mut self, c: &mut &[u8]) -> io::Result<()> {
+        let count = c.read_u16()?;
+        skip(c, 8)?;
+        for _ in 0..count {
+            let from = c.read_u16()? as usize;
+            let to = c.read_u16()? as usize;
+            let direction = c.read_u8()?;
+            skip(c, 12)?;
+            self.tags.push(AseTag {
+                name: read_string(c)?,
+                from,
+                to,
+                direction,
+            });
+        }
+        Ok(())
+    }
+    fn read_slice(&mut self, c: &mut &[u8]) -> io::Result<()> {
+        let count = c.read_u32()?;
+        let flags = c.read_u32()?;
+        skip(c, 4)?;
+        let mut slice = AseSlice {
+            name: read_string(c)?,
+            keys: Vec::new(),
+        };
+        for _ in 0..count {
+            let frame = c.read_u32()? as usize;
+            let x = c.read_i32()?;
+            let y = c.read_i32()?;
+            let width = c.read_u32()?;
+            let height = c.read_u32()?;
+            // nine patch center and pivot
+            if flags & 1 != 0 {
+                skip(c, 16)?;
+            }
+            if flags & 2 != 0 {
+                skip(c, 8)?;
+            }
+            slice.keys.push((
+                frame,
+                Rectangle::new(x as f32, y as f32, width as f32, height as f32),
+            ));
+        }
+        self.slices.push(slice);
+        Ok(())
+    }
+    fn read_tileset(&mut self, c: &mut &[u8]) -> io::Result<()> {
+        let id = c.read_u32()?;
+        let flags = c.read_u32()?;
+        let count = c.read_u32()? as usize;
+        let tile_width = c.read_u16()? as usize;
+        let tile_height = c.read_u16()? as usize;
+        skip(c, 16)?;
+        read_string(c)?;
+        if flags & 1 != 0 {
+            skip(c, 8)?;
+        }
+        let mut tileset = AseTileset {
+            id,
+            tile_width,
+            tile_height,
+            pixels: Vec::new(),
+        };
+        if flags & 2 != 0 {
+            let len = c.read_u32()? as usize;
+            let mut raw = Vec::new();
+            ZlibDecoder::new(&c[..len.min(c.len())]).read_to_end(&mut raw)?;
+            tileset.pixels = self.pixels(&raw, tile_width * tile_height * count)?;
+        }
+        self.tilesets.push(tileset);
+        Ok(())
+    }
+    /// converts raw pixels of the file color depth to rgba
+    fn pixels(&self, raw: &[u8], count: usize) -> io::Result<Vec<Pixel>> {
+        let bytes = self.depth as usize / 8;
+        if raw.len() < count * bytes {
+            return Err(invalid("pixel data is too short"));
+        }
+        Ok(raw
+            .chunks_exact(bytes)
+            .take(count)
+            .map(|p| match p {
+                [r, g, b, a] => [*r, *g, *b, *a],
+                [v, a] => [*v, *v, *v, *a],
+                [idx] if *idx == self.transparent_index => [0; 4],
+                [idx] => self.palette.get(*idx as usize).copied().unwrap_or([0; 4]),
+                _ => [0; 4],
+            })
+            .collect())
+    }
+
+    /// returns if the layer and all of its parent groups are visible
+    pub fn layer_visible(&self, layer: usize) -> bool {
+        let Some(mut level) = self.layers.get(layer).map(|l| l.child_level) else {
+            return false;
+        };
+        if !self.layers[layer].visible {
+            return false;
+        }
+        for parent in self.layers[..layer].iter().rev() {
+            if parent.child_level < level {
+                if !parent.visible {
+                    return false;
+                }
+                level = parent.child_level;
+            }
+        }
+        true
+    }
+
+    /// composites the visible layers of `frame` with normal blending
+    pub fn flatten(&self, frame: usize) -> Vec<Pixel> {
+        let mut canvas = vec![[0; 4]; self.width * self.height];
+        let Some(cels) = self.frames.get(frame).map(|f| &f.cels) else {
+            return canvas;
+        };
+        let mut order: Vec<&AseCel> = cels
+            .iter()
+            .filter(|cel| self.layer_visible(cel.layer) && self.layers[cel.layer].kind != 1)
+            .collect();
+        order.sort_by_key(|cel| (cel.layer as i32 + cel.z_index as i32, cel.z_index));
+        for cel in order {
+            let opacity = cel.opacity as u32 * self.layers[cel.layer].opacity as u32 / 255;
+            let content = match &cel.content {
+                AseCelContent::Linked(other) => self
+                    .frames
+                    .get(*other)
+                    .and_then(|f| f.cels.iter().find(|c| c.layer == cel.layer))
+                    .map(|c| &c.content),
+                content => Some(content),
+            };
+            match content {
+                Some(AseCelContent::Image {
+                    width,
+                    height,
+                    pixels,
+                }) => {
+                    for y in 0..*height {
+                        for x in 0..*width {
+                            self.blend(
+                                &mut canvas,
+                                cel.x + x as i32,
+                                cel.y + y as i32,
+                                pixels[y * width + x],
+                                opacity,
+                            );
+                        }
+                    }
+                }
+                Some(AseCelContent::Tilemap {
+                    width,
+                    height,
+                    tiles,
+                    mask,
+                }) => {
+                    let Some(tileset) = self
+                        .tilesets
+                        .iter()
+                        .find(|t| t.id as usize == self.layers[cel.layer].tileset)
+                    else {
+                        continue;
+                    };
+                    let (tw, th) = (tileset.tile_width, tileset.tile_height);
+                    for ty in 0..*height {
+                        for tx in 0..*width {
+                            // tile 0 is always empty
+                            let tile = (tiles[ty * width + tx] & mask) as usize;
+                            if tile == 0 {
+                                continue;
+                            }
+                            for y in 0..th {
+                                for x in 0..tw {
+                                    let Some(pixel) = tileset.pixels.get((tile * th + y) * tw + x)
+                                    else {
+                                        continue;
+                                    };
+                                    self.blend(
+                                        &mut canvas,
+                                        cel.x + (tx * tw + x) as i32,
+                                        cel.y + (ty * th + y) as i32,
+                                        *pixel,
+                                        opacity,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        canvas
+    }
+    /// draws `src` over the canvas pixel
+    #[inline(always)]
+    fn blend(&self, canvas: &mut [Pixel], x: i32, y: i32, src: Pixel, opacity: u32) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let dst = &mut canvas[y as usize * self.width + x as usize];
+        let sa = src[3] as u32 * opacity / 255;
+        if sa == 0 {
+            return;
+        }
+        let da = dst[3] as u32 * (255 - sa) / 255;
+        let a = sa + da;
+        for i in 0..3 {
+            dst[i] = ((src[i] as u32 * sa + dst[i] as u32 * da) / a) as u8;
+        }
+        dst[3] = a as u8;
+    }
+
+    /// flattens every frame into an atlas
+    pub fn atlas(&self) -> AseAtlas {
+        let mut unique: Vec<Vec<Pixel>> = Vec::new();
+        let mut frame_x = Vec::with_capacity(self.frames.len());
+        for frame in 0..self.frames.len() {
+            let pixels = self.flatten(frame);
+            let idx = match unique.iter().position(|other| *other == pixels) {
+                Some(idx) => idx,
+                None => {
+                    unique.push(pixels);
+                    unique.len() - 1
+                }
+            };
+            frame_x.push(idx * self.width / TILE_SIZE);
+        }
+        let width = self.width * unique.len().max(1);
+        let mut pixels = vec![[0; 4]; width * self.height];
+        for (idx, frame) in unique.iter().enumerate() {
+            for y in 0..self.height {
+                let row = &frame[y * self.width..(y + 1) * self.width];
+                let start = y * width + idx * self.width;
+                pixels[start..start + self.width].copy_from_slice(row);
+            }
+        }
+        AseAtlas {
+            width,
+            height: self.height,
+            pixels,
+            frame_x,
+        }
+    }
+
+    /// returns the frames of a tag in playing order
+    pub fn tag_frames(tag: &AseTag) -> Vec<usize> {
+        let forward: Vec<usize> = (tag.from..=tag.to).collect();
+        let reverse: Vec<usize> = forward.iter().rev().copied().collect();
+        let bounce = |first: &[usize], second: &[usize]| {
+            let mut frames = first.to_vec();
+            if second.len() > 2 {
+                frames.extend_from_slice(&second[1..second.len() - 1]);
+            }
+            frames
+        };
+        match tag.direction {
+            1 => reverse,
+            2 => bounce(&forward, &reverse),
+            3 => bounce(&reverse, &forward),
+            _ => forward,
+        }
+    }
+    /// returns the bounds of the slice `name` at `frame`
+    pub fn slice_bounds(&self, name: &str, frame: usize) -> Option<Rectangle> {
+        let slice = self.slices.iter().find(|slice| slice.name == name)?;
+        slice
+            .keys
+            .iter()
+            .rev()
+            .find(|(first, _)| *first <= frame)
+            .or(slice.keys.first())
+            .map(|(_, bounds)| *bounds)
+    }
+
+    /// animations of every tag keyed by tag name, the sprite of a frame is
+    /// at the slice named like the tag or at the frame origin,
+    /// frames are repeated so each shows for its own duration, fails if a sprite is
+    /// further in the atlas than an `AtlasPos` can point
+    pub fn animations(&self, atlas: &AseAtlas) -> io::Result<AnimationsType> {
+        let mut animations: AnimationsType = HashMap::default();
+        for tag in self.tags.iter() {
+            let frames = Self::tag_frames(tag);
+            let durations: Vec<u32> = frames
+                .iter()
+                .map(|f| {
+                    self.frames
+                        .get(*f)
+                        .map_or(100, |f| f.duration.max(1) as u32)
+                })
+                .collect();
+            let step = durations.iter().copied().reduce(gcd).unwrap_or(100);
+            let mut atlas_frames = Vec::new();
+            for (frame, duration) in frames.iter().zip(durations) {
+                let bounds = self
+                    .slice_bounds(&tag.name, *frame)
+                    .unwrap_or(Rectangle::new(0.0, 0.0, 0.0, 0.0));
+                let x =
+                    atlas.frame_x.get(*frame).copied().unwrap_or(0) + bounds.x as usize / TILE_SIZE;
+                let y = bounds.y as usize / TILE_SIZE;
+                let (Ok(x), Ok(y)) = (u8::try_from(x), u8::try_from(y)) else {
+                    return Err(invalid(format!(
+                        "frame {frame} of tag {:?} is at tile {x},{y}, past the atlas limit",
+                        tag.name
+                    )));
+                };
+                let atlas_pos = AtlasPos { x, y };
+                for _ in 0..duration / step {
+                    atlas_frames.push(atlas_pos);
+                }
+            }
+            animations.insert(tag.name.clone(), (atlas_frames, 1000.0 / step as f32));
+        }
+        Ok(animations)
+    }
+}
+
+impl AseAtlas {
+    /// copies the atlas into a raylib `Image`
+    pub fn image(&self) -> Image {
+        let mut image = Image::gen_image_color(self.width as i32, self.height as i32, Color::BLANK);
+        for (i, [r, g, b, a]) in self.pixels.iter().copied().enumerate() {
+            if a > 0 {
+                image.draw_pixel(
+                    (i % self.width) as i32,
+                    (i / self.width) as i32,
+                    Color::new(r, g, b, a),
+                );
+            }
+        }
+        image
+    }
+}
+
+#[inline(always)]
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+fn skip(r: &mut impl Read, n: u64) -> io::Result<()> {
+    io::copy(&mut r.take(n), &mut io::sink())?;
+    Ok(())
+}
+fn read_string(r: &mut impl Read) -> io::Result<String> {
+    let len = r.read_u16()? as usize;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|err| invalid(err.to_string()))
+}
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(layer: usize, opacity: u8, pixels: Vec<Pixel>) -> AseCel {
+        AseCel {
+            layer,
+            x: 0,
+            y: 0,
+            opacity,
+            z_index: 0,
+            content: AseCelContent::Image {
+                width: pixels.len(),
+                height: 1,
+                pixels,
+            },
+        }
+    }
+    fn layer(visible: bool, kind: u16, child_level: u16) -> AseLayer {
+        AseLayer {
+            visible,
+            kind,
+            child_level,
+            opacity: 255,
+            ..Default::default()
+        }
+    }
+
+    fn tagged(frame_x: usize, slice_x: usize) -> io::Result<AnimationsType> {
+        let tag = AseTag {
+            name: "idle".to_string(),
+            ..Default::default()
+        };
+        let slice = AseSlice {
+            name: tag.name.clone(),
+            keys: vec![(
+                0,
+                Rectangle::new((slice_x * TILE_SIZE) as f32, 0.0, 32.0, 32.0),
+            )],
+        };
+        let ase = Aseprite {
+            frames: vec![AseFrame::default()],
+            tags: vec![tag],
+            slices: vec![slice],
+            ..Default::default()
+        };
+        let atlas = AseAtlas {
+            frame_x: vec![frame_x],
+            ..Default::default()
+        };
+        ase.animations(&atlas)
+    }
+
+    #[test]
+    fn sprites_past_the_atlas_limit_fail() {
+        let animations = tagged(250, 5).unwrap();
+        assert_eq!(animations["idle"].0, vec![AtlasPos { x: 255, y: 0 }]);
+        let err = tagged(250, 6).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = tagged(300, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn tileset_has_the_player_animations() {
+        let ase = Aseprite::load(crate::TILESET_FILE).unwrap();
+        let animations = ase.animations(&ase.atlas()).unwrap();
+        // the player switches between these and its prefab starts on idle-side
+        for dir in ["side", "up", "down"] {
+            let tag = ase
+                .tags
+                .iter()
+                .find(|tag| tag.name == format!("walk-{dir}"))
+                .unwrap();
+            assert_eq!(Aseprite::tag_frames(tag).len(), 4, "walk-{dir}");
+            for frame in tag.from..=tag.to {
+                assert_eq!(ase.frames[frame].duration, 100, "walk-{dir}");
+            }
+            let (frames, fps) = &animations[&format!("walk-{dir}")];
+            assert_eq!((frames.len(), *fps), (4, 10.0), "walk-{dir}");
+
+            let tag = ase
+                .tags
+                .iter()
+                .find(|tag| tag.name == format!("idle-{dir}"))
+                .unwrap();
+            assert_eq!(Aseprite::tag_frames(tag).len(), 1, "idle-{dir}");
+            assert_eq!(ase.frames[tag.from].duration, 1000, "idle-{dir}");
+            let (frames, fps) = &animations[&format!("idle-{dir}")];
+            assert_eq!((frames.len(), *fps), (1, 1.0), "idle-{dir}");
+        }
+    }
+
+    #[test]
+    fn flatten_blends_visible_layers() {
+        const RED: Pixel = [255, 0, 0, 255];
+        const BLUE: Pixel = [0, 0, 255, 255];
+        let mut ase = Aseprite {
+            width: 2,
+            height: 1,
+            // a visible layer, a hidden group with a visible child and a
+            // visible layer on top
+            layers: vec![
+                layer(true, 0, 0),
+                layer(false, 1, 0),
+                layer(true, 0, 1),
+                layer(true, 0, 0),
+            ],
+            frames: vec![
+                AseFrame {
+                    duration: 100,
+                    cels: vec![
+                        image(0, 255, vec![RED, RED]),
+                        image(2, 255, vec![BLUE, BLUE]),
+                        image(3, 255, vec![[0; 4], BLUE]),
+                    ],
+                },
+                AseFrame {
+                    duration: 100,
+                    cels: vec![AseCel {
+                        content: AseCelContent::Linked(0),
+                        ..image(0, 255, Vec::new())
+                    }],
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(!ase.layer_visible(2));
+        assert_eq!(ase.flatten(0), vec![RED, BLUE]);
+        // linked cels draw the cel of the other frame
+        assert_eq!(ase.flatten(1), vec![RED, RED]);
+
+        // half transparent cels blend over the layers below
+        ase.frames[0].cels[2] = image(3, 128, vec![[0; 4], BLUE]);
+        let [_, blended] = ase.flatten(0)[..] else {
+            panic!("expected two pixels");
+        };
+        assert_eq!(blended[3], 255);
+        assert!(blended[0].abs_diff(127) <= 1 && blended[2].abs_diff(128) <= 1);
+    }
+
+    #[test]
+    fn tag_frames_follow_the_direction() {
+        let tag = |direction| AseTag {
+            from: 1,
+            to: 3,
+            direction,
+            ..Default::default()
+        };
+        assert_eq!(Aseprite::tag_frames(&tag(0)), vec![1, 2, 3]);
+        assert_eq!(Aseprite::tag_frames(&tag(1)), vec![3, 2, 1]);
+        assert_eq!(Aseprite::tag_frames(&tag(2)), vec![1, 2, 3, 2]);
+        assert_eq!(Aseprite::tag_frames(&tag(3)), vec![3, 2, 1, 2]);
+        let single = AseTag {
+            from: 4,
+            to: 4,
+            direction: 2,
+            ..Default::default()
+        };
+        assert_eq!(Aseprite::tag_frames(&single), vec![4]);
+    }
+
+    #[test]
+    fn longer_frames_repeat() {
+        let slice = AseSlice {
+            name: "blink".to_string(),
+            keys: vec![
+                (0, Rectangle::new(0.0, 0.0, 32.0, 32.0)),
+                (1, Rectangle::new(0.0, TILE_SIZE as f32, 32.0, 32.0)),
+            ],
+        };
+        let ase = Aseprite {
+            frames: [200, 100, 300]
+                .into_iter()
+                .map(|duration| AseFrame {
+                    duration,
+                    cels: Vec::new(),
+                })
+                .collect(),
+            tags: vec![AseTag {
+                name: "blink".to_string(),
+                from: 0,
+                to: 2,
+                ..Default::default()
+            }],
+            slices: vec![slice],
+            ..Default::default()
+        };
+        let atlas = AseAtlas {
+            frame_x: vec![0, 0, 0],
+            ..Default::default()
+        };
+        let (frames, fps) = &ase.animations(&atlas).unwrap()["blink"];
+        let (open, closed) = (AtlasPos { x: 0, y: 0 }, AtlasPos { x: 0, y: 1 });
+        assert_eq!(*fps, 10.0);
+        assert_eq!(*frames, vec![open, open, closed, closed, closed, closed]);
+    }
+}
@@ -0,0 +1,208 @@
+//! headless top-down map exporter
+//!
+//! usage: picocraft-map [--map NAME] [--seed N] [--from X,Y[,Z]] [--to X,Y[,Z]] [--world DIR] [--blocks FILE] [--structures FILE] [--terrain FILE] [--stored-only] [--out FILE]
+extern crate picocraft;
+
+use picocraft::world::{
+    export::render_top_down,
+    generator::{CaveGenerator, OverWorldGenerator, TerrainGenerator, gen_area},
+    map::{BlockSet, OVERWORLD, UNDERGROUND},
+    noise_graph::NoiseGraph,
+    storage::RegionStore,
+    structure::StructureSet,
+    units::ChunkPos,
+};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+struct Args {
+    map: String,
+    seed: u32,
+    start: ChunkPos,
+    end: ChunkPos,
+    world: Option<String>,
+    blocks: Option<String>,
+    structures: Option<String>,
+    terrain: Option<String>,
+    stored_only: bool,
+    out: String,
+}
+
+/// parses `X,Y` or `X,Y,Z`, levels default to 0
+fn parse_chunk_pos(s: &str) -> Result<ChunkPos, String> {
+    let parts: Vec<&str> = s.split(',').collect();
+    if !(2..=3).contains(&parts.len()) {
+        return Err(format!("expected X,Y or X,Y,Z chunk position, got {s:?}"));
+    }
+    let coord = |i: usize, name: &str| -> Result<i32, String> {
+        parts.get(i).map_or(Ok(0), |part| {
+            part.trim()
+                .parse()
+                .map_err(|err| format!("invalid {name} {part:?}: {err}"))
+        })
+    };
+    Ok(ChunkPos {
+        x: coord(0, "x")?,
+        y: coord(1, "y")?,
+        z: coord(2, "z")?,
+    })
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        map: OVERWORLD.into(),
+        seed: 42,
+        start: ChunkPos { x: -4, y: -4, z: 0 },
+        end: ChunkPos { x: 4, y: 4, z: 0 },
+        world: None,
+        blocks: None,
+        structures: None,
+        terrain: None,
+        stored_only: false,
+        out: "map.png".into(),
+    };
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "--map" => args.map = value()?,
+            "--seed" => {
+                let v = value()?;
+                args.seed = v
+                    .parse()
+                    .map_err(|err| format!("invalid seed {v:?}: {err}"))?;
+            }
+            "--from" => args.start = parse_chunk_pos(&value()?)?,
+            "--to" => args.end = parse_chunk_pos(&value()?)?,
+            "--world" => args.world = Some(value()?),
+            "--blocks" => args.blocks = Some(value()?),
+            "--structures" => args.structures = Some(value()?),
+            "--terrain" => args.terrain = Some(value()?),
+            "--stored-only" => args.stored_only = true,
+            "--out" => args.out = value()?,
+            "--help" | "-h" => {
+                println!(
+                    "usage: picocraft-map [--map NAME] [--seed N] [--from X,Y[,Z]] [--to X,Y[,Z]] [--world DIR] [--blocks FILE] [--structures FILE] [--terrain FILE] [--stored-only] [--out FILE]"
+                );
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown argument {arg:?}")),
+        }
+    }
+    if args.map != OVERWORLD && args.map != UNDERGROUND {
+        return Err(format!(
+            "unknown map {:?}, expected {OVERWORLD} or {UNDERGROUND}",
+            args.map
+        ));
+    }
+    if args.start.x > args.end.x || args.start.y > args.end.y || args.start.z > args.end.z {
+        return Err("--from must be the upper left and lowest corner of --to".into());
+    }
+    Ok(args)
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(2);
+        }
+    };
+
+    let blockset = match &args.blocks {
+        Some(path) => BlockSet::load(path).unwrap_or_else(|err| {
+            eprintln!("error: failed to load blocks: {err}");
+            process::exit(1);
+        }),
+        None => BlockSet::normal(),
+    };
+    let structures = match &args.structures {
+        Some(path) => StructureSet::load(path, &blockset).unwrap_or_else(|err| {
+            eprintln!("error: failed to load structures: {err}");
+            process::exit(1);
+        }),
+        None => StructureSet::normal(&blockset),
+    };
+    let terrain = match &args.terrain {
+        Some(path) => NoiseGraph::load(path).unwrap_or_else(|err| {
+            eprintln!("error: failed to load terrain: {err}");
+            process::exit(1);
+        }),
+        None => NoiseGraph::normal(),
+    };
+
+    // stored chunks from a saved world
+    let mut chunks = Default::default();
+    if let Some(world) = &args.world {
+        if !Path::new(world).is_dir() {
+            eprintln!("error: world {world:?} does not exist");
+            process::exit(1);
+        }
+        // the game stores the underground next to the overworld regions
+        let dir = match args.map.as_str() {
+            UNDERGROUND => Path::new(world).join(UNDERGROUND),
+            _ => PathBuf::from(world),
+        };
+        let mut storage = match RegionStore::open(&dir, blockset.palette()) {
+            Ok(storage) => storage,
+            Err(err) => {
+                eprintln!("error: failed to open world {dir:?}: {err}");
+                process::exit(1);
+            }
+        };
+        chunks = storage
+            .load_area(args.start, args.end)
+            .unwrap_or_else(|err| {
+                eprintln!("error: failed to read world {dir:?}: {err}");
+                process::exit(1);
+            });
+    }
+
+    // generate everything that was not stored
+    if !args.stored_only {
+        let generated = if args.map == UNDERGROUND {
+            let mut generator = CaveGenerator::default();
+            generator.set_seed(args.seed);
+            gen_area(&generator, args.start, args.end)
+        } else {
+            let mut generator = OverWorldGenerator {
+                structures,
+                terrain: Some(terrain),
+                ..Default::default()
+            };
+            generator.set_seed(args.seed);
+            gen_area(&generator, args.start, args.end)
+        };
+        for (cpos, chunk) in generated {
+            chunks.entry(cpos).or_insert(chunk);
+        }
+    }
+
+    let image = render_top_down(&chunks, &blockset, args.start, args.end);
+    // raylib only logs failed exports, a missing file is the only sign of one
+    if let Err(err) = fs::remove_file(&args.out)
+        && err.kind() != io::ErrorKind::NotFound
+    {
+        eprintln!("error: failed to replace {:?}: {err}", args.out);
+        process::exit(1);
+    }
+    image.export_image(&args.out);
+    if !Path::new(&args.out).is_file() {
+        eprintln!("error: failed to write {:?}", args.out);
+        process::exit(1);
+    }
+    println!(
+        "wrote {} chunks ({}x{} px) to {}",
+        chunks.len(),
+        image.width(),
+        image.height(),
+        args.out
+    );
+}
@@ -0,0 +1,194 @@
+//! generator regression snapshots
+//!
+//! usage: picocraft-snapshot record [--map NAME] [--seed N] [--from X,Y[,Z]] [--to X,Y[,Z]] [--png FILE] FILE
+//!        picocraft-snapshot check FILE...
+extern crate picocraft;
+
+use picocraft::world::{
+    export::render_top_down,
+    map::{BlockSet, OVERWORLD},
+    pipeline::default_threads,
+    snapshot::{ChunkDiff, Snapshot, generate_map},
+    units::ChunkPos,
+};
+use std::{env, process};
+
+const USAGE: &str = "usage: picocraft-snapshot record [--map NAME] [--seed N] [--from X,Y[,Z]] [--to X,Y[,Z]] [--png FILE] FILE
+       picocraft-snapshot check FILE...";
+/// changed blocks listed per chunk
+const SHOWN_CHANGES: usize = 5;
+
+/// parses `X,Y` or `X,Y,Z`, levels default to 0
+fn parse_chunk_pos(s: &str) -> Result<ChunkPos, String> {
+    let parts: Vec<&str> = s.split(',').collect();
+    if !(2..=3).contains(&parts.len()) {
+        return Err(format!("expected X,Y or X,Y,Z chunk position, got {s:?}"));
+    }
+    let coord = |i: usize, name: &str| -> Result<i32, String> {
+        parts.get(i).map_or(Ok(0), |part| {
+            part.trim()
+                .parse()
+                .map_err(|err| format!("invalid {name} {part:?}: {err}"))
+        })
+    };
+    Ok(ChunkPos {
+        x: coord(0, "x")?,
+        y: coord(1, "y")?,
+        z: coord(2, "z")?,
+    })
+}
+
+/// generates the area of a map with the generator settings shipped with the game,
+/// single-threaded and on as many generator threads as the game, both have to match
+fn generate(
+    map: &str,
+    seed: u32,
+    start: ChunkPos,
+    end: ChunkPos,
+    blockset: &BlockSet,
+) -> Result<Snapshot, String> {
+    let (snapshot, threaded) = generate_map(map, seed, start, end, blockset, default_threads())
+        .map_err(|err| err.to_string())?;
+    if !threaded.is_empty() {
+        print_diffs(&threaded, blockset);
+        return Err("threaded generation differs from the single-threaded run".into());
+    }
+    Ok(snapshot)
+}
+
+fn print_diffs(diffs: &[ChunkDiff], blockset: &BlockSet) {
+    for diff in diffs {
+        match diff {
+            ChunkDiff::Changed(cpos, changes) => {
+                println!(
+                    "  chunk {},{},{}: {} blocks changed",
+                    cpos.x,
+                    cpos.y,
+                    cpos.z,
+                    changes.len()
+                );
+                for change in changes.iter().take(SHOWN_CHANGES) {
+                    println!(
+                        "    {},{},{}: {} -> {}",
+                        change.pos.x,
+                        change.pos.y,
+                        change.pos.z,
+                        blockset.name(change.old),
+                        blockset.name(change.new)
+                    );
+                }
+                if changes.len() > SHOWN_CHANGES {
+                    println!("    ...");
+                }
+            }
+            ChunkDiff::Missing(cpos) => {
+                println!("  chunk {},{},{}: missing", cpos.x, cpos.y, cpos.z)
+            }
+            ChunkDiff::Added(cpos) => {
+                println!(
+                    "  chunk {},{},{}: not in the snapshot",
+                    cpos.x, cpos.y, cpos.z
+                )
+            }
+        }
+    }
+}
+
+fn record(args: &[String], blockset: &BlockSet) -> Result<(), String> {
+    let mut map = OVERWORLD.to_string();
+    let mut seed = 42;
+    let mut start = ChunkPos {
+        x: -2,
+        y: -2,
+        z: -1,
+    };
+    let mut end = ChunkPos { x: 2, y: 2, z: 1 };
+    let mut png = None;
+    let mut out = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "--map" => map = value()?.clone(),
+            "--seed" => {
+                let v = value()?;
+                seed = v
+                    .parse()
+                    .map_err(|err| format!("invalid seed {v:?}: {err}"))?;
+            }
+            "--from" => start = parse_chunk_pos(value()?)?,
+            "--to" => end = parse_chunk_pos(value()?)?,
+            "--png" => png = Some(value()?.clone()),
+            _ if out.is_none() && !arg.starts_with("--") => out = Some(arg.clone()),
+            _ => return Err(format!("unknown argument {arg:?}")),
+        }
+    }
+    let out = out.ok_or("missing snapshot FILE")?;
+    if start.x > end.x || start.y > end.y || start.z > end.z {
+        return Err("--from must be the upper left and lowest corner of --to".into());
+    }
+    let snapshot = generate(&map, seed, start, end, blockset)?;
+    snapshot
+        .save(&out)
+        .map_err(|err| format!("failed to write {out:?}: {err}"))?;
+    println!(
+        "recorded {} chunks of {map} seed {seed} to {out}: {:016x}",
+        snapshot.chunks.len(),
+        snapshot.hash()
+    );
+    if let Some(png) = png {
+        render_top_down(&snapshot.to_chunks(), blockset, start, end).export_image(&png);
+    }
+    Ok(())
+}
+
+/// regenerates every snapshot and reports the chunks that changed, returns if all matched
+fn check(files: &[String], blockset: &BlockSet) -> Result<bool, String> {
+    if files.is_empty() {
+        return Err("missing snapshot FILE".into());
+    }
+    let mut passed = true;
+    for file in files {
+        let stored =
+            Snapshot::load(file).map_err(|err| format!("failed to read snapshot: {err}"))?;
+        let snapshot = generate(&stored.map, stored.seed, stored.start, stored.end, blockset)?;
+        let diffs = stored.diff(&snapshot);
+        if diffs.is_empty() {
+            println!("ok {file}: {:016x}", snapshot.hash());
+        } else {
+            println!(
+                "FAILED {file}: {:016x} instead of {:016x}",
+                snapshot.hash(),
+                stored.hash()
+            );
+            print_diffs(&diffs, blockset);
+            passed = false;
+        }
+    }
+    Ok(passed)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let blockset = BlockSet::normal();
+    let result = match args.first().map(|arg| arg.as_str()) {
+        Some("record") => record(&args[1..], &blockset).map(|_| true),
+        Some("check") => check(&args[1..], &blockset),
+        Some("--help" | "-h") => {
+            println!("{USAGE}");
+            return;
+        }
+        _ => Err(format!("expected record or check\n{USAGE}")),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(2);
+        }
+    }
+}
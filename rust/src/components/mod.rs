@@ -2,14 +2,24 @@ pub mod player;
 
 use crate::{
     GameData, GameEvent,
+    prefab::{PrefabComponent, PrefabContext},
     raylib::prelude::*,
+    save::{LoadContext, SaveComponent},
+    serial::{ReadExt, WriteExt},
     world::{
-        map::BlockMapDrawBuffer,
-        units::{AtlasPos, TILE_SIZE, WorldBlockPos},
+        map::{BlockMap, BlockMapDrawBuffer, KEEP_RADIUS, OVERWORLD, PORTAL_SEARCH_RADIUS},
+        units::{AtlasPos, ChunkPos, TILE_SIZE, WorldBlockPos},
     },
 };
 use hecs::{Entity, World};
-use std::{collections::HashMap, fmt::Debug, sync::Arc};
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{self, Read, Write},
+    sync::Arc,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Body {
@@ -71,19 +81,19 @@ impl Body {
     }
     #[inline(always)]
     pub fn update_overlap(world: &mut World, data: &mut GameData) {
-        let mut snap: Vec<(Entity, Body)> = Vec::new();
+        let mut snap: Vec<(Entity, Body, String)> = Vec::new();
         // collect all the bodies
-        for (ent, body) in world.query::<(Entity, &Body)>().iter() {
-            snap.push((ent, body.clone()));
+        for (ent, body, dimension) in world.query::<(Entity, &Body, Option<&Dimension>)>().iter() {
+            snap.push((ent, body.clone(), Dimension::name(dimension).to_string()));
         }
         if snap.len() <= 1 {
             // nothing that can overlap
             return;
         }
         // find overlaps
-        for (ent_a, body_a) in snap.iter() {
-            for (ent_b, body_b) in snap.iter() {
-                if *ent_a == *ent_b {
+        for (ent_a, body_a, map_a) in snap.iter() {
+            for (ent_b, body_b, map_b) in snap.iter() {
+                if *ent_a == *ent_b || map_a != map_b {
                     continue;
                 }
                 if body_a.overlap(body_b) {
@@ -96,6 +106,130 @@ impl Body {
         }
     }
 }
+/// the `BlockMap` an entity is in, entities without one are in the `OVERWORLD`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dimension {
+    pub map: String,
+    /// the body stands in a portal, it only moves again after leaving it
+    pub in_portal: bool,
+}
+impl Dimension {
+    pub fn new(map: impl Into<String>) -> Self {
+        Self {
+            map: map.into(),
+            in_portal: false,
+        }
+    }
+    /// name of the map of an entity with an optional `Dimension`
+    #[inline(always)]
+    pub fn name(dimension: Option<&Self>) -> &str {
+        dimension.map_or(OVERWORLD, |dimension| dimension.map.as_str())
+    }
+    /// moves bodies that step into a portal block to the closest place to stand in the
+    /// map the portal leads to
+    pub fn update_portals(world: &mut World) {
+        let mut changes = Vec::new();
+        let mut travels = Vec::new();
+        {
+            let mut maps = world.query::<&BlockMap>();
+            let maps: FxHashMap<&str, &BlockMap> = maps
+                .iter()
+                .map(|block_map| (block_map.name.as_str(), block_map))
+                .collect();
+            for (ent, body, dimension) in
+                world.query::<(Entity, &Body, Option<&Dimension>)>().iter()
+            {
+                let name = Self::name(dimension);
+                let Some(block_map) = maps.get(name) else {
+                    continue;
+                };
+                let x = (body.pos.x + body.size.x / 2.0).floor() as i32;
+                let y = (body.pos.y + body.size.y / 2.0).floor() as i32;
+                let in_portal = block_map.has_portal(x, y);
+                let was_in_portal = dimension.is_some_and(|dimension| dimension.in_portal);
+                if in_portal
+                    && !was_in_portal
+                    && let Some(target) = &block_map.portal
+                {
+                    let pos = WorldBlockPos {
+                        x: body.pos.x.floor() as i32,
+                        y: body.pos.y.floor() as i32,
+                        z: body.pos.z.floor() as i32,
+                    };
+                    travels.push((ent, name.to_string(), target.clone(), pos));
+                } else if in_portal != was_in_portal {
+                    changes.push((
+                        ent,
+                        Self {
+                            map: name.to_string(),
+                            in_portal,
+                        },
+                    ));
+                }
+            }
+        }
+        for (ent, name, target, pos) in travels {
+            let spot = world
+                .query_mut::<&mut BlockMap>()
+                .into_iter()
+                .find(|block_map| block_map.name == target)
+                .and_then(|block_map| block_map.find_standing(pos, PORTAL_SEARCH_RADIUS));
+            // bodies stay in the portal when there is nowhere to stand on the other side
+            let Some(spot) = spot else {
+                eprintln!("found no place to stand in {target:?} near {pos:?}");
+                changes.push((
+                    ent,
+                    Self {
+                        map: name,
+                        in_portal: true,
+                    },
+                ));
+                continue;
+            };
+            if let Ok(mut body) = world.get::<&mut Body>(ent) {
+                body.pos = Vector3::new(spot.x as f32, spot.y as f32, spot.z as f32);
+            }
+            changes.push((
+                ent,
+                Self {
+                    map: target,
+                    in_portal: true,
+                },
+            ));
+        }
+        for (ent, dimension) in changes {
+            let _ = world.insert_one(ent, dimension);
+        }
+    }
+}
+/// keeps the chunks within `radius` of the body loaded in the map of its `Dimension`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkLoader {
+    pub radius: i32,
+}
+impl ChunkLoader {
+    /// hands the chunk of every loader to its `BlockMap`
+    pub fn update_loaders(world: &mut World) {
+        let mut loaders: FxHashMap<String, Vec<(ChunkPos, i32)>> = FxHashMap::default();
+        for (body, loader, dimension) in world
+            .query::<(&Body, &ChunkLoader, Option<&Dimension>)>()
+            .iter()
+        {
+            let cpos = ChunkPos::from(WorldBlockPos {
+                x: body.pos.x.floor() as i32,
+                y: body.pos.y.floor() as i32,
+                z: body.pos.z.floor() as i32,
+            });
+            loaders
+                .entry(Dimension::name(dimension).to_string())
+                .or_default()
+                .push((cpos, loader.radius));
+        }
+        for block_map in world.query_mut::<&mut BlockMap>() {
+            block_map.loaders = loaders.remove(&block_map.name).unwrap_or_default();
+        }
+    }
+}
 // PHYSICS
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct Physics {
@@ -105,12 +239,36 @@ pub const GRAVITY: f32 = 10.0;
 impl Physics {
     #[inline(always)]
     pub fn update_gravity(world: &mut World, dt: f32) {
-        for (body, physics) in world.query_mut::<(&mut Body, &mut Physics)>() {
+        let mut maps = world.query::<&BlockMap>();
+        let maps: FxHashMap<&str, &BlockMap> = maps
+            .iter()
+            .map(|block_map| (block_map.name.as_str(), block_map))
+            .collect();
+        for (body, physics, dimension) in world
+            .query::<(&mut Body, &mut Physics, Option<&Dimension>)>()
+            .iter()
+        {
+            // bodies land on the first solid block below where they started to fall
+            let ground = match maps.get(Dimension::name(dimension)) {
+                Some(block_map) => block_map.ground_below(
+                    body.pos.x.floor() as i32,
+                    body.pos.y.floor() as i32,
+                    body.pos.z.floor() as i32,
+                ),
+                // bodies outside of every map keep the old fixed floor
+                None => Some(2),
+            };
+            // bodies wait in the air until the chunks below them are loaded
+            let Some(ground) = ground else {
+                physics.vel.z = 0.0;
+                body.pos += physics.vel;
+                continue;
+            };
+            let ground = ground as f32;
             physics.vel.z -= GRAVITY * dt;
             body.pos += physics.vel;
-            // naiv collision for now
-            if body.pos.z < 2.0 {
-                body.pos.z = 2.0;
+            if body.pos.z < ground {
+                body.pos.z = ground;
                 physics.vel.z = 0.0;
             }
         }
@@ -136,8 +294,13 @@ impl AtlasSprite {
         }
     }
     #[inline(always)]
-    pub fn draw_with_body(world: &mut World, d: &mut BlockMapDrawBuffer, _data: &GameData) {
-        for (body, atlas_sprite) in world.query::<(&Body, &Self)>().iter() {
+    pub fn draw_with_body(world: &mut World, d: &mut BlockMapDrawBuffer, data: &GameData) {
+        for (body, atlas_sprite, dimension) in
+            world.query::<(&Body, &Self, Option<&Dimension>)>().iter()
+        {
+            if Dimension::name(dimension) != data.active_map {
+                continue;
+            }
             let src = atlas_sprite.source();
             d.register(
                 WorldBlockPos {
@@ -152,25 +315,25 @@ impl AtlasSprite {
         }
     }
 }
-pub type AnimationsType = HashMap<&'static str, (&'static [AtlasPos], f32)>;
+pub type AnimationsType = HashMap<String, (Vec<AtlasPos>, f32)>;
 #[derive(Debug, Default, Clone)]
 pub struct AtlasSpriteAnimation {
     pub animations: Arc<AnimationsType>,
-    pub current: &'static str,
+    pub current: String,
     pub time: f32,
     pub flip_h: bool,
     pub flip_v: bool,
 }
 impl AtlasSpriteAnimation {
-    pub fn switch_animation(&mut self, new: &'static str) {
+    pub fn switch_animation(&mut self, new: &str) {
         if new != self.current {
             self.time = 0.0;
-            self.current = new;
+            self.current = new.to_string();
         }
     }
     #[inline(always)]
     pub fn source(&self) -> Rectangle {
-        let (arr, scale) = *self.animations.get(&self.current).unwrap();
+        let (arr, scale) = self.animations.get(&self.current).unwrap();
         let len = arr.len();
         let atlas_pos = arr[(self.time * scale).floor() as usize % len];
         Rectangle {
@@ -195,9 +358,14 @@ impl AtlasSpriteAnimation {
         }
     }
     #[inline(always)]
-    pub fn draw_with_body(world: &mut World, d: &mut BlockMapDrawBuffer, _data: &GameData) {
-        for (body, atlas_sprite_animation) in world.query::<(&Body, &AtlasSpriteAnimation)>().iter()
+    pub fn draw_with_body(world: &mut World, d: &mut BlockMapDrawBuffer, data: &GameData) {
+        for (body, atlas_sprite_animation, dimension) in world
+            .query::<(&Body, &AtlasSpriteAnimation, Option<&Dimension>)>()
+            .iter()
         {
+            if Dimension::name(dimension) != data.active_map {
+                continue;
+            }
             let src = atlas_sprite_animation.source();
             d.register(
                 WorldBlockPos {
@@ -253,10 +421,237 @@ impl Controller {
     }
 }
 
+impl SaveComponent for Body {
+    const NAME: &'static str = "body";
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_vector3(self.pos)?;
+        w.write_vector3(self.size)
+    }
+    fn read(r: &mut dyn Read, _ctx: &LoadContext) -> io::Result<Self> {
+        Ok(Self {
+            pos: r.read_vector3()?,
+            size: r.read_vector3()?,
+        })
+    }
+}
+impl SaveComponent for Dimension {
+    const NAME: &'static str = "dimension";
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_str(&self.map)?;
+        w.write_u8(self.in_portal as u8)
+    }
+    fn read(r: &mut dyn Read, _ctx: &LoadContext) -> io::Result<Self> {
+        Ok(Self {
+            map: r.read_str()?,
+            in_portal: r.read_u8()? != 0,
+        })
+    }
+}
+impl SaveComponent for ChunkLoader {
+    const NAME: &'static str = "chunk-loader";
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_i32(self.radius)
+    }
+    fn read(r: &mut dyn Read, _ctx: &LoadContext) -> io::Result<Self> {
+        Ok(Self {
+            radius: r.read_i32()?,
+        })
+    }
+}
+impl SaveComponent for Physics {
+    const NAME: &'static str = "physics";
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_vector3(self.vel)
+    }
+    fn read(r: &mut dyn Read, _ctx: &LoadContext) -> io::Result<Self> {
+        Ok(Self {
+            vel: r.read_vector3()?,
+        })
+    }
+}
+impl SaveComponent for Controller {
+    const NAME: &'static str = "controller";
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        // input state is polled every frame, only the speed matters
+        w.write_f32(self.speed)
+    }
+    fn read(r: &mut dyn Read, _ctx: &LoadContext) -> io::Result<Self> {
+        Ok(Self {
+            speed: r.read_f32()?,
+            ..Default::default()
+        })
+    }
+}
+impl SaveComponent for AtlasSprite {
+    const NAME: &'static str = "atlas-sprite";
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_u8(self.atlas_pos.x)?;
+        w.write_u8(self.atlas_pos.y)
+    }
+    fn read(r: &mut dyn Read, _ctx: &LoadContext) -> io::Result<Self> {
+        Ok(Self {
+            atlas_pos: (r.read_u8()?, r.read_u8()?).into(),
+        })
+    }
+}
+impl SaveComponent for AtlasSpriteAnimation {
+    const NAME: &'static str = "atlas-sprite-animation";
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        // the animations come from the tileset so edits in aseprite reach saved entities
+        w.write_str(&self.current)?;
+        w.write_f32(self.time)?;
+        w.write_bool(self.flip_h)?;
+        w.write_bool(self.flip_v)
+    }
+    fn read(r: &mut dyn Read, ctx: &LoadContext) -> io::Result<Self> {
+        // version 3 and older saved the whole animation table
+        if ctx.version <= 3 {
+            for _ in 0..r.read_u16()? {
+                r.read_str()?;
+                r.read_f32()?;
+                for _ in 0..r.read_u16()? {
+                    r.read_u8()?;
+                    r.read_u8()?;
+                }
+            }
+        }
+        let current = r.read_str()?;
+        let animations = ctx.prefabs.animations.clone();
+        if !animations.contains_key(&current) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown current animation {current:?}"),
+            ));
+        }
+        Ok(Self {
+            animations,
+            current,
+            time: r.read_f32()?,
+            flip_h: r.read_bool()?,
+            flip_v: r.read_bool()?,
+        })
+    }
+}
+
+/// prefab parameters of a `Body`, the position is given on spawn
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BodyParams {
+    pub size: (f32, f32, f32),
+}
+impl Default for BodyParams {
+    fn default() -> Self {
+        Self {
+            size: (1.0, 1.0, 1.0),
+        }
+    }
+}
+impl PrefabComponent for Body {
+    type Params = BodyParams;
+    fn from_prefab(params: BodyParams, _ctx: &PrefabContext) -> io::Result<Self> {
+        let (x, y, z) = params.size;
+        Ok(Self {
+            pos: Vector3::zero(),
+            size: Vector3::new(x, y, z),
+        })
+    }
+}
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChunkLoaderParams {
+    pub radius: i32,
+}
+impl Default for ChunkLoaderParams {
+    fn default() -> Self {
+        Self {
+            radius: KEEP_RADIUS,
+        }
+    }
+}
+impl PrefabComponent for ChunkLoader {
+    type Params = ChunkLoaderParams;
+    fn from_prefab(params: ChunkLoaderParams, _ctx: &PrefabContext) -> io::Result<Self> {
+        Ok(Self {
+            radius: params.radius.max(0),
+        })
+    }
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhysicsParams {
+    pub vel: (f32, f32, f32),
+}
+impl PrefabComponent for Physics {
+    type Params = PhysicsParams;
+    fn from_prefab(params: PhysicsParams, _ctx: &PrefabContext) -> io::Result<Self> {
+        let (x, y, z) = params.vel;
+        Ok(Self {
+            vel: Vector3::new(x, y, z),
+        })
+    }
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControllerParams {
+    pub speed: f32,
+}
+impl PrefabComponent for Controller {
+    type Params = ControllerParams;
+    fn from_prefab(params: ControllerParams, _ctx: &PrefabContext) -> io::Result<Self> {
+        Ok(Self {
+            speed: params.speed,
+            ..Default::default()
+        })
+    }
+}
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AtlasSpriteParams {
+    pub atlas: (u8, u8),
+}
+impl PrefabComponent for AtlasSprite {
+    type Params = AtlasSpriteParams;
+    fn from_prefab(params: AtlasSpriteParams, _ctx: &PrefabContext) -> io::Result<Self> {
+        Ok(Self {
+            atlas_pos: params.atlas.into(),
+        })
+    }
+}
+/// prefab parameters of an `AtlasSpriteAnimation`, the animations come from the tileset
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AtlasSpriteAnimationParams {
+    pub current: String,
+    #[serde(default)]
+    pub flip_h: bool,
+    #[serde(default)]
+    pub flip_v: bool,
+}
+impl PrefabComponent for AtlasSpriteAnimation {
+    type Params = AtlasSpriteAnimationParams;
+    fn from_prefab(params: AtlasSpriteAnimationParams, ctx: &PrefabContext) -> io::Result<Self> {
+        if !ctx.animations.contains_key(&params.current) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown animation {:?}", params.current),
+            ));
+        }
+        Ok(Self {
+            animations: ctx.animations.clone(),
+            current: params.current,
+            flip_h: params.flip_h,
+            flip_v: params.flip_v,
+            ..Default::default()
+        })
+    }
+}
+
 #[inline(always)]
 pub fn update_all(rl: &mut RaylibHandle, world: &mut World, data: &mut GameData, dt: f32) {
     Controller::update(rl, world, dt);
     Body::update_overlap(world, data);
+    Dimension::update_portals(world);
+    ChunkLoader::update_loaders(world);
     Physics::update(world, dt);
     AtlasSpriteAnimation::update_animation(world, dt);
     player::Player::update(world, data);
@@ -1,5 +1,4 @@
 use hecs::World;
-use raylib::math::Vector3;
 
 use crate::{SCREEN_HEIGHT, SCREEN_WIDTH, components::*};
 
@@ -18,7 +17,13 @@ pub enum PlayerState {
 
 impl Player {
     pub fn update_camera(world: &mut World, data: &mut GameData) {
-        for body in world.query::<&Body>().with::<&Player>().iter() {
+        for (body, dimension) in world
+            .query::<(&Body, Option<&Dimension>)>()
+            .with::<&Player>()
+            .iter()
+        {
+            // the map of the player is the one that is drawn
+            data.active_map = Dimension::name(dimension).to_string();
             data.camera.target = Vector2 {
                 x: body.pos.x * TILE_SIZE as f32 - (SCREEN_WIDTH as f32 / data.camera.zoom) / 2.0
                     + TILE_SIZE as f32 / 2.0,
@@ -78,65 +83,30 @@ impl Player {
         Self::update_camera(world, data);
     }
 }
-#[inline(always)]
-pub fn spawn_player(world: &mut World) {
-    let mut map: AnimationsType = HashMap::default();
-    const WALK_SPEED: f32 = 10.0;
-    map.insert("idle-side", (&[AtlasPos { x: 9, y: 0 }], 1.0));
-    map.insert(
-        "walk-side",
-        (
-            &[
-                AtlasPos { x: 9, y: 1 },
-                AtlasPos { x: 9, y: 0 },
-                AtlasPos { x: 9, y: 2 },
-                AtlasPos { x: 9, y: 0 },
-            ],
-            WALK_SPEED,
-        ),
-    );
-    map.insert("idle-up", (&[AtlasPos { x: 9, y: 3 }], 1.0));
-    map.insert(
-        "walk-up",
-        (
-            &[
-                AtlasPos { x: 9, y: 4 },
-                AtlasPos { x: 9, y: 3 },
-                AtlasPos { x: 9, y: 5 },
-                AtlasPos { x: 9, y: 3 },
-            ],
-            WALK_SPEED,
-        ),
-    );
-    map.insert("idle-down", (&[AtlasPos { x: 9, y: 6 }], 1.0));
-    map.insert(
-        "walk-down",
-        (
-            &[
-                AtlasPos { x: 9, y: 7 },
-                AtlasPos { x: 9, y: 6 },
-                AtlasPos { x: 9, y: 8 },
-                AtlasPos { x: 9, y: 6 },
-            ],
-            WALK_SPEED,
-        ),
-    );
-    world.spawn((
-        Body {
-            pos: Vector3::zero(),
-            size: Vector3::one(),
-        },
-        Physics::default(),
-        AtlasSpriteAnimation {
-            animations: Arc::new(map),
-            current: "idle-side",
-            flip_h: true,
-            ..Default::default()
-        },
-        Controller {
-            speed: 5.0,
-            ..Default::default()
-        },
-        Player::default(),
-    ));
+impl SaveComponent for Player {
+    const NAME: &'static str = "player";
+    fn write(&self, w: &mut dyn Write) -> io::Result<()> {
+        w.write_u8(self.state as u8)?;
+        w.write_vector2(self.dir)
+    }
+    fn read(r: &mut dyn Read, _ctx: &LoadContext) -> io::Result<Self> {
+        let state = match r.read_u8()? {
+            1 => PlayerState::Walk,
+            2 => PlayerState::Jump,
+            _ => PlayerState::Idle,
+        };
+        Ok(Self {
+            state,
+            dir: r.read_vector2()?,
+        })
+    }
+}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayerParams {}
+impl PrefabComponent for Player {
+    type Params = PlayerParams;
+    fn from_prefab(_params: PlayerParams, _ctx: &PrefabContext) -> io::Result<Self> {
+        Ok(Self::default())
+    }
 }
@@ -0,0 +1,307 @@
+#![allow(dead_code)]
+extern crate base64;
+extern crate crossbeam_channel;
+extern crate flate2;
+extern crate hecs;
+extern crate noise;
+extern crate rand;
+extern crate raylib;
+extern crate rayon;
+extern crate ron;
+extern crate roxmltree;
+extern crate rustc_hash;
+extern crate serde;
+extern crate serde_json;
+
+pub mod aseprite;
+pub mod components;
+pub mod prefab;
+pub mod save;
+pub mod serial;
+pub mod world;
+
+use std::{collections::VecDeque, io, path::Path, sync::Arc};
+
+use crate::{
+    aseprite::Aseprite,
+    components::{AnimationsType, player::Player},
+    prefab::{DEFAULT_PREFABS, PrefabContext, Prefabs},
+    save::SavedData,
+    world::{
+        generator::{CaveGenerator, OverWorldGenerator},
+        map::{self, BlockMapDrawBuffer, OVERWORLD, SPAWN_SEARCH_RADIUS, UNDERGROUND},
+        noise_graph::NoiseGraph,
+        pipeline::ChunkError,
+        storage::RegionStore,
+        structure::StructureSet,
+        tiled::{TileMapping, TiledMap},
+        units::WorldBlockPos,
+    },
+};
+use hecs::{Entity, World};
+use raylib::prelude::*;
+use world::map::{BlockMap, BlockSet};
+
+pub const SCREEN_WIDTH: i32 = (1920.0 / 1.5) as i32;
+pub const SCREEN_HEIGHT: i32 = (1080.0 / 1.5) as i32;
+/// artists edit the tileset and its animation tags in aseprite
+pub const TILESET_FILE: &str = "assets/tileset.ase";
+/// designers add blocks here without recompiling
+pub const BLOCKS_FILE: &str = "assets/blocks.ron";
+/// structure templates placed by the world generator
+pub const STRUCTURES_FILE: &str = "assets/structures.ron";
+/// noise graph of the overworld terrain height
+pub const TERRAIN_FILE: &str = "assets/terrain.ron";
+/// entity prefabs, new mobs and items are added here
+pub const PREFABS_FILE: &str = "assets/prefabs.ron";
+pub const WORLD_DIR: &str = "saves/world";
+pub const SAVE_FILE: &str = "saves/world/game.pcs";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameEvent {
+    BodyEvent(components::BodyEvent),
+    /// a generator thread panicked while generating a chunk of `map`
+    ChunkError {
+        map: String,
+        error: ChunkError,
+    },
+}
+pub struct GameData {
+    events: VecDeque<GameEvent>,
+    camera: Camera2D,
+    atlas: Texture2D,
+    animations: Arc<AnimationsType>,
+    blockset: Arc<BlockSet>,
+    prefabs: Arc<Prefabs>,
+    selected: u8,
+    /// name of the `BlockMap` that is drawn and updated every frame
+    active_map: String,
+    /// where players spawn on the `OVERWORLD`, searched for once and kept in the save
+    spawn: Option<Vector3>,
+}
+pub struct Game {
+    rl: RaylibHandle,
+    thread: RaylibThread,
+    world: World,
+    data: GameData,
+}
+impl Default for Game {
+    fn default() -> Self {
+        let (mut rl, thread) = raylib::init()
+            .size(SCREEN_WIDTH, SCREEN_HEIGHT)
+            .title("PicoCraft")
+            .build();
+        let tileset = Aseprite::load(TILESET_FILE).unwrap();
+        let tileset_atlas = tileset.atlas();
+        let atlas = rl
+            .load_texture_from_image(&thread, &tileset_atlas.image())
+            .unwrap();
+        let animations = Arc::new(tileset.animations(&tileset_atlas).unwrap());
+        let blockset = Arc::new(BlockSet::load(BLOCKS_FILE).unwrap_or_else(|err| {
+            eprintln!("failed to load blocks, using the default blocks: {err}");
+            BlockSet::normal()
+        }));
+        let generator = OverWorldGenerator {
+            structures: StructureSet::load(STRUCTURES_FILE, &blockset).unwrap_or_else(|err| {
+                eprintln!("failed to load structures, using the default structures: {err}");
+                StructureSet::normal(&blockset)
+            }),
+            terrain: Some(NoiseGraph::load(TERRAIN_FILE).unwrap_or_else(|err| {
+                eprintln!("failed to load terrain, using the default terrain: {err}");
+                NoiseGraph::normal()
+            })),
+            ..Default::default()
+        };
+        let mut world = World::new();
+        world.spawn((BlockMap::new((*blockset).clone(), generator, 42)
+            .with_portal(UNDERGROUND)
+            .with_levels(-1..=1)
+            .with_storage(RegionStore::open(WORLD_DIR, blockset.palette()).unwrap()),));
+        world.spawn((
+            BlockMap::new((*blockset).clone(), CaveGenerator::default(), 43)
+                .with_name(UNDERGROUND)
+                .with_portal(OVERWORLD)
+                .with_storage(
+                    RegionStore::open(Path::new(WORLD_DIR).join(UNDERGROUND), blockset.palette())
+                        .unwrap(),
+                ),
+        ));
+        let mut prefabs = Prefabs::normal(PrefabContext {
+            animations: animations.clone(),
+        });
+        if let Err(err) = prefabs.load(PREFABS_FILE) {
+            eprintln!("failed to load prefabs, using the default prefabs: {err}");
+            prefabs
+                .load_ron(DEFAULT_PREFABS)
+                .expect("default prefabs are invalid");
+        }
+        let mut game = Self {
+            rl,
+            thread,
+            world,
+            data: GameData {
+                events: VecDeque::default(),
+                camera: Camera2D {
+                    zoom: 2.0,
+                    ..Default::default()
+                },
+                atlas,
+                animations,
+                blockset,
+                prefabs: Arc::new(prefabs),
+                selected: 1,
+                active_map: OVERWORLD.to_string(),
+                spawn: None,
+            },
+        };
+        if Path::new(SAVE_FILE).exists()
+            && let Err(err) = game.load(SAVE_FILE)
+        {
+            eprintln!("failed to load {SAVE_FILE}: {err}");
+        }
+        // new worlds and broken saves have no player yet
+        let has_player = game
+            .world
+            .query_mut::<&Player>()
+            .into_iter()
+            .next()
+            .is_some();
+        if !has_player {
+            game.respawn_player();
+        }
+        game
+    }
+}
+impl Game {
+    /// returns the world spawn, searching the `OVERWORLD` for it the first time
+    pub fn spawn_point(&mut self) -> Vector3 {
+        if let Some(spawn) = self.data.spawn {
+            return spawn;
+        }
+        let found = self
+            .world
+            .query_mut::<&mut BlockMap>()
+            .into_iter()
+            .find(|block_map| block_map.name == OVERWORLD)
+            .and_then(|block_map| block_map.find_spawn(SPAWN_SEARCH_RADIUS));
+        let spawn = match found {
+            Some(pos) => Vector3::new(pos.x as f32, pos.y as f32, pos.z as f32),
+            None => {
+                eprintln!("found no place to stand near the origin, spawning there anyway");
+                Vector3::zero()
+            }
+        };
+        self.data.spawn = Some(spawn);
+        spawn
+    }
+    /// spawns a new player at the world spawn
+    pub fn respawn_player(&mut self) {
+        let spawn = self.spawn_point();
+        let prefabs = self.data.prefabs.clone();
+        if let Err(err) = prefabs.spawn_prefab(&mut self.world, "player", spawn) {
+            eprintln!("failed to spawn the player: {err}");
+        }
+    }
+    pub fn edit(&mut self, dt: f32) {
+        self.edit_move(dt);
+    }
+    pub fn edit_move(&mut self, dt: f32) {
+        const SPEED: f32 = 300.0;
+        let mut acc = Vector2::zero();
+        if self.rl.is_key_down(KeyboardKey::KEY_A) {
+            acc.x -= 1.0;
+        }
+        if self.rl.is_key_down(KeyboardKey::KEY_D) {
+            acc.x += 1.0;
+        }
+        if self.rl.is_key_down(KeyboardKey::KEY_W) {
+            acc.y -= 1.0;
+        }
+        if self.rl.is_key_down(KeyboardKey::KEY_S) {
+            acc.y += 1.0;
+        }
+        self.data.camera.target += acc.normalized() * SPEED / self.data.camera.zoom * dt;
+    }
+    #[inline(always)]
+    pub fn update(&mut self, dt: f32) {
+        self.edit(dt);
+        map::update_map(&mut self.world, &mut self.data, dt);
+        components::update_all(&mut self.rl, &mut self.world, &mut self.data, dt);
+        self.event(dt);
+    }
+    #[inline(always)]
+    pub fn event(&mut self, dt: f32) {
+        while let Some(event) = self.data.events.pop_front() {
+            match event {
+                GameEvent::BodyEvent(body_event) => body_event.update(&mut self.world, dt),
+                GameEvent::ChunkError { map, error } => eprintln!(
+                    "failed to generate chunk {:?} of {map} at {:?}: {}",
+                    error.cpos, error.stage, error.message
+                ),
+            }
+        }
+    }
+    #[inline(always)]
+    pub fn draw(&mut self) {
+        let fps = self.rl.get_fps();
+        let mut d = self.rl.begin_drawing(&self.thread);
+        d.clear_background(Color::SKYBLUE);
+        {
+            let mut draw: RaylibMode2D<'_, RaylibDrawHandle<'_>> = d.begin_mode2D(self.data.camera);
+            let mut buffer = BlockMapDrawBuffer::default();
+            components::draw_all(&mut self.world, &mut buffer, &self.data);
+            map::draw_map(&mut self.world, &mut draw, &mut buffer, &self.data);
+        }
+        d.draw_text(&fps.to_string(), 5, 5, 32, Color::RED);
+    }
+    pub fn run(&mut self) {
+        while !self.rl.window_should_close() {
+            self.update(self.rl.get_frame_time());
+            self.draw();
+        }
+        if let Err(err) = self.save(SAVE_FILE) {
+            eprintln!("failed to save {SAVE_FILE}: {err}");
+        }
+    }
+    /// imports a Tiled map into the active map with its upper left corner at `origin`,
+    /// tiles are mapped with the `block` properties of its tilesets and then `mapping`
+    pub fn import_tiled(
+        &mut self,
+        path: impl AsRef<Path>,
+        mapping: &TileMapping,
+        origin: WorldBlockPos,
+    ) -> io::Result<Vec<Entity>> {
+        let map = TiledMap::load(path)?;
+        let mut full = TileMapping::from_tiled(&map, &self.data.blockset);
+        full.extend(mapping);
+        Ok(map
+            .import(&full)
+            .paste(&mut self.world, &self.data.active_map, origin))
+    }
+    /// writes the modified chunks of every `BlockMap` to disk
+    pub fn save_chunks(&mut self) {
+        for block_map in self.world.query_mut::<&mut BlockMap>() {
+            if let Err(err) = block_map.save() {
+                eprintln!("failed to save chunks: {err}");
+            }
+        }
+    }
+}
+impl GameData {
+    /// the part of the data that is kept in a save
+    pub fn saved(&self) -> SavedData {
+        SavedData {
+            camera: self.camera,
+            selected: self.selected,
+            spawn: self.spawn,
+        }
+    }
+    pub fn set_saved(&mut self, saved: SavedData) {
+        self.camera = saved.camera;
+        self.selected = saved.selected;
+        self.spawn = saved.spawn;
+    }
+    pub fn push_event(&mut self, event: impl Into<GameEvent>) {
+        self.events.push_back(event.into());
+    }
+}
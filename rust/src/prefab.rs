@@ -0,0 +1,288 @@
+use crate::{
+    components::{
+        AnimationsType, AtlasSprite, AtlasSpriteAnimation, Body, ChunkLoader, Controller, Physics,
+        player::Player,
+    },
+    save::SaveComponent,
+};
+use hecs::{Entity, EntityBuilder, World};
+use raylib::prelude::*;
+use ron::{Value, extensions::Extensions};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, de::DeserializeOwned};
+use std::{fs, io, path::Path, sync::Arc};
+
+/// prefabs shipped with the game
+pub const DEFAULT_PREFABS: &str = include_str!("../assets/prefabs.ron");
+
+/// game data components need when they are built from a prefab
+#[derive(Debug, Clone, Default)]
+pub struct PrefabContext {
+    pub animations: Arc<AnimationsType>,
+}
+
+/// a component that can be built from the parameters in a prefab file,
+/// it uses the `SaveComponent::NAME` of the component
+pub trait PrefabComponent: SaveComponent {
+    /// parameters of the component in the prefab file
+    type Params: DeserializeOwned;
+    fn from_prefab(params: Self::Params, ctx: &PrefabContext) -> io::Result<Self>;
+}
+
+/// builds the component from its parameters into an entity builder
+type BuildComponentFn = fn(&mut EntityBuilder, Value, &PrefabContext) -> io::Result<()>;
+
+/// one prefab of a prefab file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrefabDef {
+    /// prefab whose components are inherited and overridden
+    #[serde(default)]
+    parent: Option<String>,
+    /// component names and their parameters
+    #[serde(default)]
+    components: FxHashMap<String, Value>,
+}
+
+/// an entity described by its components with inheritance already resolved
+#[derive(Debug, Clone)]
+pub struct Prefab {
+    pub components: Vec<(String, Value)>,
+}
+
+/// holds every loaded prefab and the components they can use
+pub struct Prefabs {
+    pub ctx: PrefabContext,
+    pub prefabs: FxHashMap<String, Prefab>,
+    builders: FxHashMap<&'static str, BuildComponentFn>,
+}
+impl Prefabs {
+    /// empty prefabs with all components of the game
+    pub fn normal(ctx: PrefabContext) -> Self {
+        let mut prefabs = Self {
+            ctx,
+            prefabs: FxHashMap::default(),
+            builders: FxHashMap::default(),
+        };
+        prefabs.register::<Body>();
+        prefabs.register::<ChunkLoader>();
+        prefabs.register::<Physics>();
+        prefabs.register::<Controller>();
+        prefabs.register::<Player>();
+        prefabs.register::<AtlasSprite>();
+        prefabs.register::<AtlasSpriteAnimation>();
+        prefabs
+    }
+    pub fn register<T: PrefabComponent>(&mut self) {
+        self.builders.insert(T::NAME, |builder, params, ctx| {
+            // `()` is written for components without parameters
+            let params = match params {
+                Value::Unit => Value::Map(ron::Map::new()),
+                params => params,
+            };
+            let params = params
+                .into_rust::<T::Params>()
+                .map_err(|err| invalid(err.to_string()))?;
+            builder.add(T::from_prefab(params, ctx)?);
+            Ok(())
+        });
+    }
+
+    /// loads the prefabs of a RON file on top of the already loaded ones
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        self.load_ron(&text)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+    }
+    /// parses prefabs, a child prefab starts with the components of its parent and
+    /// overrides single parameters or whole components
+    pub fn load_ron(&mut self, text: &str) -> io::Result<()> {
+        let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+        let defs: FxHashMap<String, PrefabDef> = options
+            .from_str(text)
+            .map_err(|err| invalid(err.to_string()))?;
+        let mut resolved = FxHashMap::default();
+        for name in defs.keys() {
+            self.resolve(name, &defs, &mut resolved, &mut FxHashSet::default())?;
+        }
+        let mut prefabs = Vec::with_capacity(resolved.len());
+        for (name, components) in resolved {
+            let prefab = Prefab {
+                components: components.into_iter().collect(),
+            };
+            // catch bad parameters now instead of on the first spawn
+            self.build(&prefab)
+                .map_err(|err| invalid(format!("prefab {name:?}: {err}")))?;
+            prefabs.push((name, prefab));
+        }
+        // a broken file adds none of its prefabs
+        self.prefabs.extend(prefabs);
+        Ok(())
+    }
+    /// merges the components of `name` with the ones of its parents
+    fn resolve(
+        &self,
+        name: &str,
+        defs: &FxHashMap<String, PrefabDef>,
+        resolved: &mut FxHashMap<String, FxHashMap<String, Value>>,
+        visiting: &mut FxHashSet<String>,
+    ) -> io::Result<FxHashMap<String, Value>> {
+        if let Some(components) = resolved.get(name) {
+            return Ok(components.clone());
+        }
+        let Some(def) = defs.get(name) else {
+            // parents can also come from an earlier file
+            return match self.prefabs.get(name) {
+                Some(prefab) => Ok(prefab.components.iter().cloned().collect()),
+                None => Err(invalid(format!("unknown prefab {name:?}"))),
+            };
+        };
+        if !visiting.insert(name.to_string()) {
+            return Err(invalid(format!("prefab {name:?} inherits from itself")));
+        }
+        let mut components = match &def.parent {
+            Some(parent) => self
+                .resolve(parent, defs, resolved, visiting)
+                .map_err(|err| invalid(format!("parent of prefab {name:?}: {err}")))?,
+            None => FxHashMap::default(),
+        };
+        for (component, params) in def.components.iter() {
+            if !self.builders.contains_key(component.as_str()) {
+                return Err(invalid(format!(
+                    "prefab {name:?} uses unknown component {component:?}"
+                )));
+            }
+            match components.get_mut(component) {
+                Some(inherited) => merge(inherited, params.clone()),
+                None => {
+                    components.insert(component.clone(), params.clone());
+                }
+            }
+        }
+        visiting.remove(name);
+        resolved.insert(name.to_string(), components.clone());
+        Ok(components)
+    }
+
+    /// builds the components of a prefab
+    pub fn build(&self, prefab: &Prefab) -> io::Result<EntityBuilder> {
+        let mut builder = EntityBuilder::new();
+        for (component, params) in prefab.components.iter() {
+            let build = self
+                .builders
+                .get(component.as_str())
+                .ok_or_else(|| invalid(format!("unknown component {component:?}")))?;
+            build(&mut builder, params.clone(), &self.ctx)
+                .map_err(|err| invalid(format!("component {component:?}: {err}")))?;
+        }
+        Ok(builder)
+    }
+    /// spawns the prefab `name` with its `Body` at `pos`
+    pub fn spawn_prefab(&self, world: &mut World, name: &str, pos: Vector3) -> io::Result<Entity> {
+        let prefab = self
+            .prefabs
+            .get(name)
+            .ok_or_else(|| invalid(format!("unknown prefab {name:?}")))?;
+        let mut builder = self.build(prefab)?;
+        if let Some(body) = builder.get_mut::<&mut Body>() {
+            body.pos = pos;
+        }
+        Ok(world.spawn(builder.build()))
+    }
+}
+
+/// overrides the fields of `base` with the ones of `value`, other values are replaced
+fn merge(base: &mut Value, value: Value) {
+    match (base, value) {
+        (Value::Map(base), Value::Map(value)) => {
+            for (key, value) in value.iter() {
+                match base.get_mut(key) {
+                    Some(field) => merge(field, value.clone()),
+                    None => {
+                        base.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        // `()` keeps the inherited parameters
+        (_, Value::Unit) => {}
+        (base, value) => *base = value,
+    }
+}
+
+#[inline(always)]
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefabs(text: &str) -> io::Result<Prefabs> {
+        let mut prefabs = Prefabs::normal(PrefabContext::default());
+        prefabs.load_ron(text)?;
+        Ok(prefabs)
+    }
+
+    #[test]
+    fn children_override_single_parameters() {
+        let prefabs = prefabs(
+            r#"{
+                "walker": (
+                    components: {
+                        "body": (size: (2.0, 3.0, 4.0)),
+                        "physics": (vel: (1.0, 0.0, 0.0)),
+                        "controller": (speed: 2.0),
+                    },
+                ),
+                "runner": (
+                    parent: "walker",
+                    components: {
+                        "physics": (),
+                        "controller": (speed: 5.0),
+                    },
+                ),
+            }"#,
+        )
+        .unwrap();
+        let mut world = World::new();
+        let pos = Vector3::new(1.0, 2.0, 3.0);
+        let runner = prefabs.spawn_prefab(&mut world, "runner", pos).unwrap();
+        let walker = prefabs.spawn_prefab(&mut world, "walker", pos).unwrap();
+
+        let body = world.get::<&Body>(runner).unwrap();
+        assert_eq!((body.pos, body.size), (pos, Vector3::new(2.0, 3.0, 4.0)));
+        assert_eq!(world.get::<&Physics>(runner).unwrap().vel.x, 1.0);
+        assert_eq!(world.get::<&Controller>(runner).unwrap().speed, 5.0);
+        // the parent keeps its own parameters
+        assert_eq!(world.get::<&Controller>(walker).unwrap().speed, 2.0);
+    }
+
+    #[test]
+    fn missing_parents_fail() {
+        let err = prefabs(r#"{ "orphan": (parent: "nobody") }"#)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("nobody"), "{err}");
+    }
+
+    #[test]
+    fn inheritance_cycles_fail() {
+        let text = r#"{
+            "a": (parent: "b", components: { "physics": () }),
+            "b": (parent: "a"),
+            "c": (),
+        }"#;
+        let err = prefabs(text).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("inherits from itself"), "{err}");
+
+        // none of the prefabs of the file are added
+        let mut loaded = prefabs(r#"{ "item": (components: { "body": () }) }"#).unwrap();
+        assert!(loaded.load_ron(text).is_err());
+        assert_eq!(loaded.prefabs.keys().collect::<Vec<_>>(), ["item"]);
+    }
+}
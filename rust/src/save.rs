@@ -0,0 +1,544 @@
+use crate::{
+    Game,
+    components::{
+        AtlasSprite, AtlasSpriteAnimation, Body, ChunkLoader, Controller, Dimension, Physics,
+        player::Player,
+    },
+    prefab::PrefabContext,
+    serial::{ReadExt, WriteExt, expect_magic},
+    world::map::{Block, BlockSet},
+};
+use hecs::{Component, Entity, EntityBuilder, World};
+use raylib::prelude::{Camera2D, Vector3};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// magic bytes of a game save file
+pub const SAVE_MAGIC: &[u8; 4] = b"PCSV";
+/// current game save version
+pub const SAVE_VERSION: u8 = 4;
+
+/// a component that can be written to and read from a game save
+pub trait SaveComponent: Component + Sized {
+    /// unique name of the component in the save file
+    const NAME: &'static str;
+    fn write(&self, w: &mut dyn Write) -> io::Result<()>;
+    fn read(r: &mut dyn Read, ctx: &LoadContext) -> io::Result<Self>;
+}
+
+/// what components need to be read besides their bytes
+#[derive(Debug, Clone, Copy)]
+pub struct LoadContext<'a> {
+    /// version of the save that is read
+    pub version: u8,
+    /// game data shared with prefabs, like the animations of the tileset
+    pub prefabs: &'a PrefabContext,
+}
+
+/// checks if an entity has the component
+type HasComponentFn = fn(&World, Entity) -> bool;
+/// writes the component of an entity if it has one
+type WriteComponentFn = fn(&World, Entity, &mut Vec<u8>) -> io::Result<bool>;
+/// reads the component into an entity builder
+type ReadComponentFn = fn(&mut EntityBuilder, &mut dyn Read, &LoadContext) -> io::Result<()>;
+
+/// holds the read and write functions of a registered `SaveComponent`
+pub struct ComponentEntry {
+    pub name: &'static str,
+    pub has: HasComponentFn,
+    pub write: WriteComponentFn,
+    pub read: ReadComponentFn,
+}
+/// holds every component that is part of a game save
+#[derive(Default)]
+pub struct ComponentRegistry {
+    pub entries: Vec<ComponentEntry>,
+}
+impl ComponentRegistry {
+    /// registry with all components of the game
+    pub fn normal() -> Self {
+        let mut registry = Self::default();
+        registry.register::<Body>();
+        registry.register::<Dimension>();
+        registry.register::<ChunkLoader>();
+        registry.register::<Physics>();
+        registry.register::<Controller>();
+        registry.register::<Player>();
+        registry.register::<AtlasSprite>();
+        registry.register::<AtlasSpriteAnimation>();
+        registry
+    }
+    pub fn register<T: SaveComponent>(&mut self) {
+        self.entries.push(ComponentEntry {
+            name: T::NAME,
+            has: |world, ent| world.satisfies::<&T>(ent),
+            write: |world, ent, buf| {
+                let Ok(component) = world.get::<&T>(ent) else {
+                    return Ok(false);
+                };
+                component.write(buf)?;
+                Ok(true)
+            },
+            read: |builder, r, ctx| {
+                builder.add(T::read(r, ctx)?);
+                Ok(())
+            },
+        });
+    }
+    pub fn get(&self, name: &str) -> Option<&ComponentEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// writes every entity that has at least one registered component
+    pub fn write_world(&self, world: &World, w: &mut impl Write) -> io::Result<()> {
+        let mut entities: Vec<Vec<(&'static str, Vec<u8>)>> = Vec::new();
+        for ent in world.iter().map(|ent_ref| ent_ref.entity()) {
+            let mut components = Vec::new();
+            for entry in self.entries.iter() {
+                let mut buf = Vec::new();
+                if (entry.write)(world, ent, &mut buf)? {
+                    components.push((entry.name, buf));
+                }
+            }
+            if !components.is_empty() {
+                entities.push(components);
+            }
+        }
+        w.write_u32(entities.len() as u32)?;
+        for components in entities {
+            w.write_u16(components.len() as u16)?;
+            for (name, buf) in components {
+                w.write_str(name)?;
+                w.write_bytes(&buf)?;
+            }
+        }
+        Ok(())
+    }
+    /// spawns the saved entities into `world`, skipping unknown components
+    pub fn read_world(
+        &self,
+        world: &mut World,
+        r: &mut impl Read,
+        ctx: &LoadContext,
+    ) -> io::Result<()> {
+        let count = r.read_u32()?;
+        for _ in 0..count {
+            let mut builder = EntityBuilder::new();
+            let components = r.read_u16()?;
+            for _ in 0..components {
+                let name = r.read_str()?;
+                let buf = r.read_bytes()?;
+                let Some(entry) = self.get(&name) else {
+                    eprintln!("warning: skipping unknown component {name:?} in save");
+                    continue;
+                };
+                (entry.read)(&mut builder, &mut buf.as_slice(), ctx)?;
+            }
+            world.spawn(builder.build());
+        }
+        Ok(())
+    }
+    /// despawns every entity that would be part of a save
+    pub fn clear_world(&self, world: &mut World) {
+        let saved: Vec<Entity> = world
+            .iter()
+            .map(|ent_ref| ent_ref.entity())
+            .filter(|ent| self.entries.iter().any(|entry| (entry.has)(world, *ent)))
+            .collect();
+        for ent in saved {
+            let _ = world.despawn(ent);
+        }
+    }
+}
+
+/// the part of `GameData` that is kept in a save
+#[derive(Debug, Clone, Copy)]
+pub struct SavedData {
+    pub camera: Camera2D,
+    pub selected: u8,
+    /// where players spawn on the `OVERWORLD`
+    pub spawn: Option<Vector3>,
+}
+impl SavedData {
+    /// writes the data and the entities of `world` as a save
+    pub fn write(&self, world: &World, blockset: &BlockSet, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(SAVE_MAGIC)?;
+        w.write_u8(SAVE_VERSION)?;
+        // game data
+        w.write_vector2(self.camera.target)?;
+        w.write_vector2(self.camera.offset)?;
+        w.write_f32(self.camera.rotation)?;
+        w.write_f32(self.camera.zoom)?;
+        // blocks are saved by name so reordering `Block` keeps the selection
+        w.write_str(blockset.name(Block::from(self.selected)))?;
+        w.write_bool(self.spawn.is_some())?;
+        w.write_vector3(self.spawn.unwrap_or_default())?;
+        // entities
+        ComponentRegistry::normal().write_world(world, w)
+    }
+    /// reads a save into new data and a new world with the saved entities
+    pub fn read(
+        r: &mut impl Read,
+        blockset: &BlockSet,
+        prefabs: &PrefabContext,
+    ) -> io::Result<(Self, World)> {
+        expect_magic(r, SAVE_MAGIC)?;
+        let version = r.read_u8()?;
+        if version == 0 || version > SAVE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported save version {version}"),
+            ));
+        }
+        // game data
+        let camera = Camera2D {
+            target: r.read_vector2()?,
+            offset: r.read_vector2()?,
+            rotation: r.read_f32()?,
+            zoom: r.read_f32()?,
+        };
+        let selected = match version {
+            // version 1 saved the raw block id
+            1 => r.read_u8()?,
+            _ => {
+                let name = r.read_str()?;
+                blockset.block(&name).unwrap_or(Block::UNKNOWN).into()
+            }
+        };
+        // version 2 and older search the spawn again
+        let spawn = match version {
+            1 | 2 => None,
+            _ => {
+                let has_spawn = r.read_bool()?;
+                let spawn = r.read_vector3()?;
+                has_spawn.then_some(spawn)
+            }
+        };
+        // entities
+        let mut world = World::new();
+        let ctx = LoadContext { version, prefabs };
+        ComponentRegistry::normal().read_world(&mut world, r, &ctx)?;
+        Ok((
+            Self {
+                camera,
+                selected,
+                spawn,
+            },
+            world,
+        ))
+    }
+    /// replaces the data and the saved entities of `world` with the save, nothing
+    /// changes if the save can not be read
+    pub fn load(
+        &mut self,
+        world: &mut World,
+        blockset: &BlockSet,
+        prefabs: &PrefabContext,
+        r: &mut impl Read,
+    ) -> io::Result<()> {
+        let (data, mut loaded) = Self::read(r, blockset, prefabs)?;
+        *self = data;
+        let registry = ComponentRegistry::normal();
+        registry.clear_world(world);
+        let entities: Vec<Entity> = loaded.iter().map(|ent_ref| ent_ref.entity()).collect();
+        for ent in entities {
+            if let Ok(taken) = loaded.take(ent) {
+                world.spawn(taken);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Game {
+    /// saves all registered components, the game state and the modified chunks to `path`
+    pub fn save(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.save_chunks();
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut w = BufWriter::new(File::create(path)?);
+        self.data
+            .saved()
+            .write(&self.world, &self.data.blockset, &mut w)?;
+        w.flush()
+    }
+    /// replaces the saved entities and the game state with the save at `path`,
+    /// a save that can not be read changes nothing
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut saved = self.data.saved();
+        saved.load(
+            &mut self.world,
+            &self.data.blockset,
+            &self.data.prefabs.ctx,
+            &mut r,
+        )?;
+        self.data.set_saved(saved);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::AnimationsType;
+    use raylib::math::Vector2;
+    use std::sync::Arc;
+
+    fn test_prefabs() -> PrefabContext {
+        let mut animations = AnimationsType::default();
+        animations.insert("idle".to_string(), (vec![(1, 2).into()], 10.0));
+        animations.insert(
+            "walk".to_string(),
+            (vec![(3, 2).into(), (4, 2).into()], 5.0),
+        );
+        PrefabContext {
+            animations: Arc::new(animations),
+        }
+    }
+
+    fn load_ctx(prefabs: &PrefabContext) -> LoadContext<'_> {
+        LoadContext {
+            version: SAVE_VERSION,
+            prefabs,
+        }
+    }
+
+    fn test_world() -> World {
+        let mut world = World::new();
+        world.spawn((
+            Body {
+                pos: Vector3::new(1.5, -2.0, 3.0),
+                size: Vector3::new(1.0, 1.0, 2.0),
+            },
+            Dimension::new("underground"),
+            ChunkLoader { radius: 3 },
+            Physics {
+                vel: Vector3::new(0.0, 0.5, -1.0),
+            },
+        ));
+        world.spawn((Body {
+            pos: Vector3::zero(),
+            size: Vector3::one(),
+        },));
+        // entities without saved components are left out
+        world.spawn((7_u32,));
+        world
+    }
+
+    /// the saved components of an entity
+    type Saved = (
+        Body,
+        Option<Dimension>,
+        Option<ChunkLoader>,
+        Option<Physics>,
+    );
+
+    /// saved components of every entity in a stable order
+    fn saved(world: &World) -> Vec<Saved> {
+        let mut saved: Vec<_> = world
+            .query::<(
+                &Body,
+                Option<&Dimension>,
+                Option<&ChunkLoader>,
+                Option<&Physics>,
+            )>()
+            .iter()
+            .map(|(body, dimension, loader, physics)| {
+                (
+                    body.clone(),
+                    dimension.cloned(),
+                    loader.copied(),
+                    physics.cloned(),
+                )
+            })
+            .collect();
+        saved.sort_by(|a, b| a.0.pos.x.total_cmp(&b.0.pos.x));
+        saved
+    }
+
+    #[test]
+    fn world_round_trip() {
+        let prefabs = test_prefabs();
+        let registry = ComponentRegistry::normal();
+        let world = test_world();
+        let mut buf = Vec::new();
+        registry.write_world(&world, &mut buf).unwrap();
+
+        let mut loaded = World::new();
+        registry
+            .read_world(&mut loaded, &mut buf.as_slice(), &load_ctx(&prefabs))
+            .unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(saved(&loaded), saved(&world));
+
+        registry.clear_world(&mut loaded);
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn unknown_components_are_skipped() {
+        let prefabs = test_prefabs();
+        let mut buf = Vec::new();
+        ComponentRegistry::normal()
+            .write_world(&test_world(), &mut buf)
+            .unwrap();
+        let mut registry = ComponentRegistry::default();
+        registry.register::<Body>();
+        let mut loaded = World::new();
+        registry
+            .read_world(&mut loaded, &mut buf.as_slice(), &load_ctx(&prefabs))
+            .unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.query::<&Dimension>().iter().count(), 0);
+        assert_eq!(loaded.query::<&Body>().iter().count(), 2);
+    }
+
+    fn test_data() -> SavedData {
+        SavedData {
+            camera: Camera2D {
+                target: Vector2::new(10.0, -4.0),
+                zoom: 3.0,
+                ..Default::default()
+            },
+            selected: Block::ROCK.into(),
+            spawn: Some(Vector3::new(1.0, 2.0, 2.0)),
+        }
+    }
+
+    fn assert_same_data(a: &SavedData, b: &SavedData) {
+        assert_eq!(a.camera.target, b.camera.target);
+        assert_eq!(a.camera.offset, b.camera.offset);
+        assert_eq!(a.camera.rotation, b.camera.rotation);
+        assert_eq!(a.camera.zoom, b.camera.zoom);
+        assert_eq!(a.selected, b.selected);
+        assert_eq!(a.spawn, b.spawn);
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let blockset = BlockSet::normal();
+        let prefabs = test_prefabs();
+        let mut buf = Vec::new();
+        test_data()
+            .write(&test_world(), &blockset, &mut buf)
+            .unwrap();
+
+        let mut data = SavedData {
+            camera: Camera2D::default(),
+            selected: 0,
+            spawn: None,
+        };
+        let mut world = World::new();
+        world.spawn((Body {
+            pos: Vector3::new(-9.0, 0.0, 0.0),
+            size: Vector3::one(),
+        },));
+        let unsaved = world.spawn((7_u32,));
+        data.load(&mut world, &blockset, &prefabs, &mut buf.as_slice())
+            .unwrap();
+        assert_same_data(&data, &test_data());
+        assert_eq!(saved(&world), saved(&test_world()));
+        // entities without saved components stay
+        assert!(world.contains(unsaved));
+    }
+
+    #[test]
+    fn truncated_save_changes_nothing() {
+        let blockset = BlockSet::normal();
+        let prefabs = test_prefabs();
+        let mut buf = Vec::new();
+        SavedData {
+            camera: Camera2D::default(),
+            selected: 0,
+            spawn: None,
+        }
+        .write(&test_world(), &blockset, &mut buf)
+        .unwrap();
+        for len in 0..buf.len() {
+            let mut data = test_data();
+            let mut world = test_world();
+            assert!(
+                data.load(&mut world, &blockset, &prefabs, &mut &buf[..len])
+                    .is_err()
+            );
+            assert_same_data(&data, &test_data());
+            assert_eq!(world.len(), test_world().len());
+            assert_eq!(saved(&world), saved(&test_world()));
+        }
+    }
+
+    #[test]
+    fn truncated_world_fails() {
+        let prefabs = test_prefabs();
+        let registry = ComponentRegistry::normal();
+        let mut buf = Vec::new();
+        registry.write_world(&test_world(), &mut buf).unwrap();
+        for len in 0..buf.len() {
+            let mut loaded = World::new();
+            assert!(
+                registry
+                    .read_world(&mut loaded, &mut &buf[..len], &load_ctx(&prefabs))
+                    .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn animations_come_from_the_tileset() {
+        let prefabs = test_prefabs();
+        let animation = AtlasSpriteAnimation {
+            animations: Arc::new(AnimationsType::default()),
+            current: "walk".to_string(),
+            time: 0.25,
+            flip_h: true,
+            flip_v: false,
+        };
+        let mut buf = Vec::new();
+        animation.write(&mut buf).unwrap();
+        let loaded = AtlasSpriteAnimation::read(&mut buf.as_slice(), &load_ctx(&prefabs)).unwrap();
+        assert!(Arc::ptr_eq(&loaded.animations, &prefabs.animations));
+        assert_eq!(loaded.current, "walk");
+        assert_eq!(loaded.time, 0.25);
+        assert!(loaded.flip_h && !loaded.flip_v);
+
+        let unknown = AtlasSpriteAnimation {
+            current: "swim".to_string(),
+            ..animation
+        };
+        let mut buf = Vec::new();
+        unknown.write(&mut buf).unwrap();
+        let err = AtlasSpriteAnimation::read(&mut buf.as_slice(), &load_ctx(&prefabs)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn old_animation_tables_are_skipped() {
+        let prefabs = test_prefabs();
+        // version 3 wrote every animation with its frames before the state
+        let mut buf = Vec::new();
+        buf.write_u16(1).unwrap();
+        buf.write_str("idle").unwrap();
+        buf.write_f32(2.0).unwrap();
+        buf.write_u16(1).unwrap();
+        buf.write_u8(9).unwrap();
+        buf.write_u8(9).unwrap();
+        buf.write_str("idle").unwrap();
+        buf.write_f32(1.0).unwrap();
+        buf.write_bool(false).unwrap();
+        buf.write_bool(true).unwrap();
+        let ctx = LoadContext {
+            version: 3,
+            prefabs: &prefabs,
+        };
+        let loaded = AtlasSpriteAnimation::read(&mut buf.as_slice(), &ctx).unwrap();
+        assert_eq!(loaded.animations["idle"].0, vec![(1, 2).into()]);
+        assert_eq!(loaded.current, "idle");
+        assert!(!loaded.flip_h && loaded.flip_v);
+    }
+}
@@ -0,0 +1,207 @@
+use raylib::math::{Vector2, Vector3};
+use std::io::{self, Read, Write};
+
+/// little endian binary writing helpers for save files
+pub trait WriteExt: Write {
+    #[inline(always)]
+    fn write_u8(&mut self, v: u8) -> io::Result<()> {
+        self.write_all(&[v])
+    }
+    #[inline(always)]
+    fn write_u16(&mut self, v: u16) -> io::Result<()> {
+        self.write_all(&v.to_le_bytes())
+    }
+    #[inline(always)]
+    fn write_u32(&mut self, v: u32) -> io::Result<()> {
+        self.write_all(&v.to_le_bytes())
+    }
+    #[inline(always)]
+    fn write_u64(&mut self, v: u64) -> io::Result<()> {
+        self.write_all(&v.to_le_bytes())
+    }
+    #[inline(always)]
+    fn write_i32(&mut self, v: i32) -> io::Result<()> {
+        self.write_all(&v.to_le_bytes())
+    }
+    #[inline(always)]
+    fn write_f32(&mut self, v: f32) -> io::Result<()> {
+        self.write_all(&v.to_le_bytes())
+    }
+    #[inline(always)]
+    fn write_bool(&mut self, v: bool) -> io::Result<()> {
+        self.write_u8(v as u8)
+    }
+    fn write_vector2(&mut self, v: Vector2) -> io::Result<()> {
+        self.write_f32(v.x)?;
+        self.write_f32(v.y)
+    }
+    fn write_vector3(&mut self, v: Vector3) -> io::Result<()> {
+        self.write_f32(v.x)?;
+        self.write_f32(v.y)?;
+        self.write_f32(v.z)
+    }
+    /// writes a length prefixed byte slice
+    fn write_bytes(&mut self, v: &[u8]) -> io::Result<()> {
+        self.write_u32(v.len() as u32)?;
+        self.write_all(v)
+    }
+    /// writes a length prefixed utf-8 string
+    fn write_str(&mut self, v: &str) -> io::Result<()> {
+        self.write_bytes(v.as_bytes())
+    }
+}
+impl<W: Write + ?Sized> WriteExt for W {}
+
+/// little endian binary reading helpers for save files
+pub trait ReadExt: Read {
+    #[inline(always)]
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+        self.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+    #[inline(always)]
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let mut buf = [0; 2];
+        self.read_exact(&mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+    #[inline(always)]
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+    #[inline(always)]
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+    #[inline(always)]
+    fn read_i32(&mut self) -> io::Result<i32> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(i32::from_le_bytes(buf))
+    }
+    #[inline(always)]
+    fn read_f32(&mut self) -> io::Result<f32> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(f32::from_le_bytes(buf))
+    }
+    #[inline(always)]
+    fn read_bool(&mut self) -> io::Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+    fn read_vector2(&mut self) -> io::Result<Vector2> {
+        Ok(Vector2 {
+            x: self.read_f32()?,
+            y: self.read_f32()?,
+        })
+    }
+    fn read_vector3(&mut self) -> io::Result<Vector3> {
+        Ok(Vector3 {
+            x: self.read_f32()?,
+            y: self.read_f32()?,
+            z: self.read_f32()?,
+        })
+    }
+    /// reads a length prefixed byte vector
+    fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.read_u32()? as u64;
+        // grows with the data instead of trusting a corrupt length
+        let mut buf = Vec::new();
+        (&mut *self).take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(buf)
+    }
+    /// reads a length prefixed utf-8 string
+    fn read_str(&mut self) -> io::Result<String> {
+        String::from_utf8(self.read_bytes()?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+impl<R: Read + ?Sized> ReadExt for R {}
+
+/// checks the magic bytes at the start of a save file
+pub fn expect_magic(r: &mut impl Read, magic: &[u8; 4]) -> io::Result<()> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    if &buf != magic {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "expected magic {:?}, found {:?}",
+                String::from_utf8_lossy(magic),
+                String::from_utf8_lossy(&buf)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let mut buf = Vec::new();
+        buf.write_u8(7).unwrap();
+        buf.write_u16(0xbeef).unwrap();
+        buf.write_u32(0xdead_beef).unwrap();
+        buf.write_u64(u64::MAX - 1).unwrap();
+        buf.write_i32(-5).unwrap();
+        buf.write_f32(1.5).unwrap();
+        buf.write_bool(true).unwrap();
+        buf.write_vector2(Vector2::new(1.0, -2.0)).unwrap();
+        buf.write_vector3(Vector3::new(3.0, 4.5, -6.0)).unwrap();
+        buf.write_bytes(&[1, 2, 3]).unwrap();
+        buf.write_str("berry_bush").unwrap();
+
+        let mut r = buf.as_slice();
+        assert_eq!(r.read_u8().unwrap(), 7);
+        assert_eq!(r.read_u16().unwrap(), 0xbeef);
+        assert_eq!(r.read_u32().unwrap(), 0xdead_beef);
+        assert_eq!(r.read_u64().unwrap(), u64::MAX - 1);
+        assert_eq!(r.read_i32().unwrap(), -5);
+        assert_eq!(r.read_f32().unwrap(), 1.5);
+        assert!(r.read_bool().unwrap());
+        assert_eq!(r.read_vector2().unwrap(), Vector2::new(1.0, -2.0));
+        assert_eq!(r.read_vector3().unwrap(), Vector3::new(3.0, 4.5, -6.0));
+        assert_eq!(r.read_bytes().unwrap(), vec![1, 2, 3]);
+        assert_eq!(r.read_str().unwrap(), "berry_bush");
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn truncated_input_fails() {
+        let mut buf = Vec::new();
+        buf.write_str("picocraft").unwrap();
+        for len in 0..buf.len() {
+            let err = (&buf[..len]).read_str().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+        // a corrupt length fails once the data ends instead of allocating it
+        let mut buf = Vec::new();
+        buf.write_u32(u32::MAX).unwrap();
+        buf.write_all(&[0; 16]).unwrap();
+        let err = buf.as_slice().read_bytes().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn invalid_strings_and_magic_fail() {
+        let mut buf = Vec::new();
+        buf.write_bytes(&[0xff, 0xfe]).unwrap();
+        let err = buf.as_slice().read_str().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        assert!(expect_magic(&mut b"PCRG".as_slice(), b"PCRG").is_ok());
+        let err = expect_magic(&mut b"PCSV".as_slice(), b"PCRG").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
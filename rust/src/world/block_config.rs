@@ -0,0 +1,180 @@
+use super::map::{
+    AutoBlockFn, Block, BlockAnimation, BlockData, BlockFlag, BlockKind, BlockSet, at47,
+};
+use raylib::prelude::*;
+use ron::extensions::Extensions;
+use serde::Deserialize;
+use std::{fs, io, path::Path, sync::Arc};
+
+/// block definitions shipped with the game
+pub const DEFAULT_BLOCKS: &str = include_str!("../../assets/blocks.ron");
+/// color of blocks without one on exported maps
+pub const DEFAULT_BLOCK_COLOR: Color = Color::new(128, 128, 128, 255);
+
+/// one block of a block definition file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockDef {
+    name: String,
+    atlas: (u8, u8),
+    kind: BlockKindDef,
+    #[serde(default)]
+    flags: Vec<BlockFlag>,
+    animation: Option<AnimationDef>,
+    color: Option<(u8, u8, u8)>,
+    /// multiplied with the atlas tile when drawn, lets blocks share art
+    tint: Option<(u8, u8, u8)>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnimationDef {
+    frames: u8,
+    /// atlas distance between two frames
+    step: (u8, u8),
+    fps: f32,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum BlockKindDef {
+    /// autotiled with the named scheme
+    Autotile(String),
+    Prop,
+}
+
+/// names of the autotiling schemes usable in block definitions
+pub const AUTOTILE_SCHEMES: [&str; 1] = ["at47"];
+/// returns the autotiling function of a scheme
+pub fn autotile_scheme(name: &str) -> Option<AutoBlockFn> {
+    match name {
+        "at47" => Some(Arc::new(at47)),
+        _ => None,
+    }
+}
+
+impl BlockSet {
+    /// blocks of the game from `DEFAULT_BLOCKS`
+    pub fn normal() -> Self {
+        Self::from_ron(DEFAULT_BLOCKS).expect("default block definitions are invalid")
+    }
+    /// loads block definitions from a RON file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Self::from_ron(&text)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+    }
+    /// parses block definitions, built-in blocks keep their ids and every
+    /// other block gets the next free id in definition order
+    pub fn from_ron(text: &str) -> io::Result<Self> {
+        // lets `color: (r, g, b)` be written without `Some`
+        let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+        let defs: Vec<BlockDef> = options
+            .from_str(text)
+            .map_err(|err| invalid(err.to_string()))?;
+        let mut set = BlockSet::default();
+        let mut next_id = Block::BUILTIN.len();
+        for def in defs {
+            let name = def.name.as_str();
+            if name.is_empty() {
+                return Err(invalid("block with an empty name"));
+            }
+            if name == "air" {
+                return Err(invalid("\"air\" is built in and can not be defined"));
+            }
+            let block = match set.block(name) {
+                Some(block) => block,
+                None => {
+                    if next_id > u8::MAX as usize {
+                        return Err(invalid(format!(
+                            "block {name:?} does not fit, at most {} blocks are allowed",
+                            u8::MAX as usize + 1
+                        )));
+                    }
+                    next_id += 1;
+                    Block((next_id - 1) as u8)
+                }
+            };
+            if set.get_data(block).is_some() {
+                return Err(invalid(format!("block {name:?} is defined twice")));
+            }
+            let kind = match &def.kind {
+                BlockKindDef::Prop => BlockKind::Prop,
+                BlockKindDef::Autotile(scheme) => {
+                    BlockKind::Block(autotile_scheme(scheme).ok_or_else(|| {
+                        invalid(format!(
+                            "block {name:?} uses unknown autotile scheme {scheme:?}, expected one of {AUTOTILE_SCHEMES:?}"
+                        ))
+                    })?)
+                }
+            };
+            let animation = match def.animation {
+                Some(AnimationDef { frames, step, fps }) => {
+                    if frames == 0 || fps <= 0.0 {
+                        return Err(invalid(format!(
+                            "block {name:?} needs at least one frame and a positive fps"
+                        )));
+                    }
+                    let last = |pos: u8, step: u8| {
+                        step.checked_mul(frames - 1)
+                            .and_then(|offset| pos.checked_add(offset))
+                    };
+                    if last(def.atlas.0, step.0).is_none() || last(def.atlas.1, step.1).is_none() {
+                        return Err(invalid(format!(
+                            "block {name:?} has animation frames past the end of the atlas"
+                        )));
+                    }
+                    Some(BlockAnimation {
+                        frames,
+                        step: step.into(),
+                        fps,
+                    })
+                }
+                None => None,
+            };
+            set.insert(
+                block,
+                BlockData {
+                    name: def.name.clone(),
+                    atlas_pos: def.atlas.into(),
+                    kind,
+                    flags: def.flags,
+                    animation,
+                    color: def
+                        .color
+                        .map_or(DEFAULT_BLOCK_COLOR, |(r, g, b)| Color::new(r, g, b, 255)),
+                    tint: def
+                        .tint
+                        .map_or(Color::WHITE, |(r, g, b)| Color::new(r, g, b, 255)),
+                },
+            );
+        }
+        // the game places built-in blocks itself
+        for (id, name) in Block::BUILTIN.iter().enumerate().skip(1) {
+            if set.get_data(Block(id as u8)).is_none() {
+                return Err(invalid(format!("built-in block {name:?} is not defined")));
+            }
+        }
+        Ok(set)
+    }
+}
+
+#[inline(always)]
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn animations_past_the_atlas_are_rejected() {
+        let text = DEFAULT_BLOCKS.replace("atlas: (0, 36)", "atlas: (0, 250)");
+        let err = BlockSet::from_ron(&text).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("water"), "{err}");
+        // the last frame may end exactly on the atlas edge
+        let text = DEFAULT_BLOCKS.replace("atlas: (0, 36)", "atlas: (0, 243)");
+        assert!(BlockSet::from_ron(&text).is_ok());
+    }
+}
@@ -0,0 +1,337 @@
+use super::{map::Block, palette::BlockRemap, units::*};
+use crate::serial::{ReadExt, WriteExt};
+use std::io::{self, Read, Write};
+
+/// blocks of a chunk, either one block everywhere or bit packed indices into a palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkBlocks {
+    Single(Block),
+    Paletted(PalettedBlocks),
+}
+/// bit packed palette indices, entries never span two words
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PalettedBlocks {
+    pub palette: Vec<Block>,
+    /// how many blocks use each palette entry
+    pub counts: Vec<u16>,
+    pub bits: u8,
+    pub words: Vec<u64>,
+}
+
+impl Default for ChunkBlocks {
+    fn default() -> Self {
+        ChunkBlocks::Single(Block::default())
+    }
+}
+impl ChunkBlocks {
+    /// returns `Block` at the flat index
+    #[inline(always)]
+    pub fn get(&self, idx: usize) -> Block {
+        match self {
+            ChunkBlocks::Single(block) => *block,
+            ChunkBlocks::Paletted(paletted) => paletted.palette[paletted.index(idx)],
+        }
+    }
+    /// sets `Block` at the flat index, switching the representation if needed,
+    /// returns if the block changed
+    #[inline(always)]
+    pub fn set(&mut self, idx: usize, block: Block) -> bool {
+        match self {
+            ChunkBlocks::Single(single) => {
+                if *single == block {
+                    return false;
+                }
+                let mut paletted = PalettedBlocks::filled(*single);
+                paletted.set(idx, block);
+                *self = ChunkBlocks::Paletted(paletted);
+                true
+            }
+            ChunkBlocks::Paletted(paletted) => {
+                if !paletted.set(idx, block) {
+                    return false;
+                }
+                // collapse back once only one block is left
+                if let Some(single) = paletted.single() {
+                    *self = ChunkBlocks::Single(single);
+                }
+                true
+            }
+        }
+    }
+    /// returns if the whole chunk is `block`
+    #[inline(always)]
+    pub fn is_single(&self, block: Block) -> bool {
+        matches!(self, ChunkBlocks::Single(single) if *single == block)
+    }
+    /// approximate heap and inline size in bytes
+    pub fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + match self {
+                ChunkBlocks::Single(_) => 0,
+                ChunkBlocks::Paletted(paletted) => {
+                    paletted.palette.capacity() * std::mem::size_of::<Block>()
+                        + paletted.counts.capacity() * 2
+                        + paletted.words.capacity() * 8
+                }
+            }
+    }
+
+    /// writes the blocks as saved ids
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        match self {
+            ChunkBlocks::Single(block) => {
+                w.write_u8(0)?;
+                w.write_u8((*block).into())
+            }
+            ChunkBlocks::Paletted(paletted) => {
+                w.write_u8(1)?;
+                w.write_u16(paletted.palette.len() as u16)?;
+                for block in paletted.palette.iter() {
+                    w.write_u8((*block).into())?;
+                }
+                w.write_u8(paletted.bits)?;
+                w.write_u16(paletted.words.len() as u16)?;
+                for word in paletted.words.iter() {
+                    w.write_u64(*word)?;
+                }
+                Ok(())
+            }
+        }
+    }
+    /// reads blocks written by `write`, mapping saved ids with `remap`
+    pub fn read(r: &mut impl Read, remap: &BlockRemap) -> io::Result<Self> {
+        match r.read_u8()? {
+            0 => Ok(ChunkBlocks::Single(remap.get(r.read_u8()?))),
+            1 => {
+                let len = r.read_u16()? as usize;
+                let mut palette = Vec::with_capacity(len);
+                for _ in 0..len {
+                    palette.push(remap.get(r.read_u8()?));
+                }
+                let bits = r.read_u8()?;
+                let word_count = r.read_u16()? as usize;
+                if bits == 0
+                    || bits > 16
+                    || len == 0
+                    || word_count != PalettedBlocks::word_count(bits)
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid paletted chunk with {len} entries and {bits} bits"),
+                    ));
+                }
+                let mut words = Vec::with_capacity(word_count);
+                for _ in 0..word_count {
+                    words.push(r.read_u64()?);
+                }
+                let mut paletted = PalettedBlocks {
+                    counts: vec![0; palette.len()],
+                    palette,
+                    bits,
+                    words,
+                };
+                for idx in 0..CHUNK_VOLUME {
+                    let entry = paletted.index(idx);
+                    let Some(count) = paletted.counts.get_mut(entry) else {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("palette index {entry} out of range"),
+                        ));
+                    };
+                    *count += 1;
+                }
+                Ok(match paletted.single() {
+                    Some(single) => ChunkBlocks::Single(single),
+                    None => ChunkBlocks::Paletted(paletted),
+                })
+            }
+            tag => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown chunk encoding {tag}"),
+            )),
+        }
+    }
+}
+
+impl PalettedBlocks {
+    /// palette with one bit per block, every block set to `block`
+    pub fn filled(block: Block) -> Self {
+        Self {
+            palette: vec![block],
+            counts: vec![CHUNK_VOLUME as u16],
+            bits: 1,
+            words: vec![0; Self::word_count(1)],
+        }
+    }
+    /// number of words to store `CHUNK_VOLUME` entries of `bits`
+    #[inline(always)]
+    pub fn word_count(bits: u8) -> usize {
+        CHUNK_VOLUME.div_ceil(64 / bits as usize)
+    }
+    /// returns the palette index at the flat index
+    #[inline(always)]
+    pub fn index(&self, idx: usize) -> usize {
+        let per_word = 64 / self.bits as usize;
+        let word = self.words[idx / per_word];
+        let shift = (idx % per_word) * self.bits as usize;
+        ((word >> shift) & ((1u64 << self.bits) - 1)) as usize
+    }
+    #[inline(always)]
+    fn set_index(&mut self, idx: usize, entry: usize) {
+        let per_word = 64 / self.bits as usize;
+        let shift = (idx % per_word) * self.bits as usize;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.words[idx / per_word];
+        *word = (*word & !mask) | ((entry as u64) << shift);
+    }
+    /// returns the only used block if every other entry is unused
+    pub fn single(&self) -> Option<Block> {
+        let mut used = self
+            .palette
+            .iter()
+            .zip(self.counts.iter())
+            .filter(|(_, count)| **count > 0);
+        let (block, _) = used.next()?;
+        used.next().is_none().then_some(*block)
+    }
+    /// returns the palette index of `block`, adding it if needed
+    fn entry(&mut self, block: Block) -> usize {
+        if let Some(entry) = self.palette.iter().position(|b| *b == block) {
+            return entry;
+        }
+        // reuse an unused entry before growing
+        if let Some(entry) = self.counts.iter().position(|count| *count == 0) {
+            self.palette[entry] = block;
+            return entry;
+        }
+        self.palette.push(block);
+        self.counts.push(0);
+        if self.palette.len() > 1 << self.bits {
+            self.repack(self.bits + 1);
+        }
+        self.palette.len() - 1
+    }
+    /// stores every entry again with `bits` per entry
+    fn repack(&mut self, bits: u8) {
+        let indices: Vec<usize> = (0..CHUNK_VOLUME).map(|idx| self.index(idx)).collect();
+        self.bits = bits;
+        self.words = vec![0; Self::word_count(bits)];
+        for (idx, entry) in indices.into_iter().enumerate() {
+            self.set_index(idx, entry);
+        }
+    }
+    /// sets `Block` at the flat index, returns if the block changed
+    pub fn set(&mut self, idx: usize, block: Block) -> bool {
+        let old = self.index(idx);
+        if self.palette[old] == block {
+            return false;
+        }
+        self.counts[old] -= 1;
+        let entry = self.entry(block);
+        self.counts[entry] += 1;
+        self.set_index(idx, entry);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// blocks with `kinds` different ids spread over the chunk
+    fn pattern(kinds: usize) -> Vec<Block> {
+        (0..CHUNK_VOLUME)
+            .map(|idx| Block(((idx * 7 + idx / 13) % kinds) as u8))
+            .collect()
+    }
+
+    fn filled(blocks: &[Block]) -> ChunkBlocks {
+        let mut chunk = ChunkBlocks::default();
+        for (idx, block) in blocks.iter().enumerate() {
+            chunk.set(idx, *block);
+        }
+        chunk
+    }
+
+    fn assert_blocks(chunk: &ChunkBlocks, blocks: &[Block]) {
+        for (idx, block) in blocks.iter().enumerate() {
+            assert_eq!(chunk.get(idx), *block, "block {idx}");
+        }
+    }
+
+    #[test]
+    fn repacks_across_bit_widths() {
+        for kinds in [2, 3, 4, 5, 16, 17, 100, 256] {
+            let blocks = pattern(kinds);
+            let chunk = filled(&blocks);
+            assert_blocks(&chunk, &blocks);
+            let ChunkBlocks::Paletted(paletted) = &chunk else {
+                panic!("{kinds} blocks are not paletted");
+            };
+            assert!(paletted.palette.len() <= 1 << paletted.bits);
+            assert_eq!(
+                paletted.words.len(),
+                PalettedBlocks::word_count(paletted.bits)
+            );
+        }
+    }
+
+    #[test]
+    fn collapses_to_a_single_block() {
+        let mut chunk = filled(&pattern(5));
+        for idx in 0..CHUNK_VOLUME {
+            chunk.set(idx, Block::ROCK);
+        }
+        assert_eq!(chunk, ChunkBlocks::Single(Block::ROCK));
+        assert!(!chunk.set(3, Block::ROCK));
+        assert!(chunk.set(3, Block::AIR));
+        assert_eq!(chunk.get(3), Block::AIR);
+        assert_eq!(chunk.get(4), Block::ROCK);
+    }
+
+    #[test]
+    fn round_trip_and_remap() {
+        for kinds in [1, 2, 9, 256] {
+            let blocks = pattern(kinds);
+            let chunk = filled(&blocks);
+            let mut buf = Vec::new();
+            chunk.write(&mut buf).unwrap();
+            let read = ChunkBlocks::read(&mut buf.as_slice(), &BlockRemap::identity()).unwrap();
+            assert_blocks(&read, &blocks);
+
+            // every saved id moves one up
+            let remap = BlockRemap {
+                blocks: (0..=u8::MAX).map(|id| Block(id.wrapping_add(1))).collect(),
+            };
+            let read = ChunkBlocks::read(&mut buf.as_slice(), &remap).unwrap();
+            let moved: Vec<Block> = blocks.iter().map(|b| Block(b.0.wrapping_add(1))).collect();
+            assert_blocks(&read, &moved);
+        }
+    }
+
+    #[test]
+    fn truncated_or_corrupt_input_fails() {
+        let mut buf = Vec::new();
+        filled(&pattern(3)).write(&mut buf).unwrap();
+        for len in 0..buf.len() {
+            assert!(ChunkBlocks::read(&mut &buf[..len], &BlockRemap::identity()).is_err());
+        }
+        // tag, 3 palette entries, then the bits
+        let bits = 1 + 2 + 3;
+        for corrupt_bits in [0, 1, 17] {
+            let mut corrupt = buf.clone();
+            corrupt[bits] = corrupt_bits;
+            let err =
+                ChunkBlocks::read(&mut corrupt.as_slice(), &BlockRemap::identity()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        // an index past the palette
+        let mut corrupt = buf.clone();
+        let words = bits + 1 + 2;
+        corrupt[words] = 0xff;
+        let err = ChunkBlocks::read(&mut corrupt.as_slice(), &BlockRemap::identity()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = ChunkBlocks::read(&mut [9].as_slice(), &BlockRemap::identity()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,81 @@
+use super::{
+    map::{Block, BlockSet, Chunk},
+    units::*,
+};
+use raylib::prelude::*;
+use rustc_hash::FxHashMap;
+
+/// top-down map color of the sky
+pub const AIR_COLOR: Color = Color::new(102, 191, 255, 255);
+
+/// top-down map color of a `Block` in `BlockSet`
+pub fn block_color(block: Block, blockset: &BlockSet) -> Color {
+    if block == Block::AIR {
+        return AIR_COLOR;
+    }
+    blockset
+        .get_data(block)
+        .map_or(Color::new(255, 0, 255, 255), |data| data.color)
+}
+
+/// returns the highest non-`Air` `Block` of a column and its height
+pub fn top_block(chunk: &Chunk, x: usize, y: usize) -> Option<(Block, usize)> {
+    (0..CHUNK_HEIGHT).rev().find_map(|z| {
+        let block = chunk.get(ChunkBlockPos { x, y, z })?;
+        (block != Block::AIR).then_some((block, z))
+    })
+}
+
+/// renders the chunks between `start` and `end` (inclusive) as one pixel per column,
+/// seen from above through every level, lower columns are drawn darker and missing
+/// chunks stay transparent
+pub fn render_top_down(
+    chunks: &FxHashMap<ChunkPos, Chunk>,
+    blockset: &BlockSet,
+    start: ChunkPos,
+    end: ChunkPos,
+) -> Image {
+    let width = (end.x - start.x + 1) * CHUNK_SIZE as i32;
+    let height = (end.y - start.y + 1) * CHUNK_SIZE as i32;
+    let bottom = start.z * CHUNK_HEIGHT as i32;
+    let levels = (end.z - start.z + 1) * CHUNK_HEIGHT as i32;
+    let mut image = Image::gen_image_color(width, height, Color::BLANK);
+    for cy in start.y..=end.y {
+        for cx in start.x..=end.x {
+            let column: Vec<(i32, &Chunk)> = (start.z..=end.z)
+                .rev()
+                .filter_map(|z| Some((z, chunks.get(&ChunkPos { x: cx, y: cy, z })?)))
+                .collect();
+            if column.is_empty() {
+                continue;
+            }
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let top = column.iter().find_map(|(cz, chunk)| {
+                        let (block, z) = top_block(chunk, x, y)?;
+                        Some((block, cz * CHUNK_HEIGHT as i32 + z as i32))
+                    });
+                    let color = match top {
+                        Some((block, z)) => {
+                            let shade = 0.6 + 0.4 * ((z - bottom) as f32 / (levels - 1) as f32);
+                            let c = block_color(block, blockset);
+                            Color::new(
+                                (c.r as f32 * shade) as u8,
+                                (c.g as f32 * shade) as u8,
+                                (c.b as f32 * shade) as u8,
+                                c.a,
+                            )
+                        }
+                        None => AIR_COLOR,
+                    };
+                    image.draw_pixel(
+                        (cx - start.x) * CHUNK_SIZE as i32 + x as i32,
+                        (cy - start.y) * CHUNK_SIZE as i32 + y as i32,
+                        color,
+                    );
+                }
+            }
+        }
+    }
+    image
+}
@@ -1,188 +1,804 @@
 use super::{
     map::{Block, Chunk},
+    noise_graph::NoiseGraph,
+    noise_source::{GetNoise, NoiseConfig, NoiseLayers},
+    pipeline::{ChunkPipeline, ChunkRegion, GenStage},
+    scatter::{Scatter, ScatterRule},
+    structure::StructureSet,
     units::*,
 };
-use noise::{Fbm, NoiseFn, Perlin};
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use std::ops::RangeInclusive;
 
 pub trait TerrainGenerator {
-    type ChunkData;
-    fn get_chunk_data(&self, cpos: ChunkPos, perlin: &Fbm<Perlin>) -> Self::ChunkData;
-    fn gen_chunk(&self, cpos: ChunkPos, perlin: &Fbm<Perlin>) -> Chunk {
-        let mut chunk = Chunk::new_empty();
-        let chunk_data = self.get_chunk_data(cpos, perlin);
+    type ChunkData: Send + Sync;
+    /// world seed every noise layer is seeded from
+    fn seed(&self) -> u32;
+    /// reseeds every noise layer for the world `seed`
+    fn set_seed(&mut self, seed: u32);
+    fn get_chunk_data(&self, cpos: ChunkPos) -> Self::ChunkData;
+    /// neighbor chunks on the same level in every direction `stage` reads, they are
+    /// at the stage before
+    fn stage_radius(&self, stage: GenStage) -> i32 {
+        let _ = stage;
+        0
+    }
+    /// runs one `GenStage` on the chunk of `region`
+    fn gen_stage(&self, stage: GenStage, region: &mut ChunkRegion, chunk_data: &Self::ChunkData) {
+        match stage {
+            GenStage::Terrain => self.gen_terrain(region, chunk_data),
+            GenStage::Structures => self.gen_structures(region),
+            _ => {}
+        }
+    }
+    /// fills the chunk with `gen_block`
+    fn gen_terrain(&self, region: &mut ChunkRegion, chunk_data: &Self::ChunkData) {
         for z in 0..CHUNK_HEIGHT {
             for y in 0..CHUNK_SIZE {
                 for x in 0..CHUNK_SIZE {
                     let ctpos = ChunkBlockPos { x, y, z };
-                    let wpos = ctpos.to_world(cpos);
-                    chunk.set(ctpos, self.gen_block(wpos, perlin, &chunk_data));
+                    let wpos = ctpos.to_world(region.cpos);
+                    region.set(ctpos, self.gen_block(wpos, chunk_data));
                 }
             }
         }
-        chunk
     }
-    fn gen_block(
-        &self,
-        wpos: WorldBlockPos,
-        perlin: &Fbm<Perlin>,
-        chunk_data: &Self::ChunkData,
-    ) -> Block;
-}
-
-pub trait GetNoise<const DIM: usize> {
-    fn get(&self, pos: [f64; DIM], perlin: &Fbm<Perlin>) -> f64;
-}
-#[derive(Debug, Clone, PartialEq)]
-pub struct NoiseConfig<const DIM: usize> {
-    pub freq: f64,
-    pub amp: f64,
-    pub gain: f64,
-    pub offset: [f64; DIM],
-}
-impl GetNoise<2> for NoiseConfig<2> {
-    #[inline(always)]
-    fn get(&self, pos: [f64; 2], perlin: &Fbm<Perlin>) -> f64 {
-        let p = [0, 1].map(|i| pos[i] + self.offset[i] * self.freq);
-        perlin.get(p) * self.amp * self.gain
+    /// places the parts of `structures` that reach into the chunk
+    fn gen_structures(&self, region: &mut ChunkRegion) {
+        if let Some(structures) = self.structures() {
+            structures.place(region, self.seed(), |x, y| self.ground_height(x, y));
+        }
     }
-}
-impl GetNoise<3> for NoiseConfig<3> {
-    #[inline(always)]
-    fn get(&self, pos: [f64; 3], perlin: &Fbm<Perlin>) -> f64 {
-        let p = [0, 1, 2].map(|i| pos[i] + self.offset[i] * self.freq);
-        perlin.get(p) * self.amp * self.gain
+    fn gen_block(&self, wpos: WorldBlockPos, chunk_data: &Self::ChunkData) -> Block;
+    /// first free z above the ground of a column, `None` where nothing can be built
+    fn ground_height(&self, x: i32, y: i32) -> Option<i32>;
+    /// structures placed on top of the generated terrain
+    fn structures(&self) -> Option<&StructureSet> {
+        None
     }
 }
-#[derive(Debug, Clone, PartialEq)]
-pub struct NoiseLayers<const SIZE: usize, const DIM: usize> {
-    pub layers: [NoiseConfig<DIM>; SIZE],
-    pub scales: [f64; SIZE],
-}
-impl<const SIZE: usize> GetNoise<2> for NoiseLayers<SIZE, 2> {
-    fn get(&self, pos: [f64; 2], perlin: &Fbm<Perlin>) -> f64 {
-        self.layers
-            .iter()
-            .zip(self.scales)
-            .map(|(layer, scale)| layer.get(pos, perlin) * scale)
-            .sum::<f64>()
+
+/// generates every chunk between `start` and `end` (inclusive) without a `BlockMap`
+pub fn gen_area<G: TerrainGenerator + Sync>(
+    generator: &G,
+    start: ChunkPos,
+    end: ChunkPos,
+) -> FxHashMap<ChunkPos, Chunk> {
+    let mut pipeline = ChunkPipeline::new(generator);
+    let mut count = 0;
+    for z in start.z..=end.z {
+        for y in start.y..=end.y {
+            for x in start.x..=end.x {
+                pipeline.request(ChunkPos { x, y, z });
+                count += 1;
+            }
+        }
     }
-}
-impl<const SIZE: usize> GetNoise<3> for NoiseLayers<SIZE, 3> {
-    fn get(&self, pos: [f64; 3], perlin: &Fbm<Perlin>) -> f64 {
-        self.layers
-            .iter()
-            .zip(self.scales)
-            .map(|(layer, scale)| layer.get(pos, perlin) * scale)
-            .sum::<f64>()
+    let mut chunks = FxHashMap::default();
+    while chunks.len() < count {
+        let jobs = pipeline.jobs(usize::MAX);
+        if jobs.is_empty() {
+            break;
+        }
+        let results: Vec<_> = jobs.into_par_iter().map(|job| job.run(generator)).collect();
+        for result in results {
+            if let Some((cpos, chunk)) = pipeline.complete(result) {
+                chunks.insert(cpos, chunk);
+            }
+        }
     }
+    chunks
 }
 
 #[derive(Debug)]
 pub struct OverWorldGenerator {
     pub block_height: NoiseLayers<1, 2>,
+    /// replaces `block_height` when set, evaluated at block positions
+    pub terrain: Option<NoiseGraph>,
+    /// makes flora grow in patches
     pub plants: NoiseConfig<2>,
+    /// scale of block positions for the plants noise
+    pub plants_scale: f64,
+    /// props placed on the ground, a rule can only be `near` the blocks of earlier rules
+    pub scatter: Vec<ScatterRule>,
+    pub temperature: NoiseConfig<2>,
+    pub moisture: NoiseConfig<2>,
+    /// scale of block positions for the temperature and moisture noise
+    pub climate_scale: f64,
+    /// how fast a biome fades out towards its neighbors, higher means thinner borders
+    pub biome_sharpness: f64,
+    pub biomes: Vec<BiomeConfig>,
+    pub underground: UndergroundConfig,
+    /// ridged noise whose ridges become river valleys
+    pub rivers: NoiseConfig<2>,
+    /// scale of block positions for the river noise
+    pub river_scale: f64,
+    /// width of a river valley in ridged noise units
+    pub river_width: f64,
+    /// terrain height in the middle of a river valley, below 0 is water
+    pub river_bed: f64,
+    /// distance in blocks of the surrounding terrain checked for lake basins
+    pub lake_radius: f64,
+    pub structures: StructureSet,
+    /// set with `TerrainGenerator::set_seed`
+    pub seed: u32,
 }
 impl Default for OverWorldGenerator {
     fn default() -> Self {
         Self {
+            seed: 0,
             block_height: NoiseLayers {
                 layers: [NoiseConfig {
                     freq: 0.01,
-                    amp: 1.,
-                    gain: 1.,
-                    offset: [100., 200.],
+                    ..NoiseConfig::new(1, [100., 200.])
                 }],
                 scales: [1.],
             },
-            plants: NoiseConfig {
-                freq: 1.0,
-                amp: 1.0,
-                gain: 1.,
-                offset: [0., 0.],
-            },
+            terrain: None,
+            plants: NoiseConfig::new(2, [0., 0.]),
+            plants_scale: 0.05,
+            scatter: vec![
+                ScatterRule::new(Block::TREE, 3).with_wide(),
+                ScatterRule::new(Block::BERRY_BUSH, 3),
+                ScatterRule::new(Block::BUSH, 2),
+                ScatterRule::new(Block::STONE, 2),
+                ScatterRule::new(Block::MUSHROOM, 1).with_near(Block::TREE, 2),
+                ScatterRule::new(Block::FLOWER, 1),
+            ],
+            temperature: NoiseConfig::new(3, [37.5, -12.25]),
+            moisture: NoiseConfig::new(4, [-64.75, 81.5]),
+            climate_scale: 0.003,
+            biome_sharpness: 4.0,
+            biomes: vec![
+                BiomeConfig {
+                    biome: OverWorldBiom::Plains,
+                    temperature: 0.0,
+                    moisture: 0.0,
+                    height_scale: 1.0,
+                    height_offset: 0.0,
+                    surface: Block::GRASS,
+                    shore: Block::SAND,
+                    hill: Block::ROCK,
+                    flora: vec![
+                        (Block::TREE, 0.12),
+                        (Block::BERRY_BUSH, 0.15),
+                        (Block::BUSH, 0.2),
+                        (Block::STONE, 0.02),
+                        (Block::MUSHROOM, 0.1),
+                        (Block::FLOWER, 0.1),
+                    ],
+                },
+                BiomeConfig {
+                    biome: OverWorldBiom::Forest,
+                    temperature: 0.05,
+                    moisture: 0.25,
+                    height_scale: 1.0,
+                    height_offset: 0.02,
+                    surface: Block::GRASS,
+                    shore: Block::SAND,
+                    hill: Block::ROCK,
+                    flora: vec![
+                        (Block::TREE, 0.6),
+                        (Block::BERRY_BUSH, 0.2),
+                        (Block::BUSH, 0.25),
+                        (Block::MUSHROOM, 0.35),
+                        (Block::FLOWER, 0.04),
+                    ],
+                },
+                BiomeConfig {
+                    biome: OverWorldBiom::Desert,
+                    temperature: 0.3,
+                    moisture: -0.25,
+                    height_scale: 0.8,
+                    height_offset: 0.05,
+                    surface: Block::SAND,
+                    shore: Block::SAND,
+                    hill: Block::ROCK,
+                    flora: vec![(Block::BUSH, 0.06), (Block::STONE, 0.05)],
+                },
+                BiomeConfig {
+                    biome: OverWorldBiom::Tundra,
+                    temperature: -0.3,
+                    moisture: 0.05,
+                    height_scale: 1.0,
+                    height_offset: 0.0,
+                    surface: Block::ROCK,
+                    shore: Block::SAND,
+                    hill: Block::ROCK,
+                    flora: vec![
+                        (Block::BUSH, 0.03),
+                        (Block::STONE, 0.15),
+                        (Block::FLOWER, 0.08),
+                    ],
+                },
+                BiomeConfig {
+                    biome: OverWorldBiom::Swamp,
+                    temperature: 0.2,
+                    moisture: 0.35,
+                    height_scale: 0.6,
+                    height_offset: -0.02,
+                    surface: Block::GRASS,
+                    shore: Block::SAND,
+                    hill: Block::GRASS,
+                    flora: vec![
+                        (Block::TREE, 0.15),
+                        (Block::BUSH, 0.2),
+                        (Block::MUSHROOM, 0.5),
+                        (Block::FLOWER, 0.06),
+                    ],
+                },
+                BiomeConfig {
+                    biome: OverWorldBiom::Mountains,
+                    temperature: -0.2,
+                    moisture: -0.25,
+                    height_scale: 1.5,
+                    height_offset: 0.25,
+                    surface: Block::ROCK,
+                    shore: Block::SAND,
+                    hill: Block::ROCK,
+                    flora: vec![
+                        (Block::TREE, 0.3),
+                        (Block::STONE, 0.1),
+                        (Block::FLOWER, 0.03),
+                    ],
+                },
+            ],
+            underground: UndergroundConfig::default(),
+            rivers: NoiseConfig::new(5, [-19.5, 140.25]),
+            river_scale: 0.004,
+            river_width: 0.06,
+            river_bed: -0.05,
+            lake_radius: 12.0,
+            structures: StructureSet::default(),
         }
     }
 }
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum OverWorldBiom {
+    #[default]
     Plains,
+    Desert,
+    Forest,
+    Tundra,
+    Swamp,
+    Mountains,
+}
+/// settings of one biome of the `OverWorldGenerator`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiomeConfig {
+    pub biome: OverWorldBiom,
+    /// temperature noise value the biome is centered at
+    pub temperature: f64,
+    /// moisture noise value the biome is centered at
+    pub moisture: f64,
+    /// applied to the terrain height before the offset
+    pub height_scale: f64,
+    pub height_offset: f64,
+    /// ground block on top of the land
+    pub surface: Block,
+    /// ground block next to the water
+    pub shore: Block,
+    /// ground block of hills
+    pub hill: Block,
+    /// flora block and the chance of a `ScatterRule` candidate to grow it
+    pub flora: Vec<(Block, f64)>,
+}
+impl BiomeConfig {
+    /// returns the chance of a scatter candidate to grow `block`, 0 if the biome has none
+    #[inline(always)]
+    pub fn flora_chance(&self, block: Block) -> f64 {
+        self.flora
+            .iter()
+            .find(|(flora, _)| *flora == block)
+            .map_or(0.0, |(_, chance)| *chance)
+    }
+}
+/// settings of the blocks below the surface of the `OverWorldGenerator`
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndergroundConfig {
+    /// ground block of everything that is no cave or ore
+    pub fill: Block,
+    pub caves: NoiseLayers<2, 3>,
+    /// scale of block positions for the cave noise
+    pub cave_scale: [f64; 3],
+    /// cave noise value above which a pocket is carved
+    pub cave_threshold: f64,
+    /// minimum depth below the surface of caves so the ground never opens up
+    pub cave_depth: i32,
+    /// scale of block positions for the ore vein noise
+    pub vein_scale: [f64; 3],
+    /// rarity table, checked in order so the rarest ore comes first
+    pub ores: Vec<OreConfig>,
+}
+impl Default for UndergroundConfig {
+    fn default() -> Self {
+        Self {
+            fill: Block::ROCK,
+            caves: NoiseLayers {
+                layers: [
+                    NoiseConfig::new(6, [-310., 45., 0.]),
+                    NoiseConfig::new(7, [18., -97., 3.]),
+                ],
+                scales: [1., 0.5],
+            },
+            cave_scale: [0.08, 0.08, 0.3],
+            cave_threshold: 0.3,
+            cave_depth: 2,
+            vein_scale: [0.15, 0.15, 0.4],
+            ores: vec![
+                OreConfig {
+                    block: Block::DIAMOND,
+                    depth: 3..=i32::MAX,
+                    rarity: 0.45,
+                    vein: NoiseConfig::new(8, [71., 23., 5.]),
+                },
+                OreConfig {
+                    block: Block::RUBY,
+                    depth: 3..=i32::MAX,
+                    rarity: 0.42,
+                    vein: NoiseConfig::new(9, [-55., 140., 9.]),
+                },
+                OreConfig {
+                    block: Block::IRON,
+                    depth: 2..=i32::MAX,
+                    rarity: 0.32,
+                    vein: NoiseConfig::new(10, [260., -33., 1.]),
+                },
+                OreConfig {
+                    block: Block::STONE,
+                    depth: 1..=i32::MAX,
+                    rarity: 0.25,
+                    vein: NoiseConfig::new(11, [-8., -410., 7.]),
+                },
+            ],
+        }
+    }
+}
+/// one ore of the `UndergroundConfig` rarity table
+#[derive(Debug, Clone, PartialEq)]
+pub struct OreConfig {
+    pub block: Block,
+    /// depths below the surface the ore appears at
+    pub depth: RangeInclusive<i32>,
+    /// vein noise value above which the ore appears, higher is rarer
+    pub rarity: f64,
+    pub vein: NoiseConfig<3>,
+}
+impl UndergroundConfig {
+    /// reseeds the cave and ore vein noise for the world `seed`
+    pub fn reseed(&mut self, seed: u32) {
+        self.caves.reseed(seed);
+        for ore in self.ores.iter_mut() {
+            ore.vein.reseed(seed);
+        }
+    }
+    /// returns the ore or fill `Block` at `depth` below the surface of its column
+    pub fn gen_block(&self, x: i32, y: i32, z: i32, depth: i32) -> Block {
+        let pos = [x as f64, y as f64, z as f64];
+        let vein_pos = scale(pos, self.vein_scale);
+        self.ores
+            .iter()
+            .filter(|ore| ore.depth.contains(&depth))
+            .find(|ore| ore.vein.get(vein_pos) > ore.rarity)
+            .map_or(self.fill, |ore| ore.block)
+    }
+    /// returns if a cave is carved at `depth` below the surface of its column
+    pub fn is_cave(&self, x: i32, y: i32, z: i32, depth: i32) -> bool {
+        let pos = [x as f64, y as f64, z as f64];
+        depth >= self.cave_depth
+            && self.caves.get(scale(pos, self.cave_scale)) > self.cave_threshold
+    }
+}
+#[inline(always)]
+fn scale(pos: [f64; 3], scale: [f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| pos[i] * scale[i])
+}
+
+/// blocks up to this height are water where there is no ground
+pub const WATER_LEVEL: i32 = 1;
+
+/// biome and terrain height of one block column
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OverWorldColumn {
+    /// index into `OverWorldGenerator::biomes`
+    pub biome: usize,
+    /// terrain height blended between the surrounding biomes
+    pub height: f64,
+    /// height of the water surface in seas, rivers and lakes
+    pub water: Option<i32>,
+    /// next to water, the surface becomes the shore block of the biome
+    pub shore: bool,
+}
+/// columns of a chunk, computed once instead of for every block
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverWorldChunkData {
+    pub columns: Vec<OverWorldColumn>,
+}
+impl OverWorldChunkData {
+    #[inline(always)]
+    pub fn column(&self, x: i32, y: i32) -> OverWorldColumn {
+        let x = x.rem_euclid(CHUNK_SIZE as i32) as usize;
+        let y = y.rem_euclid(CHUNK_SIZE as i32) as usize;
+        self.columns[y * CHUNK_SIZE + x]
+    }
+}
+impl OverWorldGenerator {
+    /// highest ground block of a column with the terrain `height`
+    #[inline(always)]
+    pub fn ground_top(height: f64) -> Option<i32> {
+        if height > 0.6 {
+            // mountains keep rising and reach into the chunks above z 0
+            Some(4 + ((height - 0.6) * 10.0) as i32)
+        } else if height > 0.4 {
+            Some(3)
+        } else if height > 0.2 {
+            Some(2)
+        } else if height > 0.0 {
+            Some(1)
+        } else {
+            None
+        }
+    }
+    /// weight of every biome at the column, they add up to 1
+    pub fn biome_weights(&self, x: i32, y: i32) -> Vec<f64> {
+        let pos = [x as f64 * self.climate_scale, y as f64 * self.climate_scale];
+        let temperature = self.temperature.get(pos);
+        let moisture = self.moisture.get(pos);
+        let mut weights: Vec<f64> = self
+            .biomes
+            .iter()
+            .map(|biome| {
+                let dist =
+                    (biome.temperature - temperature).powi(2) + (biome.moisture - moisture).powi(2);
+                (dist + 1e-9).powf(-self.biome_sharpness)
+            })
+            .collect();
+        let sum: f64 = weights.iter().sum();
+        for weight in weights.iter_mut() {
+            *weight /= sum;
+        }
+        weights
+    }
+    /// terrain height blended by the biome `weights` with river valleys carved in
+    pub fn height(&self, x: i32, y: i32, weights: &[f64]) -> f64 {
+        let base = match &self.terrain {
+            Some(terrain) => terrain.get([x as f64, y as f64]),
+            None => self.block_height.get([x as f64 * 0.01, y as f64 * 0.01]),
+        };
+        let height: f64 = self
+            .biomes
+            .iter()
+            .zip(weights)
+            .map(|(biome, weight)| (base * biome.height_scale + biome.height_offset) * weight)
+            .sum();
+        // rivers follow the ridges of the noise, so they never end at a chunk border
+        let ridge = 1.0
+            - self
+                .rivers
+                .get([x as f64 * self.river_scale, y as f64 * self.river_scale])
+                .abs();
+        let t = ((ridge - (1.0 - self.river_width)) / self.river_width).clamp(0.0, 1.0);
+        let valley = t * t * (3.0 - 2.0 * t);
+        height * (1.0 - valley) + self.river_bed * valley
+    }
+    /// returns if the ground of the column is lower than all ground around it
+    pub fn is_basin(&self, x: i32, y: i32, top: i32) -> bool {
+        (0..8).all(|i| {
+            let angle = i as f64 * std::f64::consts::FRAC_PI_4;
+            let sx = x + (angle.cos() * self.lake_radius).round() as i32;
+            let sy = y + (angle.sin() * self.lake_radius).round() as i32;
+            let height = self.height(sx, sy, &self.biome_weights(sx, sy));
+            Self::ground_top(height).is_some_and(|rim| rim > top)
+        })
+    }
+    /// blends the terrain height between the biomes and picks the biome of the
+    /// column randomly by weight, so borders fray per block instead of per chunk
+    pub fn column(&self, x: i32, y: i32) -> OverWorldColumn {
+        let weights = self.biome_weights(x, y);
+        let mut column = OverWorldColumn {
+            height: self.height(x, y, &weights),
+            ..Default::default()
+        };
+        let mut pick = dither(x, y, self.seed());
+        for (idx, weight) in weights.into_iter().enumerate() {
+            if pick >= 0.0 {
+                column.biome = idx;
+                pick -= weight;
+            }
+        }
+        column.water = match Self::ground_top(column.height) {
+            None => Some(WATER_LEVEL),
+            // lakes fill local minima one block deep
+            Some(top) if top < 4 && self.is_basin(x, y, top) => Some(top + 1),
+            Some(_) => None,
+        };
+        column
+    }
+}
+/// deterministic value in `0..1` for a column
+#[inline(always)]
+fn dither(x: i32, y: i32, seed: u32) -> f64 {
+    let mut h = (x as u32 as u64) | ((y as u32 as u64) << 32);
+    h ^= (seed as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+impl OverWorldGenerator {
+    /// cuts caves into the ground below the surface
+    fn carve(&self, region: &mut ChunkRegion, chunk_data: &OverWorldChunkData) {
+        for z in 0..CHUNK_HEIGHT {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let ctpos = ChunkBlockPos { x, y, z };
+                    let wpos = ctpos.to_world(region.cpos);
+                    let Some(top) = Self::ground_top(chunk_data.column(wpos.x, wpos.y).height)
+                    else {
+                        continue;
+                    };
+                    if wpos.z < top
+                        && self
+                            .underground
+                            .is_cave(wpos.x, wpos.y, wpos.z, top - wpos.z)
+                    {
+                        region.set(ctpos, Block::AIR);
+                    }
+                }
+            }
+        }
+    }
+    /// scatters flora on low ground, wide flora also needs ground under its sides
+    /// which reads the carved blocks of the neighbor chunks at the border
+    fn decorate(&self, region: &mut ChunkRegion, chunk_data: &OverWorldChunkData) {
+        Scatter::new(self, region.cpos, chunk_data).place(region);
+    }
+    /// removes flora whose ground was replaced by a structure and wide flora a
+    /// structure of this or a neighbor chunk grew into
+    fn post_process(&self, region: &mut ChunkRegion) {
+        for z in 1..CHUNK_HEIGHT {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let pos = ChunkBlockPos { x, y, z };
+                    let block = region.get(pos);
+                    if !self.is_flora(block) {
+                        continue;
+                    }
+                    let below = region.get(ChunkBlockPos { z: z - 1, ..pos });
+                    let wpos = pos.to_world(region.cpos);
+                    // scatter keeps the sides of wide flora free, only structures fill them
+                    let crowded = self.is_wide_flora(block)
+                        && (-1..=1).any(|dy| {
+                            (-1..=1).any(|dx| {
+                                (dx, dy) != (0, 0)
+                                    && region
+                                        .get_block(WorldBlockPos {
+                                            x: wpos.x + dx,
+                                            y: wpos.y + dy,
+                                            ..wpos
+                                        })
+                                        .is_some_and(|side| side != Block::AIR)
+                            })
+                        });
+                    if below == Block::AIR || below == Block::WATER || crowded {
+                        region.set(pos, Block::AIR);
+                    }
+                }
+            }
+        }
+    }
+    /// returns if `block` is scattered as flora
+    #[inline(always)]
+    pub fn is_flora(&self, block: Block) -> bool {
+        self.scatter.iter().any(|rule| rule.block == block)
+    }
+    /// returns if `block` is scattered as flora that needs its sides free
+    #[inline(always)]
+    pub fn is_wide_flora(&self, block: Block) -> bool {
+        self.scatter
+            .iter()
+            .any(|rule| rule.wide && rule.block == block)
+    }
 }
 impl TerrainGenerator for OverWorldGenerator {
-    type ChunkData = OverWorldBiom;
-    fn get_chunk_data(&self, _cpos: ChunkPos, _perlin: &Fbm<Perlin>) -> Self::ChunkData {
-        OverWorldBiom::Plains
+    type ChunkData = OverWorldChunkData;
+    fn seed(&self) -> u32 {
+        self.seed
+    }
+    fn set_seed(&mut self, seed: u32) {
+        self.seed = seed;
+        self.block_height.reseed(seed);
+        if let Some(terrain) = self.terrain.as_mut() {
+            terrain.reseed(seed);
+        }
+        self.plants.reseed(seed);
+        self.temperature.reseed(seed);
+        self.moisture.reseed(seed);
+        self.rivers.reseed(seed);
+        self.underground.reseed(seed);
+    }
+    fn stage_radius(&self, stage: GenStage) -> i32 {
+        match stage {
+            // wide flora and the structures around it look across the chunk border
+            GenStage::Decoration | GenStage::PostProcess => 1,
+            _ => 0,
+        }
+    }
+    fn gen_stage(&self, stage: GenStage, region: &mut ChunkRegion, chunk_data: &Self::ChunkData) {
+        match stage {
+            GenStage::Terrain => self.gen_terrain(region, chunk_data),
+            GenStage::Carving => self.carve(region, chunk_data),
+            GenStage::Decoration => self.decorate(region, chunk_data),
+            GenStage::Structures => self.gen_structures(region),
+            GenStage::PostProcess => self.post_process(region),
+        }
+    }
+    fn get_chunk_data(&self, cpos: ChunkPos) -> Self::ChunkData {
+        // one extra column around the chunk to find shores at its border
+        const PADDED: usize = CHUNK_SIZE + 2;
+        let origin = ChunkBlockPos { x: 0, y: 0, z: 0 }.to_world(cpos);
+        let padded: Vec<OverWorldColumn> = (0..PADDED * PADDED)
+            .map(|idx| {
+                let x = origin.x + (idx % PADDED) as i32 - 1;
+                let y = origin.y + (idx / PADDED) as i32 - 1;
+                self.column(x, y)
+            })
+            .collect();
+        let mut columns = Vec::with_capacity(CHUNK_SIZE * CHUNK_SIZE);
+        for y in 1..=CHUNK_SIZE {
+            for x in 1..=CHUNK_SIZE {
+                let mut column = padded[y * PADDED + x];
+                column.shore = column.water.is_none()
+                    && (y - 1..=y + 1)
+                        .flat_map(|ny| (x - 1..=x + 1).map(move |nx| ny * PADDED + nx))
+                        .any(|idx| padded[idx].water.is_some());
+                columns.push(column);
+            }
+        }
+        OverWorldChunkData { columns }
     }
     fn gen_block(
         &self,
         WorldBlockPos { x, y, z }: WorldBlockPos,
-        perlin: &Fbm<Perlin>,
         chunk_data: &Self::ChunkData,
     ) -> Block {
-        // generator.rs -> gen_block(...)
-        let height = self
-            .block_height
-            .get([x as f64 * 0.01, y as f64 * 0.01], perlin);
-        let plants = self.plants.get([x as f64, y as f64], perlin);
+        let OverWorldColumn {
+            biome,
+            height,
+            water,
+            shore,
+        } = chunk_data.column(x, y);
+        let biome = &self.biomes[biome];
+        let top = Self::ground_top(height);
+        // everything covered by ground is underground
+        if let Some(top) = top
+            && z < top
+        {
+            return self.underground.gen_block(x, y, z, top - z);
+        }
+        if let Some(level) = water {
+            // the ground of seas and rivers ends at z 0
+            if top.is_none() && z < 0 {
+                return self.underground.gen_block(x, y, z, -z);
+            }
+            // sand below the water, seas and rivers have no ground column
+            return if z <= top.unwrap_or(level - 1) {
+                biome.shore
+            } else if z <= level {
+                Block::WATER
+            } else {
+                Block::default()
+            };
+        }
+        let surface = if shore { biome.shore } else { biome.surface };
 
-        match chunk_data {
-            OverWorldBiom::Plains => {
-                if z == 4 {
-                    if height > 0.6 {
-                        return Block::Rock;
-                    }
-                    return Block::default();
-                }
-                if z == 3 {
-                    if height > 0.4 {
-                        return Block::Rock;
-                    } else if height > 0.2 {
-                        if plants > 0.2 {
-                            return Block::Tree;
-                        } else if plants > 0.15 {
-                            return Block::BerryBush;
-                        } else if plants > 0.1 {
-                            return Block::Bush;
-                        }
-                    }
-                    return Block::default();
-                }
-                if z == 2 {
-                    if height > 0.2 {
-                        return Block::Grass;
-                    } else if height > 0.05 {
-                        if plants > 0.2 {
-                            return Block::Tree;
-                        } else if plants > 0.15 {
-                            return Block::BerryBush;
-                        } else if plants > 0.1 {
-                            return Block::Bush;
-                        }
-                    }
-                    return Block::default();
-                }
-                // surface layer handling
-                if z == 1 {
-                    if height > 0.05 {
-                        return Block::Grass;
-                    } else if height > 0.0 {
-                        return Block::Sand;
-                    } else {
-                        return Block::default();
-                    }
-                }
+        // mountains are capped with the hill block at any height
+        if z >= 4 {
+            if top == Some(z) {
+                return biome.hill;
+            }
+            return Block::default();
+        }
+        if z == 3 {
+            if height > 0.4 {
+                return biome.hill;
+            }
+            return Block::default();
+        }
+        if z == 2 {
+            if height > 0.2 {
+                return surface;
+            }
+            return Block::default();
+        }
+        // surface and lower layers
+        if z <= 1 {
+            if height > 0.05 {
+                return surface;
+            } else if height > 0.0 {
+                return biome.shore;
+            }
+        }
 
-                // lower layers
-                if z < 1 {
-                    if height > 0.05 {
-                        return Block::Grass;
-                    }
-                    if height > 0.0 {
-                        return Block::Sand;
-                    }
-                }
+        Block::default()
+    }
+    fn ground_height(&self, x: i32, y: i32) -> Option<i32> {
+        let column = self.column(x, y);
+        if column.water.is_some() {
+            return None;
+        }
+        Self::ground_top(column.height).map(|top| top + 1)
+    }
+    fn structures(&self) -> Option<&StructureSet> {
+        Some(&self.structures)
+    }
+}
 
-                Block::default()
-            }
+/// a dimension of cave corridors between rock walls
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaveGenerator {
+    /// the cave noise decides between corridors and walls
+    pub underground: UndergroundConfig,
+    /// highest z of the walls
+    pub wall_height: i32,
+    /// chance of a corridor block to hold a portal
+    pub portal_chance: f64,
+    /// set with `TerrainGenerator::set_seed`
+    pub seed: u32,
+}
+impl Default for CaveGenerator {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            underground: UndergroundConfig {
+                // about half of the columns are corridors
+                cave_threshold: 0.0,
+                cave_depth: 0,
+                ..Default::default()
+            },
+            wall_height: 3,
+            portal_chance: 0.002,
         }
     }
 }
+impl CaveGenerator {
+    /// returns if the column is a corridor, the same for every z so walls are straight
+    #[inline(always)]
+    pub fn is_corridor(&self, x: i32, y: i32) -> bool {
+        self.underground
+            .is_cave(x, y, 1, self.underground.cave_depth)
+    }
+}
+impl TerrainGenerator for CaveGenerator {
+    type ChunkData = ();
+    fn seed(&self) -> u32 {
+        self.seed
+    }
+    fn set_seed(&mut self, seed: u32) {
+        self.seed = seed;
+        self.underground.reseed(seed);
+    }
+    fn get_chunk_data(&self, _cpos: ChunkPos) -> Self::ChunkData {}
+    fn gen_block(
+        &self,
+        WorldBlockPos { x, y, z }: WorldBlockPos,
+        _chunk_data: &Self::ChunkData,
+    ) -> Block {
+        if z > self.wall_height {
+            return Block::AIR;
+        }
+        // ores get richer towards the floor
+        let depth = self.wall_height + 1 - z;
+        if z <= 0 || !self.is_corridor(x, y) {
+            return self.underground.gen_block(x, y, z, depth);
+        }
+        if z == 1 && dither(x, y, self.seed()) < self.portal_chance {
+            return Block::PORTAL;
+        }
+        Block::AIR
+    }
+    fn ground_height(&self, x: i32, y: i32) -> Option<i32> {
+        self.is_corridor(x, y).then_some(1)
+    }
+}
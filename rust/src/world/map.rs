@@ -1,42 +1,89 @@
 use crate::{
-    GameData,
-    world::generator::{OverWorldGenerator, TerrainGenerator},
+    GameData, GameEvent,
+    world::{
+        chunk_blocks::ChunkBlocks,
+        generator::TerrainGenerator,
+        palette::BlockPalette,
+        pipeline::{ChunkError, ChunkGeneration, ChunkWorkers, default_threads},
+        storage::{RegionPos, RegionStore},
+    },
 };
 
 use super::units::*;
-use crossbeam_channel::{Receiver, Sender, bounded};
 use hecs::World;
-use noise::{Fbm, Perlin};
 use raylib::prelude::*;
 use rayon::prelude::*;
-use rustc_hash::{FxBuildHasher, FxHashMap};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::Deserialize;
 use std::{
     collections::HashMap,
     fmt::Debug,
+    io,
+    ops::{Range, RangeInclusive},
     sync::Arc,
-    thread::{self, JoinHandle},
+    thread,
+    time::{Duration, Instant},
 };
 
-// ... add these types for thread communication
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct ChunkRequest(ChunkPos);
+/// seconds between writing dirty chunks to `storage`
+pub const AUTOSAVE_INTERVAL: f32 = 30.0;
+/// seconds between checks if chunks have to be unloaded
+pub const UNLOAD_INTERVAL: f32 = 1.0;
+/// chunks kept loaded around the chunks in view in every direction
+pub const KEEP_RADIUS: i32 = 2;
+/// bytes of loaded chunks above which chunks away from every loader are unloaded
+pub const MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+/// seconds between updates of maps that are not active
+pub const BACKGROUND_TICK: f32 = 0.5;
+/// name of the map the game starts in
+pub const OVERWORLD: &str = "overworld";
+/// name of the cave dimension below the overworld
+pub const UNDERGROUND: &str = "underground";
+/// chunks around the origin searched for a spawn point in every direction
+pub const SPAWN_SEARCH_RADIUS: i32 = 8;
+/// blocks around the arrival of a portal searched for a place to stand in every direction
+pub const PORTAL_SEARCH_RADIUS: i32 = 8;
+/// longest time `require_chunk` and `require_area` wait for the generator threads
+pub const REQUIRE_TIMEOUT: Duration = Duration::from_secs(10);
 
-#[derive(Debug)]
-pub struct ChunkResponse {
-    pub pos: ChunkPos,
-    pub chunk: Chunk,
-}
-
-/// holds static `BlockData` for every `Block`
-#[derive(Debug, Clone)]
+/// holds static `BlockData` for every `Block` indexed by its id
+#[derive(Debug, Clone, Default)]
 pub struct BlockSet {
-    pub data: Vec<BlockData>,
+    pub data: Vec<Option<BlockData>>,
 }
-/// holds the atlas position and the kind of block
+/// holds the name, atlas position, kind and flags of a block
 #[derive(Debug, Clone)]
 pub struct BlockData {
+    /// stable name used in save files
+    pub name: String,
     pub atlas_pos: AtlasPos,
     pub kind: BlockKind,
+    pub flags: Vec<BlockFlag>,
+    pub animation: Option<BlockAnimation>,
+    /// color of the block on exported maps
+    pub color: Color,
+    /// multiplied with the atlas tile when the block is drawn
+    pub tint: Color,
+}
+/// cycles the atlas position of a block through frames placed `step` apart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockAnimation {
+    pub frames: u8,
+    pub step: AtlasPos,
+    pub fps: f32,
+}
+/// gameplay properties of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockFlag {
+    /// ground that can be walked on and built on
+    Solid,
+    /// can be overwritten by generated features
+    Replaceable,
+    /// water and other fluids
+    Liquid,
+    /// moves bodies to the map of the `BlockMap` portal
+    Portal,
 }
 /// defines the kind of block
 #[derive(Clone)]
@@ -46,15 +93,42 @@ pub enum BlockKind {
 }
 /// universal autotiling function shared pointer
 pub type AutoBlockFn = Arc<dyn Fn(Neighbors) -> AtlasPos + Send + Sync>;
+/// stores a dirty chunk before it is unloaded
+pub type PersistFn = Box<dyn FnMut(ChunkPos, &Chunk) -> io::Result<()> + Send + Sync>;
 /// holds the chunks and a `BlockSet`
 pub struct BlockMap {
+    /// unique name entities use to refer to the map
+    pub name: String,
     pub seed: u32,
     pub blockset: BlockSet,
     pub chunks: FxHashMap<ChunkPos, Chunk>,
-    pub generators: Vec<JoinHandle<()>>,
-    pub request_tx: Sender<ChunkRequest>,
-    pub response_rx: Receiver<ChunkResponse>,
+    /// generator threads of any `TerrainGenerator`
+    pub generation: Box<dyn ChunkGeneration>,
+    /// chunks whose generation failed since the last update, turned into `GameEvent`s
+    pub errors: Vec<ChunkError>,
+    /// map that portal blocks lead to
+    pub portal: Option<String>,
     pub last_view: (ChunkPos, ChunkPos),
+    /// chunks in view while the map is active
+    pub view: Option<(ChunkPos, ChunkPos)>,
+    /// chunk and radius of every `ChunkLoader` in the map
+    pub loaders: Vec<(ChunkPos, i32)>,
+    /// chunks kept loaded around `view` in every direction
+    pub keep_radius: i32,
+    /// bytes of chunks above which the least recently used ones are unloaded
+    pub memory_budget: usize,
+    /// stores dirty chunks before they are unloaded instead of `storage`
+    pub persist: Option<PersistFn>,
+    /// seconds since chunks were last checked for unloading
+    pub unload_timer: f32,
+    /// `ChunkPos::z` of the stacked chunks that are loaded and drawn in view
+    pub levels: RangeInclusive<i32>,
+    pub storage: Option<RegionStore>,
+    pub autosave: f32,
+    /// seconds since the map was created, drives block animations
+    pub time: f32,
+    /// seconds since the last update while the map is not active
+    pub background: f32,
 }
 #[derive(Debug, Default)]
 pub struct BlockMapDrawBuffer {
@@ -64,135 +138,72 @@ pub struct BlockMapDrawBuffer {
 /// holds blocks and their cashed `BlockNeighbors`
 #[derive(Debug)]
 pub struct Chunk {
-    blocks: [Block; CHUNK_VOLUME],
-    neighbors: [BlockNeighbors; CHUNK_VOLUME],
+    blocks: ChunkBlocks,
+    /// only allocated once the neighbors were computed
+    neighbors: Option<Box<[BlockNeighbors]>>,
+    /// blocks changed since the chunk was last stored
+    pub dirty: bool,
+    /// `BlockMap::time` the chunk was last kept by a loader, the oldest are unloaded first
+    pub last_used: f32,
 }
-/// represents a block type
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-#[repr(u8)]
-pub enum Block {
-    #[default]
-    Air,
-    Grass,
-    Rock,
-    Sand,
-    Tree,
-    Bush,
-    BerryBush,
-    Flower,
-    Mushroom,
-    Stone,
-    Iron,
-    Diamond,
-    Ruby,
+/// represents a block type by its id in the `BlockSet`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Block(pub u8);
+impl Block {
+    pub const AIR: Block = Block(0);
+    pub const GRASS: Block = Block(1);
+    pub const ROCK: Block = Block(2);
+    pub const SAND: Block = Block(3);
+    pub const TREE: Block = Block(4);
+    pub const BUSH: Block = Block(5);
+    pub const BERRY_BUSH: Block = Block(6);
+    pub const FLOWER: Block = Block(7);
+    pub const MUSHROOM: Block = Block(8);
+    pub const STONE: Block = Block(9);
+    pub const IRON: Block = Block(10);
+    pub const DIAMOND: Block = Block(11);
+    pub const RUBY: Block = Block(12);
+    /// placeholder for blocks that no longer exist
+    pub const UNKNOWN: Block = Block(13);
+    pub const WATER: Block = Block(14);
+    pub const PORTAL: Block = Block(15);
+    /// names of the blocks the game refers to by id, other blocks get the ids after them
+    pub const BUILTIN: [&'static str; 16] = [
+        "air",
+        "grass",
+        "rock",
+        "sand",
+        "tree",
+        "bush",
+        "berry_bush",
+        "flower",
+        "mushroom",
+        "stone",
+        "iron",
+        "diamond",
+        "ruby",
+        "unknown",
+        "water",
+        "portal",
+    ];
+    /// returns the built-in `Block` with the stable `name`
+    pub fn builtin(name: &str) -> Option<Block> {
+        Self::BUILTIN
+            .iter()
+            .position(|builtin| *builtin == name)
+            .map(|id| Block(id as u8))
+    }
 }
 impl From<Block> for u8 {
+    #[inline(always)]
     fn from(val: Block) -> Self {
-        match val {
-            Block::Air => 0,
-            Block::Grass => 1,
-            Block::Rock => 2,
-            Block::Sand => 3,
-            Block::Tree => 4,
-            Block::Bush => 5,
-            Block::BerryBush => 6,
-            Block::Flower => 7,
-            Block::Mushroom => 8,
-            Block::Stone => 9,
-            Block::Iron => 10,
-            Block::Diamond => 11,
-            Block::Ruby => 12,
-        }
-    }
-}
-impl TryInto<Block> for u8 {
-    type Error = ();
-    fn try_into(self) -> Result<Block, Self::Error> {
-        match self {
-            0 => Ok(Block::Air),
-            1 => Ok(Block::Grass),
-            2 => Ok(Block::Rock),
-            3 => Ok(Block::Sand),
-            4 => Ok(Block::Tree),
-            5 => Ok(Block::Bush),
-            6 => Ok(Block::BerryBush),
-            7 => Ok(Block::Flower),
-            8 => Ok(Block::Mushroom),
-            9 => Ok(Block::Stone),
-            10 => Ok(Block::Iron),
-            11 => Ok(Block::Diamond),
-            12 => Ok(Block::Ruby),
-            _ => Err(()),
-        }
+        val.0
     }
 }
-impl BlockSet {
-    pub fn normal() -> Self {
-        let at47_arc = Arc::new(at47) as AutoBlockFn;
-        BlockSet {
-            data: vec![
-                // GRASS
-                BlockData {
-                    atlas_pos: (0, 0).into(),
-                    kind: BlockKind::Block(at47_arc.clone()),
-                },
-                // ROCK
-                BlockData {
-                    atlas_pos: (0, 12).into(),
-                    kind: BlockKind::Block(at47_arc.clone()),
-                },
-                // SAND
-                BlockData {
-                    atlas_pos: (0, 24).into(),
-                    kind: BlockKind::Block(at47_arc.clone()),
-                },
-                // TREE
-                BlockData {
-                    atlas_pos: (8, 2).into(),
-                    kind: BlockKind::Prop,
-                },
-                // BUSH
-                BlockData {
-                    atlas_pos: (7, 3).into(),
-                    kind: BlockKind::Prop,
-                },
-                // BERRY BUSH
-                BlockData {
-                    atlas_pos: (8, 3).into(),
-                    kind: BlockKind::Prop,
-                },
-                // FLOWER
-                BlockData {
-                    atlas_pos: (7, 4).into(),
-                    kind: BlockKind::Prop,
-                },
-                // MUSHROOM
-                BlockData {
-                    atlas_pos: (8, 4).into(),
-                    kind: BlockKind::Prop,
-                },
-                // STONE
-                BlockData {
-                    atlas_pos: (8, 14).into(),
-                    kind: BlockKind::Prop,
-                },
-                // IRON
-                BlockData {
-                    atlas_pos: (7, 15).into(),
-                    kind: BlockKind::Prop,
-                },
-                // DIAMOND
-                BlockData {
-                    atlas_pos: (8, 15).into(),
-                    kind: BlockKind::Prop,
-                },
-                // RUBY
-                BlockData {
-                    atlas_pos: (7, 16).into(),
-                    kind: BlockKind::Prop,
-                },
-            ],
-        }
+impl From<u8> for Block {
+    #[inline(always)]
+    fn from(id: u8) -> Self {
+        Block(id)
     }
 }
 /// represents the 8 surrounding neighbors
@@ -204,16 +215,18 @@ impl Chunk {
     #[inline(always)]
     pub fn new_empty() -> Self {
         Self {
-            blocks: [Block::default(); CHUNK_VOLUME],
-            neighbors: [(0, 0); CHUNK_VOLUME],
+            blocks: ChunkBlocks::default(),
+            neighbors: None,
+            dirty: false,
+            last_used: 0.0,
         }
     }
 
     /// returns `Block` at `ChunkBlockPos`
     #[inline(always)]
     pub fn get(&self, pos: ChunkBlockPos) -> Option<Block> {
-        if pos.x < CHUNK_SIZE && pos.y < CHUNK_SIZE && pos.z < CHUNK_SIZE {
-            Some(self.blocks.get(pos.idx()).copied().unwrap_or_default())
+        if pos.x < CHUNK_SIZE && pos.y < CHUNK_SIZE && pos.z < CHUNK_HEIGHT {
+            Some(self.blocks.get(pos.idx()))
         } else {
             None
         }
@@ -222,8 +235,13 @@ impl Chunk {
     /// returns `BlockNeighbors` at `ChunkBlockPos`
     #[inline(always)]
     pub fn get_neighbors(&self, pos: ChunkBlockPos) -> Option<BlockNeighbors> {
-        if pos.x < CHUNK_SIZE && pos.y < CHUNK_SIZE && pos.z < CHUNK_SIZE {
-            Some(self.neighbors.get(pos.idx()).copied().unwrap_or_default())
+        if pos.x < CHUNK_SIZE && pos.y < CHUNK_SIZE && pos.z < CHUNK_HEIGHT {
+            Some(
+                self.neighbors
+                    .as_ref()
+                    .and_then(|neighbors| neighbors.get(pos.idx()).copied())
+                    .unwrap_or_default(),
+            )
         } else {
             None
         }
@@ -232,31 +250,120 @@ impl Chunk {
     /// sets `Block` at `ChunkBlockPos`
     #[inline(always)]
     pub fn set(&mut self, pos: ChunkBlockPos, block: Block) {
-        if pos.x < CHUNK_SIZE && pos.y < CHUNK_SIZE && pos.z < CHUNK_SIZE {
-            let Some(b) = self.blocks.get_mut(pos.idx()) else {
-                return;
-            };
-            *b = block
+        if pos.x < CHUNK_SIZE
+            && pos.y < CHUNK_SIZE
+            && pos.z < CHUNK_HEIGHT
+            && self.blocks.set(pos.idx(), block)
+        {
+            self.dirty = true;
         }
     }
 
     /// sets `BlockNeighbors` at `ChunkBlockPos`
     #[inline(always)]
     pub fn set_neighbors(&mut self, pos: ChunkBlockPos, neighbors: BlockNeighbors) {
-        if pos.x < CHUNK_SIZE && pos.y < CHUNK_SIZE && pos.z < CHUNK_SIZE {
+        if pos.x < CHUNK_SIZE && pos.y < CHUNK_SIZE && pos.z < CHUNK_HEIGHT {
             let idx = pos.idx();
-            unsafe { *self.neighbors.get_unchecked_mut(idx) = neighbors }
+            let all = self
+                .neighbors
+                .get_or_insert_with(|| vec![(0, 0); CHUNK_VOLUME].into_boxed_slice());
+            unsafe { *all.get_unchecked_mut(idx) = neighbors }
         }
     }
+
+    /// returns the blocks of the chunk
+    #[inline(always)]
+    pub fn blocks(&self) -> &ChunkBlocks {
+        &self.blocks
+    }
+    /// replaces the blocks of the chunk
+    #[inline(always)]
+    pub fn set_blocks(&mut self, blocks: ChunkBlocks) {
+        if self.blocks != blocks {
+            self.blocks = blocks;
+            self.dirty = true;
+        }
+    }
+    /// approximate memory used by the chunk in bytes
+    pub fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>() - std::mem::size_of::<ChunkBlocks>()
+            + self.blocks.mem_size()
+            + self
+                .neighbors
+                .as_ref()
+                .map_or(0, |neighbors| std::mem::size_of_val(&**neighbors))
+    }
 }
 
 impl BlockSet {
     /// get the data for any `Block`
     pub fn get_data(&self, gid: Block) -> Option<&BlockData> {
-        if gid == Block::default() {
-            return None;
+        self.data.get(u8::from(gid) as usize)?.as_ref()
+    }
+    /// sets the `BlockData` of `Block`
+    pub fn insert(&mut self, block: Block, data: BlockData) {
+        let idx = u8::from(block) as usize;
+        if self.data.len() <= idx {
+            self.data.resize(idx + 1, None);
+        }
+        self.data[idx] = Some(data);
+    }
+    /// returns the stable name of `Block`
+    pub fn name(&self, block: Block) -> &str {
+        match self.get_data(block) {
+            Some(data) => &data.name,
+            None => Block::BUILTIN
+                .get(u8::from(block) as usize)
+                .copied()
+                .unwrap_or("unknown"),
+        }
+    }
+    /// returns the `Block` with the stable `name`
+    pub fn block(&self, name: &str) -> Option<Block> {
+        Block::builtin(name).or_else(|| {
+            self.data
+                .iter()
+                .position(|data| data.as_ref().is_some_and(|data| data.name == name))
+                .map(|id| Block(id as u8))
+        })
+    }
+    /// returns if `Block` has `BlockFlag`
+    #[inline(always)]
+    pub fn has_flag(&self, block: Block, flag: BlockFlag) -> bool {
+        self.get_data(block)
+            .is_some_and(|data| data.flags.contains(&flag))
+    }
+    /// palette of the block names by id, used to save blocks
+    pub fn palette(&self) -> BlockPalette {
+        BlockPalette {
+            names: (0..self.data.len().max(Block::BUILTIN.len()))
+                .map(|id| self.name(Block(id as u8)).to_string())
+                .collect(),
+        }
+    }
+}
+
+impl BlockData {
+    /// returns the atlas position of the animation frame at `time`
+    #[inline(always)]
+    pub fn atlas_pos_at(&self, time: f32) -> AtlasPos {
+        match self.animation {
+            Some(BlockAnimation { frames, step, fps }) if frames > 1 => {
+                let frame = ((time * fps) as u32 % frames as u32) as u8;
+                // checked when loading, saturate anyway for hand built data
+                AtlasPos {
+                    x: self
+                        .atlas_pos
+                        .x
+                        .saturating_add(step.x.saturating_mul(frame)),
+                    y: self
+                        .atlas_pos
+                        .y
+                        .saturating_add(step.y.saturating_mul(frame)),
+                }
+            }
+            _ => self.atlas_pos,
         }
-        self.data.get((Into::<u8>::into(gid) - 1) as usize)
     }
 }
 
@@ -271,47 +378,107 @@ impl BlockMapDrawBuffer {
 }
 
 impl BlockMap {
-    pub fn new(blockset: BlockSet, generator: OverWorldGenerator, seed: u32) -> Self {
-        // threaded chunk generation channels
-        let (request_tx, request_rx): (Sender<ChunkRequest>, Receiver<ChunkRequest>) = bounded(64);
-        let (response_tx, response_rx): (Sender<ChunkResponse>, Receiver<ChunkResponse>) =
-            bounded(64);
-
-        // shared generator
-        let generator = Arc::new(generator);
-
-        // spawn generator threads with channels
-        let mut generators = Vec::with_capacity(8);
-        for _ in 0..8 {
-            let request_rx = request_rx.clone();
-            let response_tx = response_tx.clone();
-            let generator = generator.clone();
-
-            let handle = thread::spawn(move || {
-                let perlin = Fbm::<Perlin>::new(seed);
-                while let Ok(ChunkRequest(pos)) = request_rx.recv() {
-                    let generator = generator.clone();
-                    let chunk = generator.gen_chunk(pos, &perlin);
-                    let _ = response_tx.send(ChunkResponse { pos, chunk });
-                }
-            });
-            generators.push(handle);
-        }
-
-        // drop unused channel references
-        drop((request_rx, response_tx));
-
+    /// hosts the chunks of `generator` reseeded for the world `seed`
+    pub fn new<G>(blockset: BlockSet, mut generator: G, seed: u32) -> Self
+    where
+        G: TerrainGenerator + Send + Sync + 'static,
+        G::ChunkData: 'static,
+    {
+        generator.set_seed(seed);
         Self {
+            name: OVERWORLD.to_string(),
             blockset,
-            chunks: FxHashMap::with_capacity_and_hasher(0xffff, FxBuildHasher),
-            generators,
+            chunks: FxHashMap::default(),
+            generation: Box::new(ChunkWorkers::spawn(generator, default_threads())),
+            errors: Vec::new(),
+            portal: None,
             seed,
-            request_tx,
-            response_rx,
             last_view: (ChunkPos::default(), ChunkPos::default()),
+            view: None,
+            loaders: Vec::new(),
+            keep_radius: KEEP_RADIUS,
+            memory_budget: MEMORY_BUDGET,
+            persist: None,
+            unload_timer: 0.0,
+            levels: 0..=0,
+            storage: None,
+            autosave: 0.0,
+            time: 0.0,
+            background: 0.0,
+        }
+    }
+
+    /// names the map, the first map is called `OVERWORLD`
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// portal blocks move bodies to the map called `name`
+    pub fn with_portal(mut self, name: impl Into<String>) -> Self {
+        self.portal = Some(name.into());
+        self
+    }
+
+    /// loads and draws the stacked chunks of `levels` instead of only the ones at z 0
+    pub fn with_levels(mut self, levels: RangeInclusive<i32>) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    /// runs `threads` generator threads instead of one per core
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.generation.set_threads(threads);
+        self
+    }
+
+    /// keeps the chunks within `keep_radius` of the view and unloads others once the
+    /// chunks use more than `memory_budget` bytes
+    pub fn with_budget(mut self, keep_radius: i32, memory_budget: usize) -> Self {
+        self.keep_radius = keep_radius;
+        self.memory_budget = memory_budget;
+        self
+    }
+
+    /// hands dirty chunks to `persist` when they are unloaded instead of `storage`
+    pub fn with_persist(
+        mut self,
+        persist: impl FnMut(ChunkPos, &Chunk) -> io::Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.persist = Some(Box::new(persist));
+        self
+    }
+
+    /// stores modified chunks in `storage` and loads chunks from it before generating them
+    pub fn with_storage(mut self, storage: RegionStore) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// loads `Chunk` at `ChunkPos` from `storage` if it was stored before
+    pub fn load_chunk(&mut self, cpos: ChunkPos) -> Option<Chunk> {
+        let storage = self.storage.as_mut()?;
+        match storage.load_chunk(cpos) {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                eprintln!("failed to load chunk {cpos:?}: {err}");
+                None
+            }
         }
     }
 
+    /// writes every dirty `Chunk` to `storage`
+    pub fn save(&mut self) -> std::io::Result<()> {
+        let Some(storage) = self.storage.as_mut() else {
+            return Ok(());
+        };
+        for (cpos, chunk) in self.chunks.iter_mut().filter(|(_, chunk)| chunk.dirty) {
+            storage.save_chunk(*cpos, chunk)?;
+            chunk.dirty = false;
+        }
+        storage.flush()
+    }
+
     /// returns `Chunk` at `ChunkPos`
     #[inline(always)]
     pub fn get_chunk(&self, pos: ChunkPos) -> Option<&Chunk> {
@@ -326,10 +493,110 @@ impl BlockMap {
 
     /// set `Chunk` at `ChunkPos`
     #[inline(always)]
-    pub fn set_chunk(&mut self, pos: ChunkPos, chunk: Chunk) {
+    pub fn set_chunk(&mut self, pos: ChunkPos, mut chunk: Chunk) {
+        chunk.last_used = self.time;
         self.chunks.insert(pos, chunk);
     }
 
+    /// chunks between two corners that are kept loaded, around the view on every level
+    /// and around every loader on its level and the ones right below and above it
+    pub fn keep_areas(&self) -> Vec<(ChunkPos, ChunkPos)> {
+        let around = |start: ChunkPos, end: ChunkPos, radius: i32, levels: i32| {
+            (
+                ChunkPos {
+                    x: start.x - radius,
+                    y: start.y - radius,
+                    z: start.z - levels,
+                },
+                ChunkPos {
+                    x: end.x + radius,
+                    y: end.y + radius,
+                    z: end.z + levels,
+                },
+            )
+        };
+        self.view
+            .map(|(start, end)| around(start, end, self.keep_radius, 0))
+            .into_iter()
+            .chain(
+                self.loaders
+                    .iter()
+                    .map(|(cpos, radius)| around(*cpos, *cpos, *radius, 1)),
+            )
+            .collect()
+    }
+
+    /// unloads the least recently used chunks outside of `keep_areas` until the chunks
+    /// and cached regions fit in `memory_budget`, dirty chunks are persisted first and
+    /// kept if that fails or the map has nowhere to persist them
+    pub fn unload(&mut self) {
+        let keep = self.keep_areas();
+        let persistent = self.persist.is_some() || self.storage.is_some();
+        let mut used = self.storage.as_ref().map_or(0, RegionStore::mem_size);
+        let mut unused = Vec::new();
+        for (cpos, chunk) in self.chunks.iter_mut() {
+            used += chunk.mem_size();
+            if in_areas(&keep, *cpos) {
+                chunk.last_used = self.time;
+            } else if !chunk.dirty || persistent {
+                unused.push((chunk.last_used, *cpos));
+            }
+        }
+        if used <= self.memory_budget {
+            return;
+        }
+        unused.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (_, cpos) in unused {
+            if used <= self.memory_budget {
+                break;
+            }
+            let Some(chunk) = self.chunks.remove(&cpos) else {
+                continue;
+            };
+            if chunk.dirty
+                && let Err(err) = self.persist_chunk(cpos, &chunk)
+            {
+                eprintln!("failed to persist chunk {cpos:?}, keeping it loaded: {err}");
+                self.chunks.insert(cpos, chunk);
+                continue;
+            }
+            used -= chunk.mem_size();
+        }
+        // unloaded chunks only move to the region cache until it is written
+        if let Some(storage) = self.storage.as_mut() {
+            if let Err(err) = storage.flush() {
+                eprintln!("failed to write regions: {err}");
+            }
+            let loaded: FxHashSet<RegionPos> =
+                self.chunks.keys().map(|cpos| (*cpos).into()).collect();
+            storage.unload(|rpos| loaded.contains(&rpos));
+        }
+    }
+
+    /// chunks of the view and every loader, their stages are generated first
+    pub fn focus(&self) -> Vec<ChunkPos> {
+        self.view
+            .map(|(start, end)| ChunkPos {
+                x: (start.x + end.x).div_euclid(2),
+                y: (start.y + end.y).div_euclid(2),
+                z: (start.z + end.z).div_euclid(2),
+            })
+            .into_iter()
+            .chain(self.loaders.iter().map(|(cpos, _)| *cpos))
+            .collect()
+    }
+
+    /// hands a dirty chunk to `persist` or `storage`
+    fn persist_chunk(&mut self, cpos: ChunkPos, chunk: &Chunk) -> io::Result<()> {
+        if let Some(persist) = self.persist.as_mut() {
+            persist(cpos, chunk)
+        } else if let Some(storage) = self.storage.as_mut() {
+            storage.save_chunk(cpos, chunk)
+        } else {
+            Err(io::Error::other("the map has no storage"))
+        }
+    }
+
     /// returns `Block` at `WorldBlockPos`
     #[inline(always)]
     pub fn get_block(&self, pos: WorldBlockPos) -> Option<Block> {
@@ -348,27 +615,54 @@ impl BlockMap {
             .and_then(|chunk| chunk.get_neighbors(ct))
     }
 
+    /// world z of every block in the chunks of `levels`
+    #[inline(always)]
+    pub fn heights(&self) -> Range<i32> {
+        self.levels.start() * CHUNK_HEIGHT as i32..(self.levels.end() + 1) * CHUNK_HEIGHT as i32
+    }
+
+    /// returns if any block of the column at `x`, `y` is a portal
+    pub fn has_portal(&self, x: i32, y: i32) -> bool {
+        self.heights().any(|z| {
+            self.get_block(WorldBlockPos { x, y, z })
+                .is_some_and(|block| self.blockset.has_flag(block, BlockFlag::Portal))
+        })
+    }
+
     /// sets `Block` at `WorldBlockPos` and updates surrounding chunks
     #[inline(always)]
     pub fn set_block(&mut self, pos: WorldBlockPos, tile: Block) {
+        let cpos = self.set_block_raw(pos, tile);
+        self.update_chunk_neighbors_around(cpos);
+    }
+
+    /// sets `Block` at `WorldBlockPos` without updating any `BlockNeighbors`,
+    /// returns the `ChunkPos` that has to be updated afterwards, chunks that are not
+    /// loaded are left alone so their stored or generated blocks are never replaced
+    #[inline(always)]
+    pub fn set_block_raw(&mut self, pos: WorldBlockPos, tile: Block) -> ChunkPos {
         let cpos: ChunkPos = pos.into();
         let ctpos: ChunkBlockPos = pos.into();
         if let Some(chunk) = self.chunks.get_mut(&cpos) {
             chunk.set(ctpos, tile);
-        } else {
-            let mut chunk = Chunk::new_empty();
-            chunk.set(ctpos, tile);
-            self.set_chunk(cpos, chunk);
         }
+        cpos
+    }
 
-        for dy in -1..=1 {
-            for dx in -1..=1 {
-                let npos = ChunkPos {
-                    x: cpos.x + dx,
-                    y: cpos.y + dy,
-                };
-                if self.get_chunk(npos).is_some() {
-                    self.update_chunk_neighbors(npos);
+    /// updates the neighbors of the chunk at `ChunkPos` and of the 26 chunks around it,
+    /// walls read the blocks below and tiles the blocks above
+    pub fn update_chunk_neighbors_around(&mut self, cpos: ChunkPos) {
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let npos = ChunkPos {
+                        x: cpos.x + dx,
+                        y: cpos.y + dy,
+                        z: cpos.z + dz,
+                    };
+                    if self.get_chunk(npos).is_some() {
+                        self.update_chunk_neighbors(npos);
+                    }
                 }
             }
         }
@@ -388,7 +682,7 @@ impl BlockMap {
         }
     }
 
-    /// identifies the viewable chunks based on camera target and zoom
+    /// identifies the viewable chunks at z 0 based on camera target and zoom
     #[inline(always)]
     pub fn view_space(cam_world_center: Vector2, cam_zoom: f32) -> (ChunkPos, ChunkPos) {
         let denom = TILE_SIZE as f32 * CHUNK_SIZE as f32;
@@ -404,41 +698,259 @@ impl BlockMap {
             ChunkPos {
                 x: chunk_start_x,
                 y: chunk_start_y,
+                z: 0,
             },
             ChunkPos {
                 x: chunk_end_x,
                 y: chunk_end_y,
+                z: 0,
             },
         )
     }
 
-    /// updates every chunk and generates new ones within `view_space`
-    pub fn update(&mut self, _dt: f32, data: &GameData) {
+    /// updates every chunk and generates new ones within `view_space` on all `levels`
+    pub fn update(&mut self, dt: f32, data: &GameData) {
         // get view space for updating chunks
         let (start, end) = Self::view_space(data.camera.target, data.camera.zoom);
-        // receive new chunks from channel
-        while let Ok(response) = self.response_rx.try_recv() {
-            self.set_chunk(response.pos, response.chunk);
-        }
         // only update neighbors if view space changed
         let update_chunk_neighbors = start != self.last_view.0 || end != self.last_view.1;
+        self.view = Some((
+            level_view(start, end, *self.levels.start()).0,
+            level_view(start, end, *self.levels.end()).1,
+        ));
         // find new chunks to load and update chunk neighbors if necessary
-        for y in start.y..=end.y {
-            for x in start.x..=end.x {
-                let pos = ChunkPos { x, y };
-                if self.get_chunk(pos).is_none() {
-                    let _ = self.request_tx.send(ChunkRequest(pos));
-                } else if update_chunk_neighbors {
-                    self.update_chunk_neighbors(pos);
+        for z in self.levels.clone() {
+            let (start, end) = level_view(start, end, z);
+            for y in start.y..=end.y {
+                for x in start.x..=end.x {
+                    let pos = ChunkPos { x, y, z };
+                    // chunks in generation were already looked up in storage
+                    if self.generation.is_requested(pos) || self.generation.has_failed(pos) {
+                        continue;
+                    }
+                    if self.get_chunk(pos).is_none() {
+                        if let Some(chunk) = self.load_chunk(pos) {
+                            self.set_chunk(pos, chunk);
+                            self.update_chunk_neighbors(pos);
+                        } else {
+                            self.generate_chunk(pos);
+                        }
+                    } else if update_chunk_neighbors {
+                        self.update_chunk_neighbors(pos);
+                    }
+                }
+            }
+        }
+        self.tick(dt);
+    }
+
+    /// receives generated chunks, advances animations and saves periodically,
+    /// maps that are not active only do this every `BACKGROUND_TICK`
+    pub fn tick(&mut self, dt: f32) {
+        // forget chunks that left the view and the loaders, generate the closest first
+        let keep = self.keep_areas();
+        self.generation
+            .retain_requests(&|cpos| in_areas(&keep, cpos));
+        self.generation.set_focus(self.focus());
+        for (pos, chunk) in self.generation.update() {
+            self.set_chunk(pos, chunk);
+        }
+        self.errors.extend(self.generation.errors());
+        self.time += dt;
+        // periodically write modified chunks to disk
+        self.autosave += dt;
+        if self.autosave >= AUTOSAVE_INTERVAL {
+            self.autosave = 0.0;
+            if let Err(err) = self.save() {
+                eprintln!("failed to save chunks: {err}");
+            }
+        }
+        self.unload_timer += dt;
+        if self.unload_timer >= UNLOAD_INTERVAL {
+            self.unload_timer = 0.0;
+            self.unload();
+        }
+    }
+
+    /// loads or generates every chunk between `start` and `end` (inclusive) right away,
+    /// the missing ones are generated at the same time, returns if all of them are
+    /// there before `REQUIRE_TIMEOUT`
+    pub fn require_area(&mut self, start: ChunkPos, end: ChunkPos) -> bool {
+        let mut missing = Vec::new();
+        for z in start.z..=end.z {
+            for y in start.y..=end.y {
+                for x in start.x..=end.x {
+                    let cpos = ChunkPos { x, y, z };
+                    if self.get_chunk(cpos).is_some() {
+                        continue;
+                    }
+                    if let Some(chunk) = self.load_chunk(cpos) {
+                        self.set_chunk(cpos, chunk);
+                        self.update_chunk_neighbors(cpos);
+                    } else {
+                        self.generate_chunk(cpos);
+                        missing.push(cpos);
+                    }
                 }
             }
         }
+        let deadline = Instant::now() + REQUIRE_TIMEOUT;
+        let mut complete = true;
+        for cpos in missing {
+            complete &= self.wait_for_chunk(cpos, deadline);
+        }
+        complete
+    }
+
+    /// loads or generates the `Chunk` at `ChunkPos` right away, blocking until the
+    /// generator threads finished it, failed or `REQUIRE_TIMEOUT` passed,
+    /// returns if the chunk is there
+    pub fn require_chunk(&mut self, cpos: ChunkPos) -> bool {
+        if self.get_chunk(cpos).is_some() {
+            return true;
+        }
+        if let Some(chunk) = self.load_chunk(cpos) {
+            self.set_chunk(cpos, chunk);
+            self.update_chunk_neighbors(cpos);
+            return true;
+        }
+        self.generate_chunk(cpos);
+        self.wait_for_chunk(cpos, Instant::now() + REQUIRE_TIMEOUT)
+    }
+    /// takes the chunks the generator threads finished until the one at `cpos` is
+    /// there, it failed or `deadline` passed
+    fn wait_for_chunk(&mut self, cpos: ChunkPos, deadline: Instant) -> bool {
+        loop {
+            if self.get_chunk(cpos).is_some() {
+                return true;
+            }
+            if self.generation.has_failed(cpos) {
+                return false;
+            }
+            if Instant::now() >= deadline {
+                eprintln!(
+                    "warning: gave up waiting for chunk {cpos:?} of {:?}",
+                    self.name
+                );
+                return false;
+            }
+            for (pos, chunk) in self.generation.update() {
+                self.set_chunk(pos, chunk);
+            }
+            self.errors.extend(self.generation.errors());
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// returns the z a body stands at on top of the column at `x`, `y`, `None` if its
+    /// top is no solid ground or there are not two free blocks above it
+    pub fn standing_height(&self, x: i32, y: i32) -> Option<i32> {
+        let heights = self.heights();
+        let block = |z| self.get_block(WorldBlockPos { x, y, z });
+        let ground = heights
+            .clone()
+            .rev()
+            .find(|z| block(*z).is_some_and(|block| block != Block::AIR))?;
+        let free = |z: i32| z >= heights.end || block(z) == Some(Block::AIR);
+        (self.blockset.has_flag(block(ground)?, BlockFlag::Solid)
+            && ground + 1 < heights.end
+            && free(ground + 1)
+            && free(ground + 2))
+        .then_some(ground + 1)
+    }
+
+    /// returns the z a body falling at `x`, `y` from the block at `z` lands on, the top of
+    /// the first solid block at or below it, `None` while a chunk above it is not loaded
+    pub fn ground_below(&self, x: i32, y: i32, z: i32) -> Option<i32> {
+        let heights = self.heights();
+        for z in (heights.start..=z.min(heights.end - 1)).rev() {
+            let block = self.get_block(WorldBlockPos { x, y, z })?;
+            if self.blockset.has_flag(block, BlockFlag::Solid) {
+                return Some(z + 1);
+            }
+        }
+        Some(heights.start)
     }
 
-    /// generate a `Chunk` at `ChunkPos` with `seed`
+    /// searches outward from the origin for the nearest open place to stand, a column
+    /// whose four neighbors can be stood on at the same height, generating the chunks
+    /// of `levels` one ring at a time up to `radius` chunks away until one is found
+    pub fn find_spawn(&mut self, radius: i32) -> Option<WorldBlockPos> {
+        for ring in 0..=radius {
+            // the inner rings are already there, only this one is generated
+            let complete = self.require_area(
+                ChunkPos {
+                    x: -ring,
+                    y: -ring,
+                    z: *self.levels.start(),
+                },
+                ChunkPos {
+                    x: ring,
+                    y: ring,
+                    z: *self.levels.end(),
+                },
+            );
+            // chunks of the inner rings are checked again since their border columns
+            // need the neighbors of this ring
+            let size = CHUNK_SIZE as i32;
+            let spawn = (-ring * size..(ring + 1) * size)
+                .flat_map(|y| (-ring * size..(ring + 1) * size).map(move |x| (x, y)))
+                .filter_map(|(x, y)| {
+                    let z = self.standing_height(x, y)?;
+                    [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                        .iter()
+                        .all(|(dx, dy)| self.standing_height(x + dx, y + dy) == Some(z))
+                        .then_some(WorldBlockPos { x, y, z })
+                })
+                .min_by_key(|pos| (pos.x * pos.x + pos.y * pos.y, pos.y, pos.x));
+            // searching further only waits longer when the generator is stuck
+            if spawn.is_some() || !complete {
+                return spawn;
+            }
+        }
+        None
+    }
+
+    /// searches the columns up to `radius` blocks around `pos` for the lowest place to
+    /// stand closest to it, so bodies end up on the floor instead of on top of walls,
+    /// generating the chunks of `levels` there first
+    pub fn find_standing(&mut self, pos: WorldBlockPos, radius: i32) -> Option<WorldBlockPos> {
+        let start = ChunkPos {
+            z: *self.levels.start(),
+            ..ChunkPos::from(WorldBlockPos {
+                x: pos.x - radius,
+                y: pos.y - radius,
+                z: 0,
+            })
+        };
+        let end = ChunkPos {
+            z: *self.levels.end(),
+            ..ChunkPos::from(WorldBlockPos {
+                x: pos.x + radius,
+                y: pos.y + radius,
+                z: 0,
+            })
+        };
+        self.require_area(start, end);
+        (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (pos.x + dx, pos.y + dy)))
+            .filter_map(|(x, y)| {
+                let z = self.standing_height(x, y)?;
+                Some(WorldBlockPos { x, y, z })
+            })
+            .min_by_key(|spot| {
+                let (dx, dy) = (spot.x - pos.x, spot.y - pos.y);
+                (spot.z, dx * dx + dy * dy, spot.y, spot.x)
+            })
+    }
+
+    /// queues the `Chunk` at `ChunkPos` for generation, its stages are sent to the
+    /// generator threads by `tick`
     #[inline(always)]
-    pub fn generate_chunk(&self, cpos: ChunkPos) {
-        let _ = self.request_tx.send(ChunkRequest(cpos));
+    pub fn generate_chunk(&mut self, cpos: ChunkPos) {
+        if let Some(chunk) = self.generation.request(cpos) {
+            self.set_chunk(cpos, chunk);
+        }
     }
 
     /// update the neighbors of each block in the chunk at `ChunkPos`
@@ -514,7 +1026,7 @@ impl BlockMap {
             .collect();
 
         if let Some(chunk) = self.get_chunk_mut(cpos) {
-            chunk.neighbors.copy_from_slice(&new_neighbors);
+            chunk.neighbors = Some(new_neighbors.into_boxed_slice());
         }
     }
 
@@ -527,10 +1039,13 @@ impl BlockMap {
     ) {
         let (start, end) = Self::view_space(data.camera.target, data.camera.zoom);
 
-        // all chunks in view
-        for y in start.y..=end.y {
-            for x in start.x..=end.x {
-                self.draw_chunk(d, buffer, &data.atlas, ChunkPos { x, y });
+        // all chunks in view, lower levels first so the ones above cover them
+        for z in self.levels.clone() {
+            let (start, end) = level_view(start, end, z);
+            for y in start.y..=end.y {
+                for x in start.x..=end.x {
+                    self.draw_chunk(d, buffer, &data.atlas, ChunkPos { x, y, z });
+                }
             }
         }
     }
@@ -621,9 +1136,11 @@ impl BlockMap {
         draw: &mut RaylibMode2D<'_, RaylibDrawHandle<'_>>,
         atlas: &Texture2D,
         spos: Vector2,
-        BlockData { atlas_pos, kind }: &BlockData,
+        data: &BlockData,
         floor_neighbors: Neighbors,
     ) {
+        let BlockData { kind, .. } = data;
+        let atlas_pos = data.atlas_pos_at(self.time);
         let dst = Rectangle::new(spos.x, spos.y, TILE_SIZE as f32, TILE_SIZE as f32);
         let src = match kind {
             BlockKind::Block(autotile) => {
@@ -638,7 +1155,7 @@ impl BlockMap {
             }
             BlockKind::Prop => atlas_pos.source(),
         };
-        draw.draw_texture_pro(atlas, src, dst, Vector2::zero(), 0.0, Color::WHITE);
+        draw.draw_texture_pro(atlas, src, dst, Vector2::zero(), 0.0, data.tint);
     }
 
     fn draw_tile_wall_cached(
@@ -646,9 +1163,11 @@ impl BlockMap {
         draw: &mut RaylibMode2D<'_, RaylibDrawHandle<'_>>,
         atlas: &Texture2D,
         spos: Vector2,
-        BlockData { atlas_pos, kind }: &BlockData,
+        data: &BlockData,
         wall_neighbors: Neighbors,
     ) {
+        let BlockData { kind, .. } = data;
+        let atlas_pos = data.atlas_pos_at(self.time);
         let dst = Rectangle::new(spos.x, spos.y, TILE_SIZE as f32, TILE_SIZE as f32);
         let src = match kind {
             BlockKind::Block(autotile) => {
@@ -663,12 +1182,57 @@ impl BlockMap {
             }
             BlockKind::Prop => atlas_pos.source(),
         };
-        draw.draw_texture_pro(atlas, src, dst, Vector2::zero(), 0.0, Color::WHITE);
+        draw.draw_texture_pro(atlas, src, dst, Vector2::zero(), 0.0, data.tint);
     }
 }
+/// chunks of level `z` in the view between `start` and `end` at z 0, the blocks of
+/// higher levels are drawn further up the screen
+fn level_view(start: ChunkPos, end: ChunkPos, z: i32) -> (ChunkPos, ChunkPos) {
+    let offset = z * CHUNK_HEIGHT as i32;
+    let size = CHUNK_SIZE as i32;
+    (
+        ChunkPos {
+            y: start.y + offset.div_euclid(size),
+            z,
+            ..start
+        },
+        ChunkPos {
+            y: end.y + (offset + size - 1).div_euclid(size),
+            z,
+            ..end
+        },
+    )
+}
+
+/// returns if the chunk at `cpos` is inside any of the `BlockMap::keep_areas`
+#[inline(always)]
+fn in_areas(areas: &[(ChunkPos, ChunkPos)], cpos: ChunkPos) -> bool {
+    areas.iter().any(|(start, end)| {
+        (start.x..=end.x).contains(&cpos.x)
+            && (start.y..=end.y).contains(&cpos.y)
+            && (start.z..=end.z).contains(&cpos.z)
+    })
+}
 pub fn update_map(world: &mut World, data: &mut GameData, dt: f32) {
     for block_map in world.query_mut::<&mut BlockMap>() {
-        block_map.update(dt, data);
+        for error in block_map.errors.drain(..) {
+            data.push_event(GameEvent::ChunkError {
+                map: block_map.name.clone(),
+                error,
+            });
+        }
+        if block_map.name == data.active_map {
+            block_map.background = 0.0;
+            block_map.update(dt, data);
+        } else {
+            // only loaders keep the chunks of maps nobody looks at
+            block_map.view = None;
+            block_map.background += dt;
+            if block_map.background >= BACKGROUND_TICK {
+                block_map.tick(block_map.background);
+                block_map.background = 0.0;
+            }
+        }
     }
 }
 pub fn draw_map(
@@ -677,7 +1241,11 @@ pub fn draw_map(
     buffer: &mut BlockMapDrawBuffer,
     data: &GameData,
 ) {
-    for block_map in world.query_mut::<&mut BlockMap>() {
+    for block_map in world
+        .query_mut::<&mut BlockMap>()
+        .into_iter()
+        .filter(|block_map| block_map.name == data.active_map)
+    {
         block_map.draw(d, buffer, data);
     }
 }
@@ -916,3 +1484,96 @@ pub fn at47(neighbors: Neighbors) -> AtlasPos {
         _ => (6, 2),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_blocks_end_at_chunk_height() {
+        let mut chunk = Chunk::new_empty();
+        let top = ChunkBlockPos {
+            x: CHUNK_SIZE - 1,
+            y: CHUNK_SIZE - 1,
+            z: CHUNK_HEIGHT - 1,
+        };
+        chunk.set(top, Block::ROCK);
+        assert_eq!(chunk.get(top), Some(Block::ROCK));
+        assert!(chunk.dirty);
+
+        let above = ChunkBlockPos {
+            z: CHUNK_HEIGHT,
+            ..top
+        };
+        assert_eq!(chunk.get(above), None);
+        assert_eq!(chunk.get_neighbors(above), None);
+        chunk.set(above, Block::ROCK);
+        chunk.set_neighbors(above, (1, 1));
+        assert_eq!(chunk.blocks().get(top.idx()), Block::ROCK);
+    }
+
+    #[test]
+    fn keep_areas_and_focus_use_levels() {
+        use crate::world::generator::CaveGenerator;
+        let mut map = BlockMap::new(BlockSet::normal(), CaveGenerator::default(), 1)
+            .with_levels(-1..=1)
+            .with_threads(1);
+        map.view = Some((
+            ChunkPos { x: 0, y: 0, z: -1 },
+            ChunkPos { x: 4, y: 2, z: 1 },
+        ));
+        map.loaders = vec![(ChunkPos { x: 40, y: 0, z: 1 }, 1)];
+        let keep = map.keep_areas();
+        for z in -1..=1 {
+            assert!(in_areas(&keep, ChunkPos { x: 2, y: 1, z }));
+        }
+        assert!(!in_areas(&keep, ChunkPos { x: 2, y: 1, z: 2 }));
+        for z in 0..=2 {
+            assert!(in_areas(&keep, ChunkPos { x: 41, y: -1, z }));
+        }
+        assert!(!in_areas(
+            &keep,
+            ChunkPos {
+                x: 41,
+                y: -1,
+                z: -1
+            }
+        ));
+        assert_eq!(
+            map.focus(),
+            vec![
+                ChunkPos { x: 2, y: 1, z: 0 },
+                ChunkPos { x: 40, y: 0, z: 1 }
+            ]
+        );
+    }
+
+    /// panics on every chunk
+    struct BrokenGenerator;
+    impl TerrainGenerator for BrokenGenerator {
+        type ChunkData = ();
+        fn seed(&self) -> u32 {
+            0
+        }
+        fn set_seed(&mut self, _seed: u32) {}
+        fn get_chunk_data(&self, _cpos: ChunkPos) {}
+        fn gen_block(&self, _wpos: WorldBlockPos, _chunk_data: &()) -> Block {
+            panic!("broken generator")
+        }
+        fn ground_height(&self, _x: i32, _y: i32) -> Option<i32> {
+            None
+        }
+    }
+
+    #[test]
+    fn failed_chunks_end_the_spawn_search() {
+        let mut map = BlockMap::new(BlockSet::normal(), BrokenGenerator, 1).with_threads(1);
+        let origin = ChunkPos::default();
+        assert!(!map.require_chunk(origin));
+        assert_eq!(map.errors[0].cpos, origin);
+        // the next ring is never generated once a ring could not be
+        assert_eq!(map.find_spawn(SPAWN_SEARCH_RADIUS), None);
+        let next = ChunkPos { x: 1, y: 0, z: 0 };
+        assert!(!map.generation.is_requested(next) && !map.generation.has_failed(next));
+    }
+}
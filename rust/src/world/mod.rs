@@ -1,3 +1,16 @@
+pub mod block_config;
+pub mod chunk_blocks;
+pub mod export;
 pub mod generator;
 pub mod map;
+pub mod noise_graph;
+pub mod noise_source;
+pub mod palette;
+pub mod pipeline;
+pub mod scatter;
+pub mod schematic;
+pub mod snapshot;
+pub mod storage;
+pub mod structure;
+pub mod tiled;
 pub mod units;
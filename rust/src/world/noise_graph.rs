@@ -0,0 +1,460 @@
+use super::noise_source::{GetNoise, NoiseConfig, NoiseKind};
+use noise::{Fbm, Perlin};
+use ron::extensions::Extensions;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::{fs, io, path::Path};
+
+/// terrain height graph shipped with the game
+pub const DEFAULT_TERRAIN: &str = include_str!("../../assets/terrain.ron");
+
+/// one node of a noise graph file, evaluated at a block position
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum NodeDef {
+    /// the same value everywhere
+    Constant(f64),
+    /// fractal noise, seeded from the world seed and `salt`
+    Source(SourceDef),
+    /// the node with that name in `nodes`
+    Ref(String),
+    /// zooms the input, a factor below 1 makes its features bigger
+    Scale {
+        input: Box<NodeDef>,
+        factor: f64,
+    },
+    Add(Vec<NodeDef>),
+    Multiply(Vec<NodeDef>),
+    Clamp {
+        input: Box<NodeDef>,
+        min: f64,
+        max: f64,
+    },
+    /// `low` where `control` is below `threshold` and `high` above it,
+    /// blended over `falloff` on both sides
+    Select {
+        control: Box<NodeDef>,
+        low: Box<NodeDef>,
+        high: Box<NodeDef>,
+        #[serde(default)]
+        threshold: f64,
+        #[serde(default)]
+        falloff: f64,
+    },
+    /// moves the position by `x` and `y` times `strength` before reading the input
+    DomainWarp {
+        input: Box<NodeDef>,
+        x: Box<NodeDef>,
+        y: Box<NodeDef>,
+        strength: f64,
+    },
+    /// sharp ridges where the input crosses 0
+    Ridged(Box<NodeDef>),
+    /// round hills where the input crosses 0
+    Billow(Box<NodeDef>),
+    /// flattens the input into `steps` levels between -1 and 1
+    Terrace {
+        input: Box<NodeDef>,
+        steps: u32,
+    },
+}
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourceDef {
+    #[serde(default)]
+    kind: NoiseKind,
+    salt: u32,
+    #[serde(default = "default_freq")]
+    freq: f64,
+    #[serde(default)]
+    offset: [f64; 2],
+    #[serde(default = "default_octaves")]
+    octaves: usize,
+    #[serde(default = "default_lacunarity")]
+    lacunarity: f64,
+}
+fn default_freq() -> f64 {
+    1.0
+}
+fn default_octaves() -> usize {
+    Fbm::<Perlin>::DEFAULT_OCTAVE_COUNT
+}
+fn default_lacunarity() -> f64 {
+    Fbm::<Perlin>::DEFAULT_LACUNARITY
+}
+/// a noise graph file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GraphDef {
+    /// name of the node whose value is the result
+    output: String,
+    nodes: FxHashMap<String, NodeDef>,
+}
+
+/// a node of a loaded `NoiseGraph`, `Ref` nodes are replaced by the node they name
+#[derive(Debug, Clone, PartialEq)]
+pub enum NoiseNode {
+    Constant(f64),
+    /// `NoiseConfig::freq` scales the position including the offset
+    Source(NoiseConfig<2>),
+    Scale(Box<NoiseNode>, f64),
+    Add(Vec<NoiseNode>),
+    Multiply(Vec<NoiseNode>),
+    Clamp(Box<NoiseNode>, f64, f64),
+    Select {
+        control: Box<NoiseNode>,
+        low: Box<NoiseNode>,
+        high: Box<NoiseNode>,
+        threshold: f64,
+        falloff: f64,
+    },
+    DomainWarp {
+        input: Box<NoiseNode>,
+        x: Box<NoiseNode>,
+        y: Box<NoiseNode>,
+        strength: f64,
+    },
+    Ridged(Box<NoiseNode>),
+    Billow(Box<NoiseNode>),
+    Terrace(Box<NoiseNode>, u32),
+}
+/// terrain noise built from a noise graph file instead of code
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseGraph {
+    pub output: NoiseNode,
+}
+
+impl NoiseNode {
+    /// rebuilds every noise source for the world `seed`
+    pub fn reseed(&mut self, seed: u32) {
+        match self {
+            Self::Constant(_) => {}
+            Self::Source(config) => config.reseed(seed),
+            Self::Add(inputs) | Self::Multiply(inputs) => {
+                for input in inputs.iter_mut() {
+                    input.reseed(seed);
+                }
+            }
+            Self::Scale(input, _)
+            | Self::Clamp(input, _, _)
+            | Self::Ridged(input)
+            | Self::Billow(input)
+            | Self::Terrace(input, _) => input.reseed(seed),
+            Self::Select {
+                control, low, high, ..
+            } => {
+                control.reseed(seed);
+                low.reseed(seed);
+                high.reseed(seed);
+            }
+            Self::DomainWarp { input, x, y, .. } => {
+                input.reseed(seed);
+                x.reseed(seed);
+                y.reseed(seed);
+            }
+        }
+    }
+}
+impl GetNoise<2> for NoiseNode {
+    fn get(&self, pos: [f64; 2]) -> f64 {
+        match self {
+            Self::Constant(value) => *value,
+            Self::Source(config) => config.get(pos.map(|v| v * config.freq)),
+            Self::Scale(input, factor) => input.get(pos.map(|v| v * factor)),
+            Self::Add(inputs) => inputs.iter().map(|input| input.get(pos)).sum(),
+            Self::Multiply(inputs) => inputs.iter().map(|input| input.get(pos)).product(),
+            Self::Clamp(input, min, max) => input.get(pos).clamp(*min, *max),
+            Self::Select {
+                control,
+                low,
+                high,
+                threshold,
+                falloff,
+            } => {
+                let control = control.get(pos);
+                if control <= threshold - falloff {
+                    return low.get(pos);
+                }
+                if control >= threshold + falloff {
+                    return high.get(pos);
+                }
+                let t = (control - (threshold - falloff)) / (2.0 * falloff);
+                let t = t * t * (3.0 - 2.0 * t);
+                low.get(pos) * (1.0 - t) + high.get(pos) * t
+            }
+            Self::DomainWarp {
+                input,
+                x,
+                y,
+                strength,
+            } => input.get([
+                pos[0] + x.get(pos) * strength,
+                pos[1] + y.get(pos) * strength,
+            ]),
+            Self::Ridged(input) => 1.0 - 2.0 * input.get(pos).abs(),
+            Self::Billow(input) => 2.0 * input.get(pos).abs() - 1.0,
+            Self::Terrace(input, steps) => {
+                let steps = *steps as f64;
+                let t = (input.get(pos) + 1.0) * 0.5 * steps;
+                // inputs past -1 and 1 stay on the lowest and highest level
+                (t.floor().clamp(0.0, steps - 1.0) / (steps - 1.0)) * 2.0 - 1.0
+            }
+        }
+    }
+}
+
+impl NoiseGraph {
+    /// graph of `DEFAULT_TERRAIN`
+    pub fn normal() -> Self {
+        Self::from_ron(DEFAULT_TERRAIN).expect("default terrain graph is invalid")
+    }
+    /// loads a `.json` or RON noise graph file
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::from_json(&text),
+            _ => Self::from_ron(&text),
+        }
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+    }
+    pub fn from_ron(text: &str) -> io::Result<Self> {
+        // lets `source(salt: 1)` be written without the extra parentheses
+        let options =
+            ron::Options::default().with_default_extension(Extensions::UNWRAP_VARIANT_NEWTYPES);
+        Self::from_def(
+            options
+                .from_str(text)
+                .map_err(|err| invalid(err.to_string()))?,
+        )
+    }
+    pub fn from_json(text: &str) -> io::Result<Self> {
+        Self::from_def(serde_json::from_str(text).map_err(|err| invalid(err.to_string()))?)
+    }
+    fn from_def(def: GraphDef) -> io::Result<Self> {
+        let node = def
+            .nodes
+            .get(&def.output)
+            .ok_or_else(|| invalid(format!("unknown output node {:?}", def.output)))?;
+        let mut path = vec![def.output.as_str()];
+        let output = build(node, &def.nodes, &mut path)?;
+        Ok(Self { output })
+    }
+    /// rebuilds every noise source for the world `seed`
+    pub fn reseed(&mut self, seed: u32) {
+        self.output.reseed(seed);
+    }
+}
+impl GetNoise<2> for NoiseGraph {
+    #[inline(always)]
+    fn get(&self, pos: [f64; 2]) -> f64 {
+        self.output.get(pos)
+    }
+}
+
+/// resolves a node and its inputs, `path` holds the names being resolved to find cycles
+fn build<'a>(
+    def: &'a NodeDef,
+    nodes: &'a FxHashMap<String, NodeDef>,
+    path: &mut Vec<&'a str>,
+) -> io::Result<NoiseNode> {
+    Ok(match def {
+        NodeDef::Constant(value) => NoiseNode::Constant(*value),
+        NodeDef::Source(source) => NoiseNode::Source(source.build()),
+        NodeDef::Ref(name) => {
+            if path.contains(&name.as_str()) {
+                return Err(invalid(format!(
+                    "node {name:?} depends on itself: {} -> {name}",
+                    path.join(" -> ")
+                )));
+            }
+            let node = nodes
+                .get(name)
+                .ok_or_else(|| invalid(format!("unknown node {name:?}")))?;
+            path.push(name);
+            let node = build(node, nodes, path)?;
+            path.pop();
+            node
+        }
+        NodeDef::Scale { input: i, factor } => NoiseNode::Scale(boxed(i, nodes, path)?, *factor),
+        NodeDef::Add(inputs) => NoiseNode::Add(build_all(inputs, nodes, path)?),
+        NodeDef::Multiply(inputs) => NoiseNode::Multiply(build_all(inputs, nodes, path)?),
+        NodeDef::Clamp { input: i, min, max } => {
+            if min > max {
+                return Err(invalid(format!("clamp min {min} is above max {max}")));
+            }
+            NoiseNode::Clamp(boxed(i, nodes, path)?, *min, *max)
+        }
+        NodeDef::Select {
+            control,
+            low,
+            high,
+            threshold,
+            falloff,
+        } => NoiseNode::Select {
+            control: boxed(control, nodes, path)?,
+            low: boxed(low, nodes, path)?,
+            high: boxed(high, nodes, path)?,
+            threshold: *threshold,
+            falloff: falloff.max(0.0),
+        },
+        NodeDef::DomainWarp {
+            input: i,
+            x,
+            y,
+            strength,
+        } => NoiseNode::DomainWarp {
+            input: boxed(i, nodes, path)?,
+            x: boxed(x, nodes, path)?,
+            y: boxed(y, nodes, path)?,
+            strength: *strength,
+        },
+        NodeDef::Ridged(i) => NoiseNode::Ridged(boxed(i, nodes, path)?),
+        NodeDef::Billow(i) => NoiseNode::Billow(boxed(i, nodes, path)?),
+        NodeDef::Terrace { input: i, steps } => {
+            if *steps < 2 {
+                return Err(invalid("terrace needs at least 2 steps"));
+            }
+            NoiseNode::Terrace(boxed(i, nodes, path)?, *steps)
+        }
+    })
+}
+fn boxed<'a>(
+    def: &'a NodeDef,
+    nodes: &'a FxHashMap<String, NodeDef>,
+    path: &mut Vec<&'a str>,
+) -> io::Result<Box<NoiseNode>> {
+    build(def, nodes, path).map(Box::new)
+}
+fn build_all<'a>(
+    defs: &'a [NodeDef],
+    nodes: &'a FxHashMap<String, NodeDef>,
+    path: &mut Vec<&'a str>,
+) -> io::Result<Vec<NoiseNode>> {
+    defs.iter().map(|def| build(def, nodes, path)).collect()
+}
+impl SourceDef {
+    fn build(&self) -> NoiseConfig<2> {
+        let mut config = NoiseConfig {
+            freq: self.freq,
+            kind: self.kind,
+            octaves: self.octaves,
+            lacunarity: self.lacunarity,
+            ..NoiseConfig::new(self.salt, self.offset)
+        };
+        config.reseed(0);
+        config
+    }
+}
+
+#[inline(always)]
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(output: &str, nodes: &str) -> io::Result<NoiseGraph> {
+        NoiseGraph::from_ron(&format!("(output: {output:?}, nodes: {{ {nodes} }})"))
+    }
+    fn error(result: io::Result<NoiseGraph>) -> String {
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        err.to_string()
+    }
+
+    #[test]
+    fn ref_cycles_fail() {
+        let err = error(graph(
+            "height",
+            r#""height": add([ref("a"), constant(1)]), "a": scale(input: ref("b"), factor: 2),
+            "b": ref("a")"#,
+        ));
+        assert!(err.contains("height -> a -> b -> a"), "{err}");
+    }
+
+    #[test]
+    fn unknown_nodes_fail() {
+        let err = error(graph("height", r#""hills": constant(0)"#));
+        assert!(err.contains("unknown output node \"height\""), "{err}");
+        let err = error(graph("height", r#""height": ridged(ref("hills"))"#));
+        assert!(err.contains("unknown node \"hills\""), "{err}");
+    }
+
+    #[test]
+    fn select_blends_over_the_falloff() {
+        let select = |control| NoiseNode::Select {
+            control: Box::new(NoiseNode::Constant(control)),
+            low: Box::new(NoiseNode::Constant(-1.0)),
+            high: Box::new(NoiseNode::Constant(1.0)),
+            threshold: 0.5,
+            falloff: 0.2,
+        };
+        assert_eq!(select(0.2).get([0.0; 2]), -1.0);
+        assert_eq!(select(0.3).get([0.0; 2]), -1.0);
+        assert_eq!(select(0.5).get([0.0; 2]), 0.0);
+        assert_eq!(select(0.7).get([0.0; 2]), 1.0);
+        // smoothstep of a quarter of the way
+        assert!((select(0.4).get([0.0; 2]) - (-1.0 + 2.0 * 0.15625)).abs() < 1e-9);
+        let mut last = -1.0;
+        for i in 0..=40 {
+            let value = select(0.3 + i as f64 * 0.01).get([0.0; 2]);
+            assert!(value >= last, "{value} after {last}");
+            last = value;
+        }
+    }
+
+    #[test]
+    fn terrace_stays_on_its_levels() {
+        let terrace = |input| NoiseNode::Terrace(Box::new(input), 4);
+        let levels = [-1.0, -1.0 / 3.0, 1.0 / 3.0, 1.0];
+        for input in [-5.0, -1.0, -0.6, 0.0, 0.4, 0.99, 1.0, 5.0] {
+            let value = terrace(NoiseNode::Constant(input)).get([0.0; 2]);
+            assert!(
+                levels.iter().any(|level| (level - value).abs() < 1e-9),
+                "{input} became {value}"
+            );
+        }
+        // noise that goes past 1 with the extra amplitude
+        let loud = terrace(NoiseNode::Multiply(vec![
+            NoiseNode::Source(NoiseConfig::new(1, [0.0; 2])),
+            NoiseNode::Constant(3.0),
+        ]));
+        for i in 0..200 {
+            let value = loud.get([i as f64 * 0.37, i as f64 * 0.11]);
+            assert!((-1.0..=1.0).contains(&value), "{value}");
+        }
+    }
+
+    #[test]
+    fn json_matches_ron() {
+        let json = r#"{
+            "output": "height",
+            "nodes": {
+                "continents": {"source": {"salt": 1, "freq": 0.01, "offset": [100, 200]}},
+                "peaks": {"multiply": [
+                    {"ridged": {"source": {"salt": 12, "freq": 0.02, "octaves": 4}}},
+                    {"constant": 0.2}
+                ]},
+                "height": {"select": {
+                    "control": {"ref": "continents"},
+                    "low": {"ref": "continents"},
+                    "high": {"add": [{"ref": "continents"}, {"ref": "peaks"}]},
+                    "threshold": 0.35,
+                    "falloff": 0.1
+                }}
+            }
+        }"#;
+        let mut from_json = NoiseGraph::from_json(json).unwrap();
+        let mut from_ron = NoiseGraph::normal();
+        assert_eq!(from_json, from_ron);
+        from_json.reseed(42);
+        from_ron.reseed(42);
+        for i in 0..50 {
+            let pos = [i as f64 * 7.3, i as f64 * -3.1];
+            assert_eq!(from_json.get(pos), from_ron.get(pos));
+        }
+    }
+}
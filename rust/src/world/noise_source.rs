@@ -0,0 +1,266 @@
+use noise::{
+    Fbm, MultiFractal, NoiseFn, Perlin, Seedable, Simplex, Value, Vector2, Vector3,
+    core::worley::{ReturnType, distance_functions::euclidean, worley_2d, worley_3d},
+    permutationtable::PermutationTable,
+};
+use serde::Deserialize;
+use std::fmt::{self, Debug};
+
+/// noise function of a layer
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseKind {
+    #[default]
+    Perlin,
+    Simplex,
+    /// cells around random points
+    Worley,
+    Value,
+}
+
+/// worley noise that can be shared between generator threads
+#[derive(Clone, Copy)]
+pub struct CellNoise {
+    seed: u32,
+    perm_table: PermutationTable,
+}
+impl Default for CellNoise {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+impl CellNoise {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            perm_table: PermutationTable::new(seed),
+        }
+    }
+}
+impl Seedable for CellNoise {
+    fn set_seed(self, seed: u32) -> Self {
+        Self::new(seed)
+    }
+    fn seed(&self) -> u32 {
+        self.seed
+    }
+}
+impl NoiseFn<f64, 2> for CellNoise {
+    #[inline(always)]
+    fn get(&self, point: [f64; 2]) -> f64 {
+        worley_2d(
+            &self.perm_table,
+            euclidean,
+            ReturnType::Value,
+            Vector2::from(point),
+        )
+    }
+}
+impl NoiseFn<f64, 3> for CellNoise {
+    #[inline(always)]
+    fn get(&self, point: [f64; 3]) -> f64 {
+        worley_3d(
+            &self.perm_table,
+            euclidean,
+            ReturnType::Value,
+            Vector3::from(point),
+        )
+    }
+}
+impl Debug for CellNoise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CellNoise")
+            .field("seed", &self.seed)
+            .finish()
+    }
+}
+
+/// seeded fractal noise owned by one layer
+#[derive(Debug, Clone)]
+pub enum NoiseSource {
+    Perlin(Fbm<Perlin>),
+    Simplex(Fbm<Simplex>),
+    Worley(Fbm<CellNoise>),
+    Value(Fbm<Value>),
+}
+impl NoiseSource {
+    pub fn new(kind: NoiseKind, seed: u32, octaves: usize, lacunarity: f64) -> Self {
+        match kind {
+            NoiseKind::Perlin => Self::Perlin(fbm(seed, octaves, lacunarity)),
+            NoiseKind::Simplex => Self::Simplex(fbm(seed, octaves, lacunarity)),
+            NoiseKind::Worley => Self::Worley(fbm(seed, octaves, lacunarity)),
+            NoiseKind::Value => Self::Value(fbm(seed, octaves, lacunarity)),
+        }
+    }
+    #[inline(always)]
+    pub fn seed(&self) -> u32 {
+        match self {
+            Self::Perlin(fbm) => fbm.seed(),
+            Self::Simplex(fbm) => fbm.seed(),
+            Self::Worley(fbm) => fbm.seed(),
+            Self::Value(fbm) => fbm.seed(),
+        }
+    }
+}
+#[inline(always)]
+fn fbm<T: Default + Seedable + Clone>(seed: u32, octaves: usize, lacunarity: f64) -> Fbm<T> {
+    Fbm::<T>::new(seed)
+        .set_octaves(octaves)
+        .set_lacunarity(lacunarity)
+}
+impl NoiseFn<f64, 2> for NoiseSource {
+    #[inline(always)]
+    fn get(&self, point: [f64; 2]) -> f64 {
+        match self {
+            Self::Perlin(fbm) => fbm.get(point),
+            Self::Simplex(fbm) => fbm.get(point),
+            Self::Worley(fbm) => fbm.get(point),
+            Self::Value(fbm) => fbm.get(point),
+        }
+    }
+}
+impl NoiseFn<f64, 3> for NoiseSource {
+    #[inline(always)]
+    fn get(&self, point: [f64; 3]) -> f64 {
+        match self {
+            Self::Perlin(fbm) => fbm.get(point),
+            Self::Simplex(fbm) => fbm.get(point),
+            Self::Worley(fbm) => fbm.get(point),
+            Self::Value(fbm) => fbm.get(point),
+        }
+    }
+}
+
+pub trait GetNoise<const DIM: usize> {
+    fn get(&self, pos: [f64; DIM]) -> f64;
+}
+/// one noise layer with its own `NoiseSource`
+#[derive(Debug, Clone)]
+pub struct NoiseConfig<const DIM: usize> {
+    pub freq: f64,
+    pub amp: f64,
+    pub gain: f64,
+    pub offset: [f64; DIM],
+    pub kind: NoiseKind,
+    pub octaves: usize,
+    /// frequency multiplier between octaves
+    pub lacunarity: f64,
+    /// mixed into the world seed so every layer gets different noise
+    pub salt: u32,
+    /// built from the settings above by `reseed`
+    pub source: NoiseSource,
+}
+impl<const DIM: usize> NoiseConfig<DIM> {
+    /// perlin noise with the default octaves, seeded for world seed 0
+    pub fn new(salt: u32, offset: [f64; DIM]) -> Self {
+        let octaves = Fbm::<Perlin>::DEFAULT_OCTAVE_COUNT;
+        let lacunarity = Fbm::<Perlin>::DEFAULT_LACUNARITY;
+        Self {
+            freq: 1.0,
+            amp: 1.0,
+            gain: 1.,
+            offset,
+            kind: NoiseKind::Perlin,
+            octaves,
+            lacunarity,
+            salt,
+            source: NoiseSource::new(NoiseKind::Perlin, layer_seed(0, salt), octaves, lacunarity),
+        }
+    }
+    /// rebuilds the noise source for the world `seed`, also needed after changing
+    /// the kind, octaves or lacunarity
+    pub fn reseed(&mut self, seed: u32) {
+        self.source = NoiseSource::new(
+            self.kind,
+            layer_seed(seed, self.salt),
+            self.octaves,
+            self.lacunarity,
+        );
+    }
+}
+/// seed of the layer with `salt` in the world `seed`, hashed so no two salts share
+/// their noise across neighboring world seeds
+#[inline(always)]
+pub fn layer_seed(seed: u32, salt: u32) -> u32 {
+    let mut h = (seed as u64) | ((salt as u64) << 32);
+    h = h.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    (h >> 32) as u32
+}
+impl<const DIM: usize> PartialEq for NoiseConfig<DIM> {
+    fn eq(&self, other: &Self) -> bool {
+        self.freq == other.freq
+            && self.amp == other.amp
+            && self.gain == other.gain
+            && self.offset == other.offset
+            && self.kind == other.kind
+            && self.octaves == other.octaves
+            && self.lacunarity == other.lacunarity
+            && self.salt == other.salt
+            && self.source.seed() == other.source.seed()
+    }
+}
+impl GetNoise<2> for NoiseConfig<2> {
+    #[inline(always)]
+    fn get(&self, pos: [f64; 2]) -> f64 {
+        let p = [0, 1].map(|i| pos[i] + self.offset[i] * self.freq);
+        self.source.get(p) * self.amp * self.gain
+    }
+}
+impl GetNoise<3> for NoiseConfig<3> {
+    #[inline(always)]
+    fn get(&self, pos: [f64; 3]) -> f64 {
+        let p = [0, 1, 2].map(|i| pos[i] + self.offset[i] * self.freq);
+        self.source.get(p) * self.amp * self.gain
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoiseLayers<const SIZE: usize, const DIM: usize> {
+    pub layers: [NoiseConfig<DIM>; SIZE],
+    pub scales: [f64; SIZE],
+}
+impl<const SIZE: usize, const DIM: usize> NoiseLayers<SIZE, DIM> {
+    /// rebuilds the noise source of every layer for the world `seed`
+    pub fn reseed(&mut self, seed: u32) {
+        for layer in self.layers.iter_mut() {
+            layer.reseed(seed);
+        }
+    }
+}
+impl<const SIZE: usize> GetNoise<2> for NoiseLayers<SIZE, 2> {
+    fn get(&self, pos: [f64; 2]) -> f64 {
+        self.layers
+            .iter()
+            .zip(self.scales)
+            .map(|(layer, scale)| layer.get(pos) * scale)
+            .sum::<f64>()
+    }
+}
+impl<const SIZE: usize> GetNoise<3> for NoiseLayers<SIZE, 3> {
+    fn get(&self, pos: [f64; 3]) -> f64 {
+        self.layers
+            .iter()
+            .zip(self.scales)
+            .map(|(layer, scale)| layer.get(pos) * scale)
+            .sum::<f64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_hash::FxHashSet;
+
+    #[test]
+    fn layer_seeds_never_alias() {
+        let mut seen = FxHashSet::default();
+        for seed in 0..64 {
+            for salt in 0..64 {
+                assert!(seen.insert(layer_seed(seed, salt)), "{seed} {salt}");
+            }
+        }
+        assert_ne!(layer_seed(42, 2), layer_seed(43, 1));
+    }
+}
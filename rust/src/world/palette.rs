@@ -0,0 +1,145 @@
+use super::map::Block;
+use crate::serial::{ReadExt, WriteExt};
+use std::io::{self, Read, Write};
+
+/// maps the block ids of a save file to stable block names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockPalette {
+    /// block names indexed by their saved id
+    pub names: Vec<String>,
+}
+impl BlockPalette {
+    /// palette of saves written before palettes existed
+    pub fn legacy() -> Self {
+        Self {
+            names: [
+                "air",
+                "grass",
+                "rock",
+                "sand",
+                "tree",
+                "bush",
+                "berry_bush",
+                "flower",
+                "mushroom",
+                "stone",
+                "iron",
+                "diamond",
+                "ruby",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+    /// returns the `Block` of `current` for every saved id, unknown names become `Block::UNKNOWN`
+    pub fn remap(&self, current: &BlockPalette) -> BlockRemap {
+        BlockRemap {
+            blocks: self
+                .names
+                .iter()
+                .map(|name| {
+                    current.block(name).unwrap_or_else(|| {
+                        eprintln!("warning: unknown block {name:?} in save, using placeholder");
+                        Block::UNKNOWN
+                    })
+                })
+                .collect(),
+        }
+    }
+    /// returns the `Block` saved with `name`
+    #[inline(always)]
+    pub fn block(&self, name: &str) -> Option<Block> {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .map(|id| Block(id as u8))
+    }
+    pub fn read(r: &mut impl Read) -> io::Result<Self> {
+        let len = r.read_u16()?;
+        let mut names = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            names.push(r.read_str()?);
+        }
+        Ok(Self { names })
+    }
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_u16(self.names.len() as u16)?;
+        for name in self.names.iter() {
+            w.write_str(name)?;
+        }
+        Ok(())
+    }
+}
+
+/// migrates saved block ids to the current `Block`s
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRemap {
+    pub blocks: Vec<Block>,
+}
+impl BlockRemap {
+    /// remap of the running game, every id maps to itself
+    pub fn identity() -> Self {
+        Self {
+            blocks: (0..=u8::MAX).map(Block).collect(),
+        }
+    }
+    #[inline(always)]
+    pub fn is_identity(&self) -> bool {
+        self.blocks
+            .iter()
+            .enumerate()
+            .all(|(id, block)| u8::from(*block) as usize == id)
+    }
+    /// returns the current `Block` of a saved id
+    #[inline(always)]
+    pub fn get(&self, id: u8) -> Block {
+        self.blocks
+            .get(id as usize)
+            .copied()
+            .unwrap_or(Block::UNKNOWN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette(names: &[&str]) -> BlockPalette {
+        BlockPalette {
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn palette_round_trip() {
+        let saved = palette(&["air", "rock", "berry_bush"]);
+        let mut buf = Vec::new();
+        saved.write(&mut buf).unwrap();
+        assert_eq!(BlockPalette::read(&mut buf.as_slice()).unwrap(), saved);
+        for len in 0..buf.len() {
+            assert!(BlockPalette::read(&mut &buf[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn remap_by_name() {
+        let current = BlockPalette::legacy();
+        assert!(current.remap(&current).is_identity());
+
+        let remap = palette(&["rock", "air", "removed", "ruby"]).remap(&current);
+        assert!(!remap.is_identity());
+        assert_eq!(remap.get(0), Block::ROCK);
+        assert_eq!(remap.get(1), Block::AIR);
+        assert_eq!(remap.get(2), Block::UNKNOWN);
+        assert_eq!(remap.get(3), Block::RUBY);
+        // ids past the saved palette are unknown as well
+        assert_eq!(remap.get(4), Block::UNKNOWN);
+    }
+
+    #[test]
+    fn identity_keeps_every_id() {
+        let remap = BlockRemap::identity();
+        assert!(remap.is_identity());
+        assert!((0..=u8::MAX).all(|id| remap.get(id) == Block(id)));
+    }
+}
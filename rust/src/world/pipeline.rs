@@ -0,0 +1,838 @@
+use super::{
+    chunk_blocks::ChunkBlocks,
+    generator::TerrainGenerator,
+    map::{Block, Chunk},
+    units::*,
+};
+use crossbeam_channel::{Receiver, Sender, bounded, unbounded};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+
+/// steps of chunk generation in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GenStage {
+    /// ground, water and ores
+    Terrain,
+    /// caves cut into the ground
+    Carving,
+    /// flora on the surface
+    Decoration,
+    /// structure templates
+    Structures,
+    /// cleans up what the earlier stages left behind
+    PostProcess,
+}
+impl GenStage {
+    pub const ALL: [GenStage; 5] = [
+        GenStage::Terrain,
+        GenStage::Carving,
+        GenStage::Decoration,
+        GenStage::Structures,
+        GenStage::PostProcess,
+    ];
+    /// the stage of a finished chunk
+    pub const LAST: GenStage = GenStage::PostProcess;
+
+    #[inline(always)]
+    pub fn prev(self) -> Option<Self> {
+        Self::ALL.get((self as usize).checked_sub(1)?).copied()
+    }
+    #[inline(always)]
+    pub fn next(self) -> Option<Self> {
+        Self::ALL.get(self as usize + 1).copied()
+    }
+}
+
+/// the chunk a stage works on and the neighbors it reads, neighbors hold the
+/// blocks of the previous stage so the result never depends on timing
+#[derive(Debug, Clone)]
+pub struct ChunkRegion {
+    pub cpos: ChunkPos,
+    pub blocks: ChunkBlocks,
+    neighbors: FxHashMap<ChunkPos, Arc<ChunkBlocks>>,
+}
+impl ChunkRegion {
+    /// returns `Block` of the chunk at `ChunkBlockPos`
+    #[inline(always)]
+    pub fn get(&self, pos: ChunkBlockPos) -> Block {
+        self.blocks.get(pos.idx())
+    }
+    /// sets `Block` of the chunk at `ChunkBlockPos`
+    #[inline(always)]
+    pub fn set(&mut self, pos: ChunkBlockPos, block: Block) {
+        self.blocks.set(pos.idx(), block);
+    }
+    /// returns `Block` at `WorldBlockPos` if it is in the chunk or a neighbor of the stage
+    #[inline(always)]
+    pub fn get_block(&self, pos: WorldBlockPos) -> Option<Block> {
+        let cpos: ChunkPos = pos.into();
+        let ctpos: ChunkBlockPos = pos.into();
+        if cpos == self.cpos {
+            Some(self.get(ctpos))
+        } else {
+            self.neighbors
+                .get(&cpos)
+                .map(|blocks| blocks.get(ctpos.idx()))
+        }
+    }
+    /// sets `Block` at `WorldBlockPos` if it is in the chunk, neighbors are read only
+    #[inline(always)]
+    pub fn set_block(&mut self, pos: WorldBlockPos, block: Block) {
+        if ChunkPos::from(pos) == self.cpos {
+            self.set(pos.into(), block);
+        }
+    }
+}
+
+/// one stage of one chunk that is ready to run
+#[derive(Debug)]
+pub struct StageJob<D> {
+    pub stage: GenStage,
+    pub region: ChunkRegion,
+    /// computed by the first stage and shared with the later ones
+    pub data: Option<Arc<D>>,
+}
+/// the blocks of a chunk after a `StageJob`
+#[derive(Debug)]
+pub struct StageResult<D> {
+    pub cpos: ChunkPos,
+    pub stage: GenStage,
+    pub blocks: ChunkBlocks,
+    pub data: Arc<D>,
+}
+impl<D> StageJob<D> {
+    /// runs the stage, this is the only part that has to run on a worker
+    pub fn run<G>(self, generator: &G) -> StageResult<D>
+    where
+        G: TerrainGenerator<ChunkData = D> + ?Sized,
+    {
+        let Self {
+            stage,
+            mut region,
+            data,
+        } = self;
+        let data = data.unwrap_or_else(|| Arc::new(generator.get_chunk_data(region.cpos)));
+        generator.gen_stage(stage, &mut region, &data);
+        StageResult {
+            cpos: region.cpos,
+            stage,
+            blocks: region.blocks,
+            data,
+        }
+    }
+}
+
+/// a chunk that has not been through every stage it is needed for
+#[derive(Debug)]
+struct ProtoChunk<D> {
+    /// blocks after every finished stage
+    levels: Vec<Arc<ChunkBlocks>>,
+    data: Option<Arc<D>>,
+    /// last stage the chunk or its neighbors need
+    target: Option<GenStage>,
+    running: bool,
+    /// the finished chunk was requested and not only read by neighbors
+    wanted: bool,
+}
+impl<D> ProtoChunk<D> {
+    /// stage that runs next
+    #[inline(always)]
+    fn next(&self) -> Option<GenStage> {
+        GenStage::ALL.get(self.levels.len()).copied()
+    }
+    /// the blocks of the last finished stage as a `Chunk`
+    fn chunk(&self) -> Chunk {
+        let mut chunk = Chunk::new_empty();
+        if let Some(blocks) = self.levels.last() {
+            chunk.set_blocks((**blocks).clone());
+        }
+        // generated blocks can be generated again, only edits have to be stored
+        chunk.dirty = false;
+        chunk
+    }
+    /// returns if every stage up to `target` is finished
+    #[inline(always)]
+    fn is_idle(&self) -> bool {
+        !self.running && self.next().is_none_or(|next| Some(next) > self.target)
+    }
+}
+
+/// schedules the stages of chunks once the neighbors they read are far enough
+#[derive(Debug)]
+pub struct ChunkPipeline<D> {
+    /// neighbor chunks on the same level every stage reads in each direction
+    radius: [i32; GenStage::ALL.len()],
+    protos: FxHashMap<ChunkPos, ProtoChunk<D>>,
+    /// chunks of the camera and players, the stages closest to them run first
+    focus: Vec<ChunkPos>,
+    /// chunks whose generation panicked, they are not generated again until
+    /// `retain_requests` drops them
+    failed: FxHashSet<ChunkPos>,
+}
+impl<D> ChunkPipeline<D> {
+    pub fn new<G>(generator: &G) -> Self
+    where
+        G: TerrainGenerator<ChunkData = D> + ?Sized,
+    {
+        Self {
+            radius: GenStage::ALL.map(|stage| generator.stage_radius(stage).max(0)),
+            protos: FxHashMap::default(),
+            focus: Vec::new(),
+            failed: FxHashSet::default(),
+        }
+    }
+    /// stages of the chunks closest to any `focus` chunk are handed out first
+    #[inline(always)]
+    pub fn set_focus(&mut self, focus: Vec<ChunkPos>) {
+        self.focus = focus;
+    }
+    /// squared distance to the closest focus chunk, lower runs first
+    fn priority(&self, cpos: ChunkPos) -> i64 {
+        self.focus
+            .iter()
+            .map(|focus| {
+                let (dx, dy, dz) = (
+                    (cpos.x - focus.x) as i64,
+                    (cpos.y - focus.y) as i64,
+                    (cpos.z - focus.z) as i64,
+                );
+                dx * dx + dy * dy + dz * dz
+            })
+            .min()
+            .unwrap_or(0)
+    }
+    /// cancels every requested chunk `keep` returns false for, chunks that were only
+    /// needed by them are dropped, failed chunks outside of `keep` are forgotten so
+    /// they are tried again once they are requested after coming back into view
+    pub fn retain_requests(&mut self, keep: impl Fn(ChunkPos) -> bool) {
+        self.failed.retain(|cpos| keep(*cpos));
+        let mut cancelled = false;
+        for (cpos, proto) in self.protos.iter_mut() {
+            if proto.wanted && !keep(*cpos) {
+                proto.wanted = false;
+                cancelled = true;
+            }
+        }
+        if !cancelled {
+            return;
+        }
+        // the targets of the neighbors only come from chunks that are still wanted
+        for proto in self.protos.values_mut() {
+            proto.target = None;
+        }
+        let wanted: Vec<ChunkPos> = self
+            .protos
+            .iter()
+            .filter(|(_, proto)| proto.wanted)
+            .map(|(cpos, _)| *cpos)
+            .collect();
+        for cpos in wanted {
+            self.require(cpos, GenStage::LAST);
+        }
+        self.cleanup();
+    }
+    /// asks for the finished chunk at `cpos`, it is returned by `complete` or right
+    /// away if it was already finished
+    pub fn request(&mut self, cpos: ChunkPos) -> Option<Chunk> {
+        if self.failed.contains(&cpos) {
+            return None;
+        }
+        self.require(cpos, GenStage::LAST);
+        let proto = self.protos.get_mut(&cpos)?;
+        if proto.next().is_none() {
+            return Some(proto.chunk());
+        }
+        proto.wanted = true;
+        None
+    }
+    /// returns if the finished chunk at `cpos` was requested and is not done yet
+    #[inline(always)]
+    pub fn is_requested(&self, cpos: ChunkPos) -> bool {
+        self.protos.get(&cpos).is_some_and(|proto| proto.wanted)
+    }
+    /// number of chunks in the pipeline including the ones only read by neighbors
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.protos.len()
+    }
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.protos.is_empty()
+    }
+    /// makes sure the chunk at `cpos` runs up to `stage` and its neighbors up to
+    /// the stage before
+    fn require(&mut self, cpos: ChunkPos, stage: GenStage) {
+        if self.failed.contains(&cpos) {
+            return;
+        }
+        let proto = self.protos.entry(cpos).or_insert_with(|| ProtoChunk {
+            levels: Vec::new(),
+            data: None,
+            target: None,
+            running: false,
+            wanted: false,
+        });
+        let old_target = proto.target;
+        if old_target >= Some(stage) {
+            return;
+        }
+        proto.target = Some(stage);
+        let finished = proto.levels.len();
+        // every new stage needs its neighbors at the stage before
+        let stages = GenStage::ALL.into_iter().skip(finished);
+        for new in stages.take_while(|new| *new <= stage) {
+            let Some(prev) = new.prev() else {
+                continue;
+            };
+            if Some(new) <= old_target {
+                continue;
+            }
+            let radius = self.radius[new as usize];
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    if dx != 0 || dy != 0 {
+                        let npos = ChunkPos {
+                            x: cpos.x + dx,
+                            y: cpos.y + dy,
+                            z: cpos.z,
+                        };
+                        self.require(npos, prev);
+                    }
+                }
+            }
+        }
+    }
+    /// takes up to `limit` stages whose neighbors finished the stage before, the ones
+    /// closest to the focus first
+    pub fn jobs(&mut self, limit: usize) -> Vec<StageJob<D>> {
+        let mut ready: Vec<(i64, ChunkPos, GenStage)> = self
+            .protos
+            .iter()
+            .filter(|(_, proto)| !proto.running && !proto.is_idle())
+            .filter_map(|(cpos, proto)| {
+                let next = proto.next()?;
+                self.neighbors_finished(*cpos, next)
+                    .then(|| (self.priority(*cpos), *cpos, next))
+            })
+            .collect();
+        // ties are broken by position so the order never depends on the hash map
+        ready.sort_unstable_by_key(|(priority, cpos, _)| (*priority, cpos.z, cpos.y, cpos.x));
+        ready.truncate(limit);
+        ready
+            .into_iter()
+            .map(|(_, cpos, stage)| {
+                let radius = if stage == GenStage::Terrain {
+                    0
+                } else {
+                    self.radius[stage as usize]
+                };
+                let mut neighbors = FxHashMap::default();
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let npos = ChunkPos {
+                            x: cpos.x + dx,
+                            y: cpos.y + dy,
+                            z: cpos.z,
+                        };
+                        // failed neighbors are left out
+                        if npos != cpos
+                            && let Some(blocks) = self
+                                .protos
+                                .get(&npos)
+                                .and_then(|proto| proto.levels.get(stage as usize - 1))
+                        {
+                            neighbors.insert(npos, blocks.clone());
+                        }
+                    }
+                }
+                let proto = self.protos.get_mut(&cpos).unwrap();
+                proto.running = true;
+                StageJob {
+                    stage,
+                    region: ChunkRegion {
+                        cpos,
+                        blocks: proto
+                            .levels
+                            .last()
+                            .map_or_else(ChunkBlocks::default, |blocks| (**blocks).clone()),
+                        neighbors,
+                    },
+                    data: proto.data.clone(),
+                }
+            })
+            .collect()
+    }
+    /// returns if every neighbor `stage` reads has finished the stage before or failed,
+    /// the stage runs without the failed ones
+    fn neighbors_finished(&self, cpos: ChunkPos, stage: GenStage) -> bool {
+        if stage == GenStage::Terrain {
+            return true;
+        }
+        let radius = self.radius[stage as usize];
+        (-radius..=radius).all(|dy| {
+            (-radius..=radius).all(|dx| {
+                let npos = ChunkPos {
+                    x: cpos.x + dx,
+                    y: cpos.y + dy,
+                    z: cpos.z,
+                };
+                self.failed.contains(&npos)
+                    || self
+                        .protos
+                        .get(&npos)
+                        .is_some_and(|proto| proto.levels.len() >= stage as usize)
+            })
+        })
+    }
+    /// gives up on the chunk at `cpos` after one of its stages failed
+    pub fn fail(&mut self, cpos: ChunkPos) {
+        self.failed.insert(cpos);
+        if let Some(proto) = self.protos.get_mut(&cpos) {
+            proto.running = false;
+            proto.wanted = false;
+            proto.target = None;
+        }
+        self.cleanup();
+    }
+    /// returns if the chunk at `cpos` failed and is not generated again
+    #[inline(always)]
+    pub fn has_failed(&self, cpos: ChunkPos) -> bool {
+        self.failed.contains(&cpos)
+    }
+    /// stores the result of a stage, returns the chunk once a requested chunk is finished
+    pub fn complete(&mut self, result: StageResult<D>) -> Option<(ChunkPos, Chunk)> {
+        let StageResult {
+            cpos,
+            stage,
+            blocks,
+            data,
+        } = result;
+        let proto = self.protos.get_mut(&cpos)?;
+        proto.running = false;
+        if proto.levels.len() != stage as usize {
+            return None;
+        }
+        proto.levels.push(Arc::new(blocks));
+        proto.data = Some(data);
+        if stage != GenStage::LAST {
+            return None;
+        }
+        let finished = proto.wanted.then(|| {
+            proto.wanted = false;
+            (cpos, proto.chunk())
+        });
+        self.cleanup();
+        finished
+    }
+    /// drops finished chunks no waiting chunk can read anymore
+    fn cleanup(&mut self) {
+        let reach: i32 = self.radius[1..].iter().sum();
+        let waiting: Vec<ChunkPos> = self
+            .protos
+            .iter()
+            .filter(|(_, proto)| !proto.is_idle())
+            .map(|(cpos, _)| *cpos)
+            .collect();
+        self.protos.retain(|cpos, proto| {
+            !proto.is_idle()
+                || waiting.iter().any(|w| {
+                    w.z == cpos.z && (w.x - cpos.x).abs() <= reach && (w.y - cpos.y).abs() <= reach
+                })
+        });
+    }
+}
+
+/// a message for the generator threads
+#[derive(Debug)]
+pub enum ChunkRequest<D> {
+    /// runs a generation stage of a chunk
+    Stage(StageJob<D>),
+    /// ends the thread that receives it
+    Stop,
+}
+
+/// a message from the generator threads
+#[derive(Debug)]
+pub enum ChunkResponse<D> {
+    Done(StageResult<D>),
+    /// the stage panicked, the thread ended after sending this
+    Failed(ChunkError),
+}
+
+/// a generation stage that panicked on a generator thread
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkError {
+    pub cpos: ChunkPos,
+    pub stage: GenStage,
+    pub message: String,
+}
+
+/// chunk generation of a `BlockMap` without the chunk data type of its generator
+pub trait ChunkGeneration: Send + Sync {
+    /// queues the finished chunk at `cpos`, returns it right away if it is done
+    fn request(&mut self, cpos: ChunkPos) -> Option<Chunk>;
+    /// returns if the chunk at `cpos` is queued and not done yet
+    fn is_requested(&self, cpos: ChunkPos) -> bool;
+    /// returns if generating the chunk at `cpos` failed, it is not generated again
+    /// while it stays in the chunks kept by `retain_requests`
+    fn has_failed(&self, cpos: ChunkPos) -> bool;
+    /// chunks whose stages are handed out first, the closest ones first
+    fn set_focus(&mut self, focus: Vec<ChunkPos>);
+    /// cancels every queued chunk `keep` returns false for and forgets the failed ones
+    fn retain_requests(&mut self, keep: &dyn Fn(ChunkPos) -> bool);
+    /// number of generator threads kept running
+    fn set_threads(&mut self, threads: usize);
+    /// hands out the ready stages and returns the chunks that were finished
+    fn update(&mut self) -> Vec<(ChunkPos, Chunk)>;
+    /// stages that failed since the last call
+    fn errors(&mut self) -> Vec<ChunkError>;
+}
+
+/// runs a stage with the generator of the `ChunkWorkers`
+type RunStageFn<D> = dyn Fn(StageJob<D>) -> StageResult<D> + Send + Sync;
+
+/// generator threads, one per core
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |threads| threads.get())
+}
+
+/// runs the stages of a `ChunkPipeline` on a pool of generator threads, threads whose
+/// stage panicked are replaced and all of them are stopped when it is dropped
+pub struct ChunkWorkers<D> {
+    pub pipeline: ChunkPipeline<D>,
+    /// every thread returns whether it ended because of `ChunkRequest::Stop`
+    pub handles: Vec<JoinHandle<bool>>,
+    /// generator threads the pool keeps running
+    pub threads: usize,
+    /// queued `ChunkRequest::Stop`s no thread took yet
+    stopping: usize,
+    run: Arc<RunStageFn<D>>,
+    request_tx: Sender<ChunkRequest<D>>,
+    /// kept to start new threads and to clear the queue on drop
+    request_rx: Receiver<ChunkRequest<D>>,
+    response_tx: Sender<ChunkResponse<D>>,
+    response_rx: Receiver<ChunkResponse<D>>,
+    errors: Vec<ChunkError>,
+}
+impl<D: Send + Sync + 'static> ChunkWorkers<D> {
+    /// spawns `threads` generator threads sharing `generator`
+    pub fn spawn<G>(generator: G, threads: usize) -> Self
+    where
+        G: TerrainGenerator<ChunkData = D> + Send + Sync + 'static,
+    {
+        // threaded chunk generation channels, responses never block a thread
+        let (request_tx, request_rx) = bounded::<ChunkRequest<D>>(64);
+        let (response_tx, response_rx) = unbounded::<ChunkResponse<D>>();
+
+        // shared generator
+        let generator = Arc::new(generator);
+        let pipeline = ChunkPipeline::new(&*generator);
+        let run: Arc<RunStageFn<D>> = Arc::new(move |job: StageJob<D>| job.run(&*generator));
+
+        let mut workers = Self {
+            pipeline,
+            handles: Vec::with_capacity(threads),
+            threads: threads.max(1),
+            stopping: 0,
+            run,
+            request_tx,
+            request_rx,
+            response_tx,
+            response_rx,
+            errors: Vec::new(),
+        };
+        workers.supervise();
+        workers
+    }
+    /// starts a generator thread that runs stages until it is stopped or one panics
+    fn spawn_thread(&self) -> JoinHandle<bool> {
+        let run = self.run.clone();
+        let request_rx = self.request_rx.clone();
+        let response_tx = self.response_tx.clone();
+        thread::spawn(move || {
+            while let Ok(request) = request_rx.recv() {
+                let job = match request {
+                    ChunkRequest::Stage(job) => job,
+                    ChunkRequest::Stop => return true,
+                };
+                let (cpos, stage) = (job.region.cpos, job.stage);
+                match panic::catch_unwind(AssertUnwindSafe(|| run(job))) {
+                    Ok(result) => {
+                        let _ = response_tx.send(ChunkResponse::Done(result));
+                    }
+                    Err(payload) => {
+                        let _ = response_tx.send(ChunkResponse::Failed(ChunkError {
+                            cpos,
+                            stage,
+                            message: panic_message(&*payload),
+                        }));
+                        // `supervise` starts a new thread in its place
+                        return false;
+                    }
+                }
+            }
+            true
+        })
+    }
+    /// joins the threads that ended and starts or stops threads until `threads` run
+    fn supervise(&mut self) {
+        let (ended, running): (Vec<_>, Vec<_>) = std::mem::take(&mut self.handles)
+            .into_iter()
+            .partition(|handle| handle.is_finished());
+        self.handles = running;
+        for handle in ended {
+            if let Ok(true) = handle.join() {
+                self.stopping = self.stopping.saturating_sub(1);
+            }
+        }
+        // every queued stop ends one thread, even if the threads that were meant to
+        // take them panicked, so `threads` are left once all of them are taken
+        let target = self.threads + self.stopping;
+        for _ in self.handles.len()..target {
+            let handle = self.spawn_thread();
+            self.handles.push(handle);
+        }
+        for _ in target..self.handles.len() {
+            if self.request_tx.try_send(ChunkRequest::Stop).is_err() {
+                break;
+            }
+            self.stopping += 1;
+        }
+    }
+}
+impl<D: Send + Sync + 'static> ChunkGeneration for ChunkWorkers<D> {
+    #[inline(always)]
+    fn request(&mut self, cpos: ChunkPos) -> Option<Chunk> {
+        self.pipeline.request(cpos)
+    }
+    #[inline(always)]
+    fn is_requested(&self, cpos: ChunkPos) -> bool {
+        self.pipeline.is_requested(cpos)
+    }
+    #[inline(always)]
+    fn has_failed(&self, cpos: ChunkPos) -> bool {
+        self.pipeline.has_failed(cpos)
+    }
+    #[inline(always)]
+    fn set_focus(&mut self, focus: Vec<ChunkPos>) {
+        self.pipeline.set_focus(focus);
+    }
+    #[inline(always)]
+    fn retain_requests(&mut self, keep: &dyn Fn(ChunkPos) -> bool) {
+        self.pipeline.retain_requests(keep);
+    }
+    fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+        self.supervise();
+    }
+    fn update(&mut self) -> Vec<(ChunkPos, Chunk)> {
+        // receive finished stages from channel
+        let mut finished = Vec::new();
+        while let Ok(response) = self.response_rx.try_recv() {
+            match response {
+                ChunkResponse::Done(result) => finished.extend(self.pipeline.complete(result)),
+                ChunkResponse::Failed(error) => {
+                    self.pipeline.fail(error.cpos);
+                    self.errors.push(error);
+                }
+            }
+        }
+        self.supervise();
+        // hand out the stages whose neighbors are ready, only as many as fit in the
+        // channel so the frame never waits on the generator threads
+        let free = self.request_tx.capacity().unwrap_or(usize::MAX) - self.request_tx.len();
+        for job in self.pipeline.jobs(free) {
+            // never full since this is the only sender
+            let _ = self.request_tx.try_send(ChunkRequest::Stage(job));
+        }
+        finished
+    }
+    fn errors(&mut self) -> Vec<ChunkError> {
+        std::mem::take(&mut self.errors)
+    }
+}
+impl<D> Drop for ChunkWorkers<D> {
+    /// stops every generator thread once it finished its current stage
+    fn drop(&mut self) {
+        // disconnecting the only sender ends the threads once the queue is empty
+        let (disconnected, _) = bounded(0);
+        drop(std::mem::replace(&mut self.request_tx, disconnected));
+        // queued stages are thrown away so the threads stop right away
+        while self.request_rx.try_recv().is_ok() {}
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// the text a panic was started with
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// carving marks the corner of every chunk and decoration copies the corners of
+    /// the neighbors it reads into its own chunk
+    struct NeighborGenerator;
+    impl NeighborGenerator {
+        fn corner(cpos: ChunkPos) -> Block {
+            Block(10 + (cpos.x * 3 + cpos.y).rem_euclid(7) as u8)
+        }
+        fn copied(dx: i32, dy: i32) -> ChunkBlockPos {
+            ChunkBlockPos {
+                x: (dx + 1) as usize,
+                y: (dy + 1) as usize,
+                z: 1,
+            }
+        }
+    }
+    impl TerrainGenerator for NeighborGenerator {
+        type ChunkData = ();
+        fn seed(&self) -> u32 {
+            0
+        }
+        fn set_seed(&mut self, _seed: u32) {}
+        fn get_chunk_data(&self, _cpos: ChunkPos) {}
+        fn stage_radius(&self, stage: GenStage) -> i32 {
+            (stage == GenStage::Decoration) as i32
+        }
+        fn gen_stage(&self, stage: GenStage, region: &mut ChunkRegion, _chunk_data: &()) {
+            match stage {
+                GenStage::Terrain => region.blocks = ChunkBlocks::Single(Block::ROCK),
+                GenStage::Carving => {
+                    region.set(ChunkBlockPos::default(), Self::corner(region.cpos))
+                }
+                GenStage::Decoration => {
+                    for dy in -1..=1 {
+                        for dx in -1..=1 {
+                            let npos = ChunkPos {
+                                x: region.cpos.x + dx,
+                                y: region.cpos.y + dy,
+                                z: region.cpos.z,
+                            };
+                            let corner = ChunkBlockPos::default().to_world(npos);
+                            let block = region.get_block(corner).unwrap_or(Block::AIR);
+                            region.set(Self::copied(dx, dy), block);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        fn gen_block(&self, _wpos: WorldBlockPos, _chunk_data: &()) -> Block {
+            Block::AIR
+        }
+        fn ground_height(&self, _x: i32, _y: i32) -> Option<i32> {
+            None
+        }
+    }
+
+    /// runs every job the pipeline hands out, `fails` chunks are failed instead
+    fn run(pipeline: &mut ChunkPipeline<()>, fails: &[ChunkPos]) -> FxHashMap<ChunkPos, Chunk> {
+        let mut chunks = FxHashMap::default();
+        loop {
+            let jobs = pipeline.jobs(usize::MAX);
+            if jobs.is_empty() {
+                return chunks;
+            }
+            // later chunks first so nothing depends on the order of the jobs
+            for job in jobs.into_iter().rev() {
+                if fails.contains(&job.region.cpos) {
+                    pipeline.fail(job.region.cpos);
+                } else if let Some((cpos, chunk)) = pipeline.complete(job.run(&NeighborGenerator)) {
+                    chunks.insert(cpos, chunk);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stages_read_finished_neighbors() {
+        let mut pipeline = ChunkPipeline::new(&NeighborGenerator);
+        let cpos = ChunkPos { x: 2, y: -1, z: 0 };
+        assert!(pipeline.request(cpos).is_none());
+        // the 8 neighbors are generated up to carving to be read
+        assert_eq!(pipeline.len(), 9);
+        let chunks = run(&mut pipeline, &[]);
+        assert_eq!(chunks.len(), 1);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let npos = ChunkPos {
+                    x: cpos.x + dx,
+                    y: cpos.y + dy,
+                    z: cpos.z,
+                };
+                assert_eq!(
+                    chunks[&cpos].get(NeighborGenerator::copied(dx, dy)),
+                    Some(NeighborGenerator::corner(npos))
+                );
+            }
+        }
+        // nothing waits for the neighbors anymore
+        assert!(pipeline.is_empty());
+    }
+
+    #[test]
+    fn failed_neighbors_are_left_out() {
+        let mut pipeline = ChunkPipeline::new(&NeighborGenerator);
+        let cpos = ChunkPos::default();
+        let failed = ChunkPos { x: 1, y: 0, z: 0 };
+        pipeline.request(cpos);
+        let chunks = run(&mut pipeline, &[failed]);
+        assert!(pipeline.has_failed(failed));
+        assert_eq!(
+            chunks[&cpos].get(NeighborGenerator::copied(1, 0)),
+            Some(Block::AIR)
+        );
+        assert_eq!(
+            chunks[&cpos].get(NeighborGenerator::copied(-1, 0)),
+            Some(NeighborGenerator::corner(ChunkPos { x: -1, y: 0, z: 0 }))
+        );
+        assert!(pipeline.is_empty());
+    }
+
+    #[test]
+    fn cancelled_requests_drop_their_neighbors() {
+        let mut pipeline = ChunkPipeline::new(&NeighborGenerator);
+        let cpos = ChunkPos::default();
+        pipeline.request(cpos);
+        assert!(pipeline.is_requested(cpos));
+        pipeline.retain_requests(|_| false);
+        assert!(!pipeline.is_requested(cpos));
+        assert!(pipeline.is_empty());
+        assert!(run(&mut pipeline, &[]).is_empty());
+    }
+
+    #[test]
+    fn failed_chunks_are_retried_after_leaving_the_kept_area() {
+        let mut pipeline = ChunkPipeline::new(&NeighborGenerator);
+        let cpos = ChunkPos { x: 5, y: 5, z: 0 };
+        pipeline.request(cpos);
+        run(&mut pipeline, &[cpos]);
+        assert!(pipeline.has_failed(cpos));
+        // still kept, it stays failed so it is not requested every frame
+        pipeline.retain_requests(|npos| npos == cpos);
+        assert!(pipeline.has_failed(cpos));
+        assert!(pipeline.request(cpos).is_none());
+        assert!(!pipeline.is_requested(cpos));
+
+        pipeline.retain_requests(|_| false);
+        assert!(!pipeline.has_failed(cpos));
+        pipeline.request(cpos);
+        assert!(run(&mut pipeline, &[]).contains_key(&cpos));
+    }
+}
@@ -0,0 +1,383 @@
+use super::{
+    generator::{OverWorldChunkData, OverWorldColumn, OverWorldGenerator, TerrainGenerator},
+    map::Block,
+    noise_source::GetNoise,
+    pipeline::ChunkRegion,
+    units::*,
+};
+use rustc_hash::FxHashMap;
+
+/// one kind of prop scattered over the ground by the `OverWorldGenerator`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScatterRule {
+    pub block: Block,
+    /// minimum distance in blocks between two props of the rule, also the side of the
+    /// grid cells that hold one candidate each
+    pub spacing: i32,
+    /// needs the 8 blocks around it free, like trees
+    pub wide: bool,
+    /// only grows within this distance of a block placed by an earlier rule,
+    /// e.g. mushrooms in the shade of trees
+    pub near: Option<(Block, i32)>,
+}
+impl ScatterRule {
+    pub fn new(block: Block, spacing: i32) -> Self {
+        Self {
+            block,
+            spacing,
+            wide: false,
+            near: None,
+        }
+    }
+    pub fn with_wide(mut self) -> Self {
+        self.wide = true;
+        self
+    }
+    pub fn with_near(mut self, block: Block, distance: i32) -> Self {
+        self.near = Some((block, distance));
+        self
+    }
+}
+
+/// places the `OverWorldGenerator::scatter` rules on a chunk, every prop only depends
+/// on the seed and the columns around it so chunks agree on props near their border
+pub struct Scatter<'a> {
+    generator: &'a OverWorldGenerator,
+    columns: FxHashMap<(i32, i32), OverWorldColumn>,
+    accepted: FxHashMap<(usize, i32, i32), Option<WorldBlockPos>>,
+}
+impl<'a> Scatter<'a> {
+    pub fn new(
+        generator: &'a OverWorldGenerator,
+        cpos: ChunkPos,
+        chunk_data: &OverWorldChunkData,
+    ) -> Self {
+        let origin = ChunkBlockPos { x: 0, y: 0, z: 0 }.to_world(cpos);
+        let mut columns = FxHashMap::default();
+        for y in origin.y..origin.y + CHUNK_SIZE as i32 {
+            for x in origin.x..origin.x + CHUNK_SIZE as i32 {
+                columns.insert((x, y), chunk_data.column(x, y));
+            }
+        }
+        Self {
+            generator,
+            columns,
+            accepted: FxHashMap::default(),
+        }
+    }
+    /// sets every accepted prop inside the chunk of `region`, wide props that would
+    /// hang over lower ground are left out
+    pub fn place(&mut self, region: &mut ChunkRegion) {
+        let origin = ChunkBlockPos { x: 0, y: 0, z: 0 }.to_world(region.cpos);
+        for (rule, def) in self.generator.scatter.iter().enumerate() {
+            let s = def.spacing;
+            for cy in origin.y.div_euclid(s)..=(origin.y + CHUNK_SIZE as i32 - 1).div_euclid(s) {
+                for cx in origin.x.div_euclid(s)..=(origin.x + CHUNK_SIZE as i32 - 1).div_euclid(s)
+                {
+                    if let Some(pos) = self.accepted(rule, cx, cy)
+                        && ChunkPos::from(pos) == region.cpos
+                        && (!def.wide || has_ground_around(region, pos))
+                    {
+                        region.set_block(pos, def.block);
+                    }
+                }
+            }
+        }
+    }
+
+    /// column with its shore computed like `get_chunk_data` does
+    fn column(&mut self, x: i32, y: i32) -> OverWorldColumn {
+        if let Some(column) = self.columns.get(&(x, y)) {
+            return *column;
+        }
+        let mut column = self.generator.column(x, y);
+        column.shore = column.water.is_none()
+            && (-1..=1)
+                .any(|dy| (-1..=1).any(|dx| self.generator.column(x + dx, y + dy).water.is_some()));
+        self.columns.insert((x, y), column);
+        column
+    }
+    /// z a prop grows at on top of the column, only low dry ground away from the water
+    fn ground(&mut self, x: i32, y: i32) -> Option<i32> {
+        let column = self.column(x, y);
+        if column.water.is_some() || column.shore || column.height <= 0.05 {
+            return None;
+        }
+        match OverWorldGenerator::ground_top(column.height)? {
+            top @ 1..=2 => Some(top + 1),
+            _ => None,
+        }
+    }
+    /// returns if the column has no ground or water at `z`
+    fn is_free(&mut self, x: i32, y: i32, z: i32) -> bool {
+        let column = self.column(x, y);
+        OverWorldGenerator::ground_top(column.height).is_none_or(|top| top < z)
+            && column.water.is_none_or(|level| level < z)
+    }
+
+    /// position and priority of the candidate of a grid cell if its rule grows there,
+    /// before checking the spacing to the other candidates
+    fn candidate(&mut self, rule: usize, cx: i32, cy: i32) -> Option<(WorldBlockPos, u64)> {
+        let def = &self.generator.scatter[rule];
+        let (block, s, wide, near) = (def.block, def.spacing, def.wide, def.near);
+        let h = cell_hash(self.generator.seed(), rule, cx, cy);
+        let x = cx * s + (h % s as u64) as i32;
+        let y = cy * s + ((h >> 16) % s as u64) as i32;
+        let z = self.ground(x, y)?;
+        let pos = WorldBlockPos { x, y, z };
+        // patches of flora where the plants noise is high
+        let column = self.column(x, y);
+        let scale = self.generator.plants_scale;
+        let patches = 1.0
+            + self
+                .generator
+                .plants
+                .get([x as f64 * scale, y as f64 * scale]);
+        let chance =
+            self.generator.biomes[column.biome].flora_chance(block) * patches.clamp(0.0, 2.0);
+        if ((h >> 32) as u32 as f64 / u32::MAX as f64) >= chance {
+            return None;
+        }
+        if wide && !around(x, y, 1).all(|(nx, ny)| (nx, ny) == (x, y) || self.is_free(nx, ny, z)) {
+            return None;
+        }
+        // props of earlier rules keep their spot, wide ones also their sides
+        for earlier in 0..rule {
+            let reach = if wide || self.generator.scatter[earlier].wide {
+                1
+            } else {
+                0
+            };
+            if self.any_accepted(earlier, pos, reach, false) {
+                return None;
+            }
+        }
+        if let Some((near_block, distance)) = near {
+            let earlier =
+                (0..rule).find(|earlier| self.generator.scatter[*earlier].block == near_block)?;
+            if !self.any_accepted(earlier, pos, distance, true) {
+                return None;
+            }
+        }
+        Some((pos, splitmix(h)))
+    }
+    /// returns the prop of a grid cell if its candidate has the highest priority of all
+    /// candidates closer than the spacing of the rule
+    pub fn accepted(&mut self, rule: usize, cx: i32, cy: i32) -> Option<WorldBlockPos> {
+        if let Some(accepted) = self.accepted.get(&(rule, cx, cy)) {
+            return *accepted;
+        }
+        let accepted = self.candidate(rule, cx, cy).and_then(|(pos, priority)| {
+            let s = self.generator.scatter[rule].spacing;
+            let beaten = around(cx, cy, 1).any(|(ncx, ncy)| {
+                (ncx, ncy) != (cx, cy)
+                    && self
+                        .candidate(rule, ncx, ncy)
+                        .is_some_and(|(other, other_priority)| {
+                            let (dx, dy) = (other.x - pos.x, other.y - pos.y);
+                            dx * dx + dy * dy < s * s
+                                && (other_priority, ncx, ncy) > (priority, cx, cy)
+                        })
+            });
+            (!beaten).then_some(pos)
+        });
+        self.accepted.insert((rule, cx, cy), accepted);
+        accepted
+    }
+    /// returns if a prop of `rule` is within `distance` of `pos`, chebyshev distance
+    /// unless `round`
+    fn any_accepted(
+        &mut self,
+        rule: usize,
+        pos: WorldBlockPos,
+        distance: i32,
+        round: bool,
+    ) -> bool {
+        let s = self.generator.scatter[rule].spacing;
+        let (min_x, max_x) = (
+            (pos.x - distance).div_euclid(s),
+            (pos.x + distance).div_euclid(s),
+        );
+        let (min_y, max_y) = (
+            (pos.y - distance).div_euclid(s),
+            (pos.y + distance).div_euclid(s),
+        );
+        (min_y..=max_y).any(|cy| {
+            (min_x..=max_x).any(|cx| {
+                self.accepted(rule, cx, cy).is_some_and(|other| {
+                    let (dx, dy) = ((other.x - pos.x).abs(), (other.y - pos.y).abs());
+                    if round {
+                        dx * dx + dy * dy <= distance * distance
+                    } else {
+                        dx <= distance && dy <= distance
+                    }
+                })
+            })
+        })
+    }
+}
+
+/// returns if the blocks under the sides of `pos` are ground, sides in chunks the
+/// region does not read count as ground
+fn has_ground_around(region: &ChunkRegion, pos: WorldBlockPos) -> bool {
+    around(pos.x, pos.y, 1).all(|(x, y)| {
+        region
+            .get_block(WorldBlockPos { x, y, z: pos.z - 1 })
+            .is_none_or(|below| below != Block::AIR && below != Block::WATER)
+    })
+}
+/// every position within `distance` of `x`, `y` on both axes
+#[inline(always)]
+fn around(x: i32, y: i32, distance: i32) -> impl Iterator<Item = (i32, i32)> {
+    (y - distance..=y + distance)
+        .flat_map(move |ny| (x - distance..=x + distance).map(move |nx| (nx, ny)))
+}
+/// deterministic random bits of a grid cell of a rule
+#[inline(always)]
+fn cell_hash(seed: u32, rule: usize, cx: i32, cy: i32) -> u64 {
+    let mut h = (cx as u32 as u64) | ((cy as u32 as u64) << 32);
+    h ^= (seed as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    h ^= (rule as u64 + 1).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    splitmix(h)
+}
+#[inline(always)]
+fn splitmix(mut h: u64) -> u64 {
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::generator::gen_area;
+
+    fn generator() -> OverWorldGenerator {
+        let mut generator = OverWorldGenerator::default();
+        generator.set_seed(42);
+        generator
+    }
+    fn rule(generator: &OverWorldGenerator, block: Block) -> usize {
+        generator
+            .scatter
+            .iter()
+            .position(|rule| rule.block == block)
+            .unwrap()
+    }
+    /// accepted props of `rule` in the grid cells overlapping chunks `start..=end`
+    fn props(
+        generator: &OverWorldGenerator,
+        rule: usize,
+        start: ChunkPos,
+        end: ChunkPos,
+    ) -> Vec<WorldBlockPos> {
+        let s = generator.scatter[rule].spacing;
+        let chunk_data = generator.get_chunk_data(start);
+        let mut scatter = Scatter::new(generator, start, &chunk_data);
+        let min = ChunkBlockPos::default().to_world(start);
+        let max = ChunkBlockPos::default().to_world(end);
+        let mut props = Vec::new();
+        for cy in min.y.div_euclid(s)..=(max.y + CHUNK_SIZE as i32 - 1).div_euclid(s) {
+            for cx in min.x.div_euclid(s)..=(max.x + CHUNK_SIZE as i32 - 1).div_euclid(s) {
+                props.extend(scatter.accepted(rule, cx, cy));
+            }
+        }
+        props
+    }
+
+    #[test]
+    fn neighbors_agree_on_their_border() {
+        let generator = generator();
+        let (left, right) = (
+            ChunkPos { x: 1, y: -1, z: 0 },
+            ChunkPos { x: 2, y: -1, z: 0 },
+        );
+        let origin = ChunkBlockPos::default().to_world(right);
+        let mut checked = 0;
+        for (rule, def) in generator.scatter.iter().enumerate() {
+            // every cell a prop on either side of the border could come from
+            let s = def.spacing;
+            let data = [left, right].map(|cpos| generator.get_chunk_data(cpos));
+            let mut scatters = [
+                Scatter::new(&generator, left, &data[0]),
+                Scatter::new(&generator, right, &data[1]),
+            ];
+            for cy in origin.y.div_euclid(s)..=(origin.y + CHUNK_SIZE as i32 - 1).div_euclid(s) {
+                for cx in (origin.x - s - 1).div_euclid(s)..=(origin.x + s).div_euclid(s) {
+                    let [a, b] = &mut scatters;
+                    let accepted = a.accepted(rule, cx, cy);
+                    assert_eq!(accepted, b.accepted(rule, cx, cy), "rule {rule} {cx},{cy}");
+                    checked += accepted.is_some() as usize;
+                }
+            }
+        }
+        assert!(checked > 0);
+
+        // and generating them one by one gives the same blocks as together
+        let together = gen_area(&generator, left, right);
+        for cpos in [left, right] {
+            let alone = gen_area(&generator, cpos, cpos);
+            for z in 0..CHUNK_HEIGHT {
+                for y in 0..CHUNK_SIZE {
+                    for x in 0..CHUNK_SIZE {
+                        let pos = ChunkBlockPos { x, y, z };
+                        assert_eq!(alone[&cpos].get(pos), together[&cpos].get(pos), "{pos:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn props_keep_their_spacing() {
+        let generator = generator();
+        let (start, end) = (
+            ChunkPos { x: -2, y: -2, z: 0 },
+            ChunkPos { x: 2, y: 2, z: 0 },
+        );
+        for (rule, def) in generator.scatter.iter().enumerate() {
+            let props = props(&generator, rule, start, end);
+            assert!(!props.is_empty(), "rule {rule}");
+            for (i, a) in props.iter().enumerate() {
+                for b in &props[i + 1..] {
+                    let (dx, dy) = (a.x - b.x, a.y - b.y);
+                    assert!(
+                        dx * dx + dy * dy >= def.spacing * def.spacing,
+                        "{a:?} {b:?} of rule {rule}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mushrooms_grow_near_trees() {
+        let generator = generator();
+        let (start, end) = (
+            ChunkPos { x: -2, y: -2, z: 0 },
+            ChunkPos { x: 2, y: 2, z: 0 },
+        );
+        let mushroom = rule(&generator, Block::MUSHROOM);
+        let Some((_, distance)) = generator.scatter[mushroom].near else {
+            panic!("mushrooms grow anywhere");
+        };
+        // trees just outside the area can shade its mushrooms as well
+        let trees = props(
+            &generator,
+            rule(&generator, Block::TREE),
+            ChunkPos { x: -3, y: -3, z: 0 },
+            ChunkPos { x: 3, y: 3, z: 0 },
+        );
+        let mushrooms = props(&generator, mushroom, start, end);
+        assert!(!mushrooms.is_empty());
+        for pos in mushrooms {
+            assert!(
+                trees.iter().any(|tree| {
+                    let (dx, dy) = (tree.x - pos.x, tree.y - pos.y);
+                    dx * dx + dy * dy <= distance * distance
+                }),
+                "mushroom at {pos:?} has no tree"
+            );
+        }
+    }
+}
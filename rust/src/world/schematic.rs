@@ -0,0 +1,353 @@
+use super::{
+    map::{Block, BlockMap, BlockSet},
+    palette::BlockPalette,
+    units::*,
+};
+use crate::serial::{ReadExt, WriteExt, expect_magic};
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// magic bytes of a schematic file
+pub const SCHEMATIC_MAGIC: &[u8; 4] = b"PCSC";
+/// current schematic file version
+pub const SCHEMATIC_VERSION: u8 = 1;
+/// most blocks a schematic file may hold, larger sizes are treated as corrupt
+pub const MAX_SCHEMATIC_VOLUME: usize = 1 << 24;
+
+/// a copied box of blocks that can be pasted somewhere else
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schematic {
+    pub width: usize,
+    pub depth: usize,
+    pub height: usize,
+    /// blocks in x, y, z order like `ChunkBlockPos::idx`
+    pub blocks: Vec<Block>,
+}
+/// rotation and mirroring applied when pasting a `Schematic`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SchematicTransform {
+    /// clockwise quarter turns around the z axis
+    pub rotation: u8,
+    /// mirror along the x axis before rotating
+    pub mirror_x: bool,
+    /// mirror along the y axis before rotating
+    pub mirror_y: bool,
+    /// keep the existing blocks where the schematic has `Air`
+    pub skip_air: bool,
+}
+
+impl Schematic {
+    pub fn new_empty(width: usize, depth: usize, height: usize) -> Self {
+        Self {
+            width,
+            depth,
+            height,
+            blocks: vec![Block::AIR; width * depth * height],
+        }
+    }
+
+    /// copies the blocks in the box between the corners `a` and `b` (inclusive),
+    /// blocks of missing chunks are copied as `Air`
+    pub fn copy(map: &BlockMap, a: WorldBlockPos, b: WorldBlockPos) -> Self {
+        let min = WorldBlockPos {
+            x: a.x.min(b.x),
+            y: a.y.min(b.y),
+            z: a.z.min(b.z),
+        };
+        let max = WorldBlockPos {
+            x: a.x.max(b.x),
+            y: a.y.max(b.y),
+            z: a.z.max(b.z),
+        };
+        let mut schematic = Self::new_empty(
+            (max.x - min.x + 1) as usize,
+            (max.y - min.y + 1) as usize,
+            (max.z - min.z + 1) as usize,
+        );
+        for z in 0..schematic.height {
+            for y in 0..schematic.depth {
+                for x in 0..schematic.width {
+                    let wpos = WorldBlockPos {
+                        x: min.x + x as i32,
+                        y: min.y + y as i32,
+                        z: min.z + z as i32,
+                    };
+                    schematic.set(x, y, z, map.get_block(wpos).unwrap_or_default());
+                }
+            }
+        }
+        schematic
+    }
+
+    #[inline(always)]
+    fn idx(&self, x: usize, y: usize, z: usize) -> usize {
+        z * (self.width * self.depth) + y * self.width + x
+    }
+    /// returns `Block` at the local position
+    #[inline(always)]
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Option<Block> {
+        if x < self.width && y < self.depth && z < self.height {
+            Some(self.blocks[self.idx(x, y, z)])
+        } else {
+            None
+        }
+    }
+    /// sets `Block` at the local position
+    #[inline(always)]
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: Block) {
+        if x < self.width && y < self.depth && z < self.height {
+            let idx = self.idx(x, y, z);
+            self.blocks[idx] = block;
+        }
+    }
+
+    /// returns a copy rotated clockwise by `quarter_turns` around the z axis
+    pub fn rotated(&self, quarter_turns: u8) -> Self {
+        let mut schematic = self.clone();
+        for _ in 0..quarter_turns % 4 {
+            let mut rotated = Self::new_empty(schematic.depth, schematic.width, schematic.height);
+            for z in 0..schematic.height {
+                for y in 0..schematic.depth {
+                    for x in 0..schematic.width {
+                        let block = schematic.get(x, y, z).unwrap_or_default();
+                        rotated.set(schematic.depth - 1 - y, x, z, block);
+                    }
+                }
+            }
+            schematic = rotated;
+        }
+        schematic
+    }
+    /// returns a copy mirrored along the x and/or y axis
+    pub fn mirrored(&self, mirror_x: bool, mirror_y: bool) -> Self {
+        let mut mirrored = Self::new_empty(self.width, self.depth, self.height);
+        for z in 0..self.height {
+            for y in 0..self.depth {
+                for x in 0..self.width {
+                    let sx = if mirror_x { self.width - 1 - x } else { x };
+                    let sy = if mirror_y { self.depth - 1 - y } else { y };
+                    mirrored.set(x, y, z, self.get(sx, sy, z).unwrap_or_default());
+                }
+            }
+        }
+        mirrored
+    }
+    /// returns a copy with mirroring and then rotation of `SchematicTransform` applied
+    pub fn transformed(&self, transform: SchematicTransform) -> Self {
+        self.mirrored(transform.mirror_x, transform.mirror_y)
+            .rotated(transform.rotation)
+    }
+
+    /// pastes the schematic with its lowest corner at `origin` and updates the
+    /// `BlockNeighbors` of every touched chunk once, chunks that are not loaded are
+    /// loaded or generated first so the paste lands on top of their blocks
+    pub fn paste(&self, map: &mut BlockMap, origin: WorldBlockPos, transform: SchematicTransform) {
+        let schematic = self.transformed(transform);
+        if schematic.blocks.is_empty() {
+            return;
+        }
+        let end = WorldBlockPos {
+            x: origin.x + schematic.width as i32 - 1,
+            y: origin.y + schematic.depth as i32 - 1,
+            z: origin.z + schematic.height as i32 - 1,
+        };
+        map.require_area(origin.into(), end.into());
+        let mut touched: FxHashSet<ChunkPos> = FxHashSet::default();
+        for z in 0..schematic.height {
+            for y in 0..schematic.depth {
+                for x in 0..schematic.width {
+                    let block = schematic.get(x, y, z).unwrap_or_default();
+                    if transform.skip_air && block == Block::AIR {
+                        continue;
+                    }
+                    let wpos = WorldBlockPos {
+                        x: origin.x + x as i32,
+                        y: origin.y + y as i32,
+                        z: origin.z + z as i32,
+                    };
+                    touched.insert(map.set_block_raw(wpos, block));
+                }
+            }
+        }
+        // autotiling of the touched chunks and their borders
+        let mut update: FxHashSet<ChunkPos> = FxHashSet::default();
+        for cpos in touched {
+            for dz in -1..=1 {
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        update.insert(ChunkPos {
+                            x: cpos.x + dx,
+                            y: cpos.y + dy,
+                            z: cpos.z + dz,
+                        });
+                    }
+                }
+            }
+        }
+        for cpos in update {
+            if map.get_chunk(cpos).is_some() {
+                map.update_chunk_neighbors(cpos);
+            }
+        }
+    }
+
+    /// writes the schematic with a palette of the used block names
+    pub fn write(&self, w: &mut impl Write, blockset: &BlockSet) -> io::Result<()> {
+        let mut palette = BlockPalette { names: Vec::new() };
+        let mut ids: FxHashMap<Block, u8> = FxHashMap::default();
+        for block in self.blocks.iter() {
+            ids.entry(*block).or_insert_with(|| {
+                palette.names.push(blockset.name(*block).to_string());
+                (palette.names.len() - 1) as u8
+            });
+        }
+        w.write_all(SCHEMATIC_MAGIC)?;
+        w.write_u8(SCHEMATIC_VERSION)?;
+        w.write_u32(self.width as u32)?;
+        w.write_u32(self.depth as u32)?;
+        w.write_u32(self.height as u32)?;
+        palette.write(w)?;
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        for block in self.blocks.iter() {
+            encoder.write_u8(ids[block])?;
+        }
+        w.write_bytes(&encoder.finish()?)
+    }
+    /// reads a schematic with the blocks of `blockset`, unknown block names become `Block::UNKNOWN`
+    pub fn read(r: &mut impl Read, blockset: &BlockSet) -> io::Result<Self> {
+        expect_magic(r, SCHEMATIC_MAGIC)?;
+        let version = r.read_u8()?;
+        if version != SCHEMATIC_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported schematic version {version}"),
+            ));
+        }
+        let width = r.read_u32()? as usize;
+        let depth = r.read_u32()? as usize;
+        let height = r.read_u32()? as usize;
+        width
+            .checked_mul(depth)
+            .and_then(|area| area.checked_mul(height))
+            .filter(|volume| *volume <= MAX_SCHEMATIC_VOLUME)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("schematic of {width}x{depth}x{height} blocks is too large"),
+                )
+            })?;
+        let remap = BlockPalette::read(r)?.remap(&blockset.palette());
+        let data = r.read_bytes()?;
+        let mut decoder = DeflateDecoder::new(data.as_slice());
+        let mut schematic = Self::new_empty(width, depth, height);
+        for block in schematic.blocks.iter_mut() {
+            *block = remap.get(decoder.read_u8()?);
+        }
+        Ok(schematic)
+    }
+    pub fn save(&self, path: impl AsRef<Path>, blockset: &BlockSet) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w, blockset)?;
+        w.flush()
+    }
+    pub fn load(path: impl AsRef<Path>, blockset: &BlockSet) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?), blockset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{generator::CaveGenerator, storage::RegionStore};
+
+    fn cave_map(blockset: &BlockSet, dir: &Path) -> BlockMap {
+        BlockMap::new(blockset.clone(), CaveGenerator::default(), 7)
+            .with_threads(1)
+            .with_storage(RegionStore::open(dir, blockset.palette()).unwrap())
+    }
+
+    #[test]
+    fn paste_keeps_the_blocks_of_unloaded_chunks() {
+        let blockset = BlockSet::normal();
+        let dir = std::env::temp_dir().join(format!("picocraft-paste-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // an edit that was saved and unloaded before the paste
+        let edit = WorldBlockPos { x: 3, y: 4, z: 1 };
+        let mut edited = cave_map(&blockset, &dir);
+        edited.require_chunk(edit.into());
+        edited.set_block(edit, Block::DIAMOND);
+        edited.save().unwrap();
+        drop(edited);
+
+        // crosses the border between two chunks that are not loaded
+        let mut schematic = Schematic::new_empty(2, 1, 1);
+        schematic.set(0, 0, 0, Block::RUBY);
+        schematic.set(1, 0, 0, Block::RUBY);
+        let origin = WorldBlockPos { x: 15, y: 4, z: 1 };
+        let mut map = cave_map(&blockset, &dir);
+        schematic.paste(&mut map, origin, SchematicTransform::default());
+
+        let mut generated = BlockMap::new(blockset.clone(), CaveGenerator::default(), 7);
+        for x in [0, 1] {
+            let cpos = ChunkPos { x, y: 0, z: 0 };
+            generated.require_chunk(cpos);
+            assert!(map.get_chunk(cpos).unwrap().dirty);
+            for idx in 0..CHUNK_VOLUME {
+                let pos = ChunkBlockPos {
+                    x: idx % CHUNK_SIZE,
+                    y: idx / CHUNK_SIZE % CHUNK_SIZE,
+                    z: idx / (CHUNK_SIZE * CHUNK_SIZE),
+                }
+                .to_world(cpos);
+                let expected = if pos == edit {
+                    Block::DIAMOND
+                } else if pos.y == origin.y && pos.z == origin.z && (15..=16).contains(&pos.x) {
+                    Block::RUBY
+                } else {
+                    generated.get_block(pos).unwrap()
+                };
+                assert_eq!(map.get_block(pos), Some(expected), "{pos:?}");
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn round_trip() {
+        let blockset = BlockSet::normal();
+        let mut schematic = Schematic::new_empty(3, 2, 2);
+        schematic.set(0, 0, 0, Block::ROCK);
+        schematic.set(2, 1, 1, Block::ROCK);
+        let mut buf = Vec::new();
+        schematic.write(&mut buf, &blockset).unwrap();
+        let read = Schematic::read(&mut buf.as_slice(), &blockset).unwrap();
+        assert_eq!(read, schematic);
+        for len in 0..buf.len() {
+            assert!(Schematic::read(&mut &buf[..len], &blockset).is_err());
+        }
+    }
+
+    #[test]
+    fn oversized_schematic_fails() {
+        let blockset = BlockSet::normal();
+        for (width, depth, height) in [(u32::MAX, u32::MAX, u32::MAX), (4096, 4096, 2)] {
+            let mut buf = Vec::new();
+            buf.write_all(SCHEMATIC_MAGIC).unwrap();
+            buf.write_u8(SCHEMATIC_VERSION).unwrap();
+            buf.write_u32(width).unwrap();
+            buf.write_u32(depth).unwrap();
+            buf.write_u32(height).unwrap();
+            let err = Schematic::read(&mut buf.as_slice(), &blockset).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
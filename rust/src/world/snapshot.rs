@@ -0,0 +1,480 @@
+use super::{
+    chunk_blocks::ChunkBlocks,
+    generator::{CaveGenerator, OverWorldGenerator, TerrainGenerator},
+    map::{Block, BlockSet, Chunk, OVERWORLD, UNDERGROUND},
+    noise_graph::NoiseGraph,
+    pipeline::{ChunkGeneration, ChunkPipeline, ChunkWorkers},
+    structure::StructureSet,
+    units::*,
+};
+use rustc_hash::FxHashMap;
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+    thread,
+};
+
+/// first line of a snapshot file
+pub const SNAPSHOT_HEADER: &str = "picocraft snapshot 2";
+/// character of every block id in a snapshot dump
+const BLOCK_CHARS: &[u8; 64] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ+/";
+
+/// generated blocks of an area, used to notice when the generator output changes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub map: String,
+    pub seed: u32,
+    pub start: ChunkPos,
+    pub end: ChunkPos,
+    /// every chunk of the area level by level and row by row
+    pub chunks: Vec<(ChunkPos, ChunkBlocks)>,
+}
+/// a block that differs between two snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockChange {
+    pub pos: WorldBlockPos,
+    pub old: Block,
+    pub new: Block,
+}
+/// how a chunk differs between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChunkDiff {
+    Changed(ChunkPos, Vec<BlockChange>),
+    Missing(ChunkPos),
+    Added(ChunkPos),
+}
+
+/// stable FNV-1a hash of the block ids of a chunk in flat index order
+pub fn chunk_hash(blocks: &ChunkBlocks) -> u64 {
+    fnv((0..CHUNK_VOLUME).map(|idx| blocks.get(idx).0), FNV_OFFSET)
+}
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+#[inline(always)]
+fn fnv(bytes: impl IntoIterator<Item = u8>, hash: u64) -> u64 {
+    bytes.into_iter().fold(hash, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// generates the chunks between `start` and `end` (inclusive) one stage at a time
+pub fn gen_area_serial<G: TerrainGenerator>(
+    generator: &G,
+    start: ChunkPos,
+    end: ChunkPos,
+) -> FxHashMap<ChunkPos, Chunk> {
+    let mut pipeline = ChunkPipeline::new(generator);
+    let mut chunks = FxHashMap::default();
+    for z in start.z..=end.z {
+        for y in start.y..=end.y {
+            for x in start.x..=end.x {
+                pipeline.request(ChunkPos { x, y, z });
+            }
+        }
+    }
+    while let Some(job) = pipeline.jobs(1).pop() {
+        chunks.extend(pipeline.complete(job.run(generator)));
+    }
+    chunks
+}
+/// generates the chunks between `start` and `end` (inclusive) on `threads` generator
+/// threads the way `BlockMap::new` does
+pub fn gen_area_threaded<G>(
+    mut generator: G,
+    seed: u32,
+    threads: usize,
+    start: ChunkPos,
+    end: ChunkPos,
+) -> FxHashMap<ChunkPos, Chunk>
+where
+    G: TerrainGenerator + Send + Sync + 'static,
+    G::ChunkData: 'static,
+{
+    generator.set_seed(seed);
+    let mut workers = ChunkWorkers::spawn(generator, threads);
+    let mut chunks = FxHashMap::default();
+    let mut count = 0;
+    for z in start.z..=end.z {
+        for y in start.y..=end.y {
+            for x in start.x..=end.x {
+                let cpos = ChunkPos { x, y, z };
+                if let Some(chunk) = workers.request(cpos) {
+                    chunks.insert(cpos, chunk);
+                }
+                count += 1;
+            }
+        }
+    }
+    let mut failed = 0;
+    while chunks.len() + failed < count {
+        chunks.extend(workers.update());
+        for error in workers.errors() {
+            eprintln!(
+                "failed to generate chunk {:?} at {:?}: {}",
+                error.cpos, error.stage, error.message
+            );
+            failed += 1;
+        }
+        thread::yield_now();
+    }
+    chunks
+}
+
+/// generates an area of `map` with the generator settings shipped with the game, once
+/// one stage at a time and once on `threads` generator threads, returns the first run
+/// and how the threaded one differs from it
+pub fn generate_map(
+    map: &str,
+    seed: u32,
+    start: ChunkPos,
+    end: ChunkPos,
+    blockset: &BlockSet,
+    threads: usize,
+) -> io::Result<(Snapshot, Vec<ChunkDiff>)> {
+    match map {
+        OVERWORLD => Ok(compare(
+            || OverWorldGenerator {
+                structures: StructureSet::normal(blockset),
+                terrain: Some(NoiseGraph::normal()),
+                ..Default::default()
+            },
+            map,
+            seed,
+            start,
+            end,
+            threads,
+        )),
+        UNDERGROUND => Ok(compare(
+            CaveGenerator::default,
+            map,
+            seed,
+            start,
+            end,
+            threads,
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown map {map:?}, expected {OVERWORLD} or {UNDERGROUND}"),
+        )),
+    }
+}
+fn compare<G>(
+    make: impl Fn() -> G,
+    map: &str,
+    seed: u32,
+    start: ChunkPos,
+    end: ChunkPos,
+    threads: usize,
+) -> (Snapshot, Vec<ChunkDiff>)
+where
+    G: TerrainGenerator + Send + Sync + 'static,
+    G::ChunkData: 'static,
+{
+    let mut generator = make();
+    generator.set_seed(seed);
+    let serial = Snapshot::new(
+        map,
+        seed,
+        start,
+        end,
+        &gen_area_serial(&generator, start, end),
+    );
+    let threaded = Snapshot::new(
+        map,
+        seed,
+        start,
+        end,
+        &gen_area_threaded(make(), seed, threads, start, end),
+    );
+    let diffs = serial.diff(&threaded);
+    (serial, diffs)
+}
+
+impl Snapshot {
+    /// takes the blocks of every chunk between `start` and `end`, missing chunks are left out
+    pub fn new(
+        map: &str,
+        seed: u32,
+        start: ChunkPos,
+        end: ChunkPos,
+        chunks: &FxHashMap<ChunkPos, Chunk>,
+    ) -> Self {
+        let mut blocks = Vec::new();
+        for z in start.z..=end.z {
+            for y in start.y..=end.y {
+                for x in start.x..=end.x {
+                    let cpos = ChunkPos { x, y, z };
+                    if let Some(chunk) = chunks.get(&cpos) {
+                        blocks.push((cpos, chunk.blocks().clone()));
+                    }
+                }
+            }
+        }
+        Self {
+            map: map.to_string(),
+            seed,
+            start,
+            end,
+            chunks: blocks,
+        }
+    }
+    /// stable hash of every chunk and its position
+    pub fn hash(&self) -> u64 {
+        self.chunks.iter().fold(FNV_OFFSET, |hash, (cpos, blocks)| {
+            let bytes = [
+                cpos.x.to_le_bytes(),
+                cpos.y.to_le_bytes(),
+                cpos.z.to_le_bytes(),
+            ];
+            let hash = fnv(bytes.into_iter().flatten(), hash);
+            fnv(chunk_hash(blocks).to_le_bytes(), hash)
+        })
+    }
+    /// turns the blocks back into chunks, e.g. for `render_top_down`
+    pub fn to_chunks(&self) -> FxHashMap<ChunkPos, Chunk> {
+        self.chunks
+            .iter()
+            .map(|(cpos, blocks)| {
+                let mut chunk = Chunk::new_empty();
+                chunk.set_blocks(blocks.clone());
+                (*cpos, chunk)
+            })
+            .collect()
+    }
+    /// returns every chunk of `new` that differs from this snapshot
+    pub fn diff(&self, new: &Snapshot) -> Vec<ChunkDiff> {
+        let new_chunks: FxHashMap<ChunkPos, &ChunkBlocks> = new
+            .chunks
+            .iter()
+            .map(|(cpos, blocks)| (*cpos, blocks))
+            .collect();
+        let mut diffs = Vec::new();
+        for (cpos, old) in self.chunks.iter() {
+            let Some(new) = new_chunks.get(cpos) else {
+                diffs.push(ChunkDiff::Missing(*cpos));
+                continue;
+            };
+            let changes: Vec<BlockChange> = (0..CHUNK_VOLUME)
+                .filter(|idx| old.get(*idx) != new.get(*idx))
+                .map(|idx| BlockChange {
+                    pos: pos_of(idx).to_world(*cpos),
+                    old: old.get(idx),
+                    new: new.get(idx),
+                })
+                .collect();
+            if !changes.is_empty() {
+                diffs.push(ChunkDiff::Changed(*cpos, changes));
+            }
+        }
+        for (cpos, _) in new.chunks.iter() {
+            if !self.chunks.iter().any(|(old, _)| old == cpos) {
+                diffs.push(ChunkDiff::Added(*cpos));
+            }
+        }
+        diffs
+    }
+
+    /// writes the snapshot as text, every chunk is a line with its hash followed by one
+    /// line of blocks per z level
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "{SNAPSHOT_HEADER}")?;
+        writeln!(w, "map {}", self.map)?;
+        writeln!(w, "seed {}", self.seed)?;
+        writeln!(
+            w,
+            "area {} {} {} {} {} {}",
+            self.start.x, self.start.y, self.start.z, self.end.x, self.end.y, self.end.z
+        )?;
+        writeln!(w, "hash {:016x}", self.hash())?;
+        for (cpos, blocks) in self.chunks.iter() {
+            writeln!(
+                w,
+                "chunk {} {} {} {:016x}",
+                cpos.x,
+                cpos.y,
+                cpos.z,
+                chunk_hash(blocks)
+            )?;
+            let mut line = String::with_capacity(CHUNK_SIZE * CHUNK_SIZE);
+            for idx in 0..CHUNK_VOLUME {
+                let block = blocks.get(idx);
+                let c = BLOCK_CHARS.get(block.0 as usize).ok_or_else(|| {
+                    invalid(format!("block id {} does not fit in a snapshot", block.0))
+                })?;
+                line.push(*c as char);
+                if line.len() == CHUNK_SIZE * CHUNK_SIZE {
+                    writeln!(w, "{line}")?;
+                    line.clear();
+                }
+            }
+        }
+        Ok(())
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut w = io::BufWriter::new(fs::File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()
+    }
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Self::from_text(&text)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+    }
+    /// parses the text written by `write`, chunks whose blocks do not match their hash
+    /// are rejected
+    pub fn from_text(text: &str) -> io::Result<Self> {
+        let mut lines = text.lines().enumerate().map(|(n, line)| (n + 1, line));
+        let mut next = |key: &str| -> io::Result<(usize, Vec<&str>)> {
+            let (n, line) = lines
+                .next()
+                .ok_or_else(|| invalid(format!("missing {key:?} line")))?;
+            Ok((n, words(n, line, key)?))
+        };
+        let (_, header) = next("picocraft")?;
+        if header.join(" ") != SNAPSHOT_HEADER["picocraft ".len()..] {
+            return Err(invalid("not a picocraft snapshot"));
+        }
+        let (_, map) = next("map")?;
+        let (n, seed) = next("seed")?;
+        let seed = parse(&seed, 0, n)?;
+        let (n, area) = next("area")?;
+        let start = ChunkPos {
+            x: parse(&area, 0, n)?,
+            y: parse(&area, 1, n)?,
+            z: parse(&area, 2, n)?,
+        };
+        let end = ChunkPos {
+            x: parse(&area, 3, n)?,
+            y: parse(&area, 4, n)?,
+            z: parse(&area, 5, n)?,
+        };
+        let (n, hash) = next("hash")?;
+        let hash = parse_hash(&hash, 0, n)?;
+        let mut snapshot = Self {
+            map: map.join(" "),
+            seed,
+            start,
+            end,
+            chunks: Vec::new(),
+        };
+        while let Some((n, line)) = lines.next() {
+            let chunk = words(n, line, "chunk")?;
+            let cpos = ChunkPos {
+                x: parse(&chunk, 0, n)?,
+                y: parse(&chunk, 1, n)?,
+                z: parse(&chunk, 2, n)?,
+            };
+            let mut blocks = ChunkBlocks::default();
+            for z in 0..CHUNK_HEIGHT {
+                let (n, line) = lines
+                    .next()
+                    .ok_or_else(|| invalid(format!("chunk on line {n} is cut off")))?;
+                if line.len() != CHUNK_SIZE * CHUNK_SIZE {
+                    return Err(invalid(format!(
+                        "line {n}: expected {} blocks",
+                        CHUNK_SIZE * CHUNK_SIZE
+                    )));
+                }
+                for (i, c) in line.bytes().enumerate() {
+                    let id = BLOCK_CHARS.iter().position(|b| *b == c).ok_or_else(|| {
+                        invalid(format!("line {n}: unknown block {:?}", c as char))
+                    })?;
+                    blocks.set(z * CHUNK_SIZE * CHUNK_SIZE + i, Block(id as u8));
+                }
+            }
+            if chunk_hash(&blocks) != parse_hash(&chunk, 3, n)? {
+                return Err(invalid(format!(
+                    "line {n}: blocks do not match the chunk hash"
+                )));
+            }
+            snapshot.chunks.push((cpos, blocks));
+        }
+        if snapshot.hash() != hash {
+            return Err(invalid("chunks do not match the snapshot hash"));
+        }
+        Ok(snapshot)
+    }
+}
+
+/// splits a line into its words after checking that it starts with `key`
+fn words<'a>(n: usize, line: &'a str, key: &str) -> io::Result<Vec<&'a str>> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some(key) {
+        return Err(invalid(format!("line {n}: expected {key:?}")));
+    }
+    Ok(words.collect())
+}
+/// position of a flat chunk index
+#[inline(always)]
+fn pos_of(idx: usize) -> ChunkBlockPos {
+    ChunkBlockPos {
+        x: idx % CHUNK_SIZE,
+        y: idx / CHUNK_SIZE % CHUNK_SIZE,
+        z: idx / (CHUNK_SIZE * CHUNK_SIZE),
+    }
+}
+fn parse<T: std::str::FromStr>(words: &[&str], i: usize, line: usize) -> io::Result<T> {
+    words
+        .get(i)
+        .and_then(|word| word.parse().ok())
+        .ok_or_else(|| invalid(format!("line {line}: expected a number")))
+}
+fn parse_hash(words: &[&str], i: usize, line: usize) -> io::Result<u64> {
+    words
+        .get(i)
+        .and_then(|word| u64::from_str_radix(word, 16).ok())
+        .ok_or_else(|| invalid(format!("line {line}: expected a hash")))
+}
+
+#[inline(always)]
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::pipeline::default_threads;
+
+    #[test]
+    fn stored_snapshots_match() {
+        let blockset = BlockSet::normal();
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("snapshots");
+        let mut checked = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "snap") {
+                continue;
+            }
+            let stored = Snapshot::load(&path).unwrap();
+            let (snapshot, threaded) = generate_map(
+                &stored.map,
+                stored.seed,
+                stored.start,
+                stored.end,
+                &blockset,
+                default_threads(),
+            )
+            .unwrap();
+            assert_eq!(
+                threaded,
+                Vec::new(),
+                "{}: threaded run differs",
+                path.display()
+            );
+            assert_eq!(
+                stored.diff(&snapshot),
+                Vec::new(),
+                "{} changed",
+                path.display()
+            );
+            checked += 1;
+        }
+        assert!(checked > 0, "no snapshots found");
+    }
+}
@@ -0,0 +1,465 @@
+use super::{
+    chunk_blocks::ChunkBlocks,
+    map::Chunk,
+    palette::{BlockPalette, BlockRemap},
+    units::*,
+};
+use crate::serial::{ReadExt, WriteExt, expect_magic};
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+};
+
+/// static region size in chunks
+pub const REGION_SIZE: i32 = 32;
+/// magic bytes of a region file
+pub const REGION_MAGIC: &[u8; 4] = b"PCRG";
+/// current region file version
+pub const REGION_VERSION: u8 = 4;
+
+/// represents the position of a region of `REGION_SIZE`x`REGION_SIZE` chunk columns
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RegionPos {
+    pub x: i32,
+    pub y: i32,
+}
+impl From<ChunkPos> for RegionPos {
+    #[inline(always)]
+    fn from(c: ChunkPos) -> Self {
+        RegionPos {
+            x: c.x.div_euclid(REGION_SIZE),
+            y: c.y.div_euclid(REGION_SIZE),
+        }
+    }
+}
+
+/// compressed chunks of one region file
+#[derive(Debug, Default)]
+pub struct Region {
+    pub chunks: FxHashMap<ChunkPos, Vec<u8>>,
+    /// read from an older version or with other block ids, has to be written again
+    pub migrated: bool,
+}
+
+/// stores chunks compressed in region files inside `dir`
+#[derive(Debug)]
+pub struct RegionStore {
+    pub dir: PathBuf,
+    pub regions: FxHashMap<RegionPos, Region>,
+    pub dirty: FxHashSet<RegionPos>,
+    /// block names of the running game
+    pub palette: BlockPalette,
+}
+impl RegionStore {
+    /// opens the region store at `dir`, creating the directory if necessary,
+    /// stored blocks are migrated to `palette`
+    pub fn open(dir: impl Into<PathBuf>, palette: BlockPalette) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            regions: FxHashMap::default(),
+            dirty: FxHashSet::default(),
+            palette,
+        })
+    }
+
+    /// returns the file path of the region at `RegionPos`
+    #[inline(always)]
+    pub fn region_path(&self, rpos: RegionPos) -> PathBuf {
+        self.dir.join(format!("r.{}.{}.pcr", rpos.x, rpos.y))
+    }
+
+    /// returns the cached region at `RegionPos`, reading it from disk if needed
+    pub fn region(&mut self, rpos: RegionPos) -> io::Result<&mut Region> {
+        if !self.regions.contains_key(&rpos) {
+            let path = self.region_path(rpos);
+            let region = if path.exists() {
+                Region::read(&mut BufReader::new(File::open(&path)?), &self.palette)?
+            } else {
+                Region::default()
+            };
+            // rewrite migrated regions so they are only migrated once
+            if region.migrated {
+                self.dirty.insert(rpos);
+            }
+            self.regions.insert(rpos, region);
+        }
+        Ok(self.regions.get_mut(&rpos).unwrap())
+    }
+
+    /// loads the `Chunk` at `ChunkPos` if it was stored before
+    pub fn load_chunk(&mut self, cpos: ChunkPos) -> io::Result<Option<Chunk>> {
+        let region = self.region(cpos.into())?;
+        let Some(data) = region.chunks.get(&cpos) else {
+            return Ok(None);
+        };
+        Chunk::decompress(data, &BlockRemap::identity()).map(Some)
+    }
+
+    /// loads every stored `Chunk` between `start` and `end` (inclusive)
+    pub fn load_area(
+        &mut self,
+        start: ChunkPos,
+        end: ChunkPos,
+    ) -> io::Result<FxHashMap<ChunkPos, Chunk>> {
+        let mut chunks = FxHashMap::default();
+        for z in start.z..=end.z {
+            for y in start.y..=end.y {
+                for x in start.x..=end.x {
+                    let cpos = ChunkPos { x, y, z };
+                    if let Some(chunk) = self.load_chunk(cpos)? {
+                        chunks.insert(cpos, chunk);
+                    }
+                }
+            }
+        }
+        Ok(chunks)
+    }
+
+    /// stores the `Chunk` at `ChunkPos` in its cached region
+    pub fn save_chunk(&mut self, cpos: ChunkPos, chunk: &Chunk) -> io::Result<()> {
+        let data = chunk.compress()?;
+        let rpos: RegionPos = cpos.into();
+        self.region(rpos)?.chunks.insert(cpos, data);
+        self.dirty.insert(rpos);
+        Ok(())
+    }
+
+    /// writes all modified regions to disk
+    pub fn flush(&mut self) -> io::Result<()> {
+        for rpos in std::mem::take(&mut self.dirty) {
+            let Some(region) = self.regions.get(&rpos) else {
+                continue;
+            };
+            // write to a temporary file first so a crash never leaves half a region
+            let path = self.region_path(rpos);
+            let tmp = path.with_extension("tmp");
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            region.write(&mut file, &self.palette)?;
+            file.into_inner()?.sync_all()?;
+            fs::rename(tmp, path)?;
+        }
+        Ok(())
+    }
+
+    /// drops cached regions that are written to disk and not kept by `keep`
+    pub fn unload(&mut self, keep: impl Fn(RegionPos) -> bool) {
+        self.regions
+            .retain(|rpos, _| self.dirty.contains(rpos) || keep(*rpos));
+    }
+
+    /// approximate memory used by the cached regions in bytes
+    pub fn mem_size(&self) -> usize {
+        self.regions
+            .values()
+            .flat_map(|region| region.chunks.values())
+            .map(|data| std::mem::size_of::<(ChunkPos, Vec<u8>)>() + data.len())
+            .sum()
+    }
+}
+
+impl Region {
+    /// reads a region and migrates its chunks to the block ids of `current`
+    pub fn read(r: &mut impl Read, current: &BlockPalette) -> io::Result<Self> {
+        expect_magic(r, REGION_MAGIC)?;
+        let version = r.read_u8()?;
+        let palette = match version {
+            // version 1 had no palette
+            1 => BlockPalette::legacy(),
+            2..=4 => BlockPalette::read(r)?,
+            version => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported region version {version}"),
+                ));
+            }
+        };
+        let remap = palette.remap(current);
+        // stacked chunks may not fit the old count
+        let count = if version < 4 {
+            r.read_u16()? as u32
+        } else {
+            r.read_u32()?
+        };
+        let mut chunks = FxHashMap::default();
+        for _ in 0..count {
+            let x = r.read_i32()?;
+            let y = r.read_i32()?;
+            // versions before 4 only had the chunks at z 0
+            let z = if version < 4 { 0 } else { r.read_i32()? };
+            let mut data = r.read_bytes()?;
+            // versions before 3 stored one raw id per block
+            if version < 3 {
+                data = Chunk::decompress_raw(&data, &remap)?.compress()?;
+            } else if !remap.is_identity() {
+                data = Chunk::decompress(&data, &remap)?.compress()?;
+            }
+            chunks.insert(ChunkPos { x, y, z }, data);
+        }
+        Ok(Self {
+            chunks,
+            migrated: version < REGION_VERSION || !remap.is_identity(),
+        })
+    }
+    /// writes the region with the block names of `palette`
+    pub fn write(&self, w: &mut impl Write, palette: &BlockPalette) -> io::Result<()> {
+        w.write_all(REGION_MAGIC)?;
+        w.write_u8(REGION_VERSION)?;
+        palette.write(w)?;
+        w.write_u32(self.chunks.len() as u32)?;
+        for (cpos, data) in self.chunks.iter() {
+            w.write_i32(cpos.x)?;
+            w.write_i32(cpos.y)?;
+            w.write_i32(cpos.z)?;
+            w.write_bytes(data)?;
+        }
+        Ok(())
+    }
+}
+
+impl Chunk {
+    /// encodes the `ChunkBlocks` of the chunk as deflate compressed bytes
+    pub fn compress(&self) -> io::Result<Vec<u8>> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        self.blocks().write(&mut encoder)?;
+        encoder.finish()
+    }
+    /// decodes a chunk from `compress`ed bytes, mapping saved ids with `remap`
+    pub fn decompress(data: &[u8], remap: &BlockRemap) -> io::Result<Self> {
+        let mut decoder = DeflateDecoder::new(data);
+        let mut chunk = Chunk::new_empty();
+        chunk.set_blocks(ChunkBlocks::read(&mut decoder, remap)?);
+        chunk.dirty = false;
+        Ok(chunk)
+    }
+    /// encodes the chunk with one raw id per block like region versions before 3
+    pub fn compress_raw(&self) -> io::Result<Vec<u8>> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        for z in 0..CHUNK_HEIGHT {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let block = self.get(ChunkBlockPos { x, y, z }).unwrap_or_default();
+                    encoder.write_u8(block.into())?;
+                }
+            }
+        }
+        encoder.finish()
+    }
+    /// decodes a chunk from `compress_raw`ed bytes, mapping saved ids with `remap`
+    pub fn decompress_raw(data: &[u8], remap: &BlockRemap) -> io::Result<Self> {
+        let mut decoder = DeflateDecoder::new(data);
+        let mut chunk = Chunk::new_empty();
+        for z in 0..CHUNK_HEIGHT {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let block = remap.get(decoder.read_u8()?);
+                    chunk.set(ChunkBlockPos { x, y, z }, block);
+                }
+            }
+        }
+        chunk.dirty = false;
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::map::Block;
+
+    /// empty directory for a test store
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("picocraft-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// chunk with a few different blocks so it is paletted
+    fn test_chunk(seed: usize) -> Chunk {
+        let mut chunk = Chunk::new_empty();
+        for idx in (seed..CHUNK_VOLUME).step_by(7 + seed) {
+            let pos = ChunkBlockPos {
+                x: idx % CHUNK_SIZE,
+                y: idx / CHUNK_SIZE % CHUNK_SIZE,
+                z: idx / (CHUNK_SIZE * CHUNK_SIZE),
+            };
+            chunk.set(pos, Block((idx % 5) as u8 + 1));
+        }
+        chunk
+    }
+
+    #[test]
+    fn regions_round_trip() {
+        let dir = test_dir("regions-round-trip");
+        let positions = [
+            ChunkPos { x: 0, y: 0, z: 0 },
+            ChunkPos { x: -1, y: 3, z: -1 },
+            ChunkPos { x: 31, y: 31, z: 2 },
+            ChunkPos {
+                x: 32,
+                y: -33,
+                z: 0,
+            },
+        ];
+        let mut store = RegionStore::open(&dir, BlockPalette::legacy()).unwrap();
+        for (seed, cpos) in positions.iter().enumerate() {
+            store.save_chunk(*cpos, &test_chunk(seed)).unwrap();
+        }
+        store.flush().unwrap();
+        assert!(store.dirty.is_empty());
+
+        let mut store = RegionStore::open(&dir, BlockPalette::legacy()).unwrap();
+        for (seed, cpos) in positions.iter().enumerate() {
+            let chunk = store.load_chunk(*cpos).unwrap().unwrap();
+            assert_eq!(chunk.blocks(), test_chunk(seed).blocks());
+            assert!(!chunk.dirty);
+        }
+        assert!(
+            store
+                .load_chunk(ChunkPos { x: 5, y: 5, z: 0 })
+                .unwrap()
+                .is_none()
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn truncated_region_fails() {
+        let mut region = Region::default();
+        region.chunks.insert(
+            ChunkPos { x: 1, y: 2, z: 0 },
+            test_chunk(1).compress().unwrap(),
+        );
+        let mut buf = Vec::new();
+        region.write(&mut buf, &BlockPalette::legacy()).unwrap();
+        assert!(Region::read(&mut buf.as_slice(), &BlockPalette::legacy()).is_ok());
+        for len in 0..buf.len() {
+            assert!(Region::read(&mut &buf[..len], &BlockPalette::legacy()).is_err());
+        }
+        let mut corrupt = buf.clone();
+        corrupt[4] = REGION_VERSION + 1;
+        let err = Region::read(&mut corrupt.as_slice(), &BlockPalette::legacy()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// compares block by block, the palettes may differ in unused entries
+    fn assert_same_blocks(a: &Chunk, b: &Chunk) {
+        for idx in 0..CHUNK_VOLUME {
+            assert_eq!(a.blocks().get(idx), b.blocks().get(idx), "block {idx}");
+        }
+    }
+
+    /// `test_chunk` with every block id mapped by `map`
+    fn mapped_chunk(seed: usize, map: impl Fn(Block) -> Block) -> Chunk {
+        let chunk = test_chunk(seed);
+        let mut mapped = Chunk::new_empty();
+        for idx in 0..CHUNK_VOLUME {
+            let pos = ChunkBlockPos {
+                x: idx % CHUNK_SIZE,
+                y: idx / CHUNK_SIZE % CHUNK_SIZE,
+                z: idx / (CHUNK_SIZE * CHUNK_SIZE),
+            };
+            mapped.set(pos, map(chunk.get(pos).unwrap()));
+        }
+        mapped
+    }
+
+    #[test]
+    fn regions_migrate_to_the_current_palette() {
+        let current = BlockPalette::legacy();
+        // the saved ids are the legacy ids in reverse
+        let mut saved = current.clone();
+        saved.names.reverse();
+        let last = current.names.len() as u8 - 1;
+        let cpos = ChunkPos { x: 3, y: -4, z: 1 };
+        let mut region = Region::default();
+        region.chunks.insert(
+            cpos,
+            mapped_chunk(3, |block| Block(last - block.0))
+                .compress()
+                .unwrap(),
+        );
+        let mut buf = Vec::new();
+        region.write(&mut buf, &saved).unwrap();
+
+        let read = Region::read(&mut buf.as_slice(), &current).unwrap();
+        let chunk = Chunk::decompress(&read.chunks[&cpos], &BlockRemap::identity()).unwrap();
+        assert_same_blocks(&chunk, &test_chunk(3));
+    }
+
+    #[test]
+    fn migrated_regions_are_written_again() {
+        let dir = test_dir("migrated-regions");
+        fs::create_dir_all(&dir).unwrap();
+        let cpos = ChunkPos { x: 1, y: 1, z: 0 };
+        let mut saved = BlockPalette::legacy();
+        saved.names.swap(0, 1);
+        let mut region = Region::default();
+        region
+            .chunks
+            .insert(cpos, test_chunk(5).compress().unwrap());
+        let mut store = RegionStore::open(&dir, BlockPalette::legacy()).unwrap();
+        let path = store.region_path(cpos.into());
+        region
+            .write(&mut File::create(&path).unwrap(), &saved)
+            .unwrap();
+
+        let migrated = store.load_chunk(cpos).unwrap().unwrap();
+        assert!(store.dirty.contains(&cpos.into()));
+        store.flush().unwrap();
+
+        let mut store = RegionStore::open(&dir, BlockPalette::legacy()).unwrap();
+        assert_same_blocks(&store.load_chunk(cpos).unwrap().unwrap(), &migrated);
+        assert!(store.dirty.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn raw_regions_of_old_versions_migrate() {
+        let cpos = ChunkPos { x: -7, y: 2, z: 0 };
+        let raw = test_chunk(4).compress_raw().unwrap();
+        // version 1 had no palette, version 2 one raw id per block
+        let mut v1 = Vec::new();
+        v1.write_all(REGION_MAGIC).unwrap();
+        v1.write_u8(1).unwrap();
+        v1.write_u16(1).unwrap();
+        v1.write_i32(cpos.x).unwrap();
+        v1.write_i32(cpos.y).unwrap();
+        v1.write_bytes(&raw).unwrap();
+        let mut v2 = Vec::new();
+        v2.write_all(REGION_MAGIC).unwrap();
+        v2.write_u8(2).unwrap();
+        BlockPalette::legacy().write(&mut v2).unwrap();
+        v2.write_u16(1).unwrap();
+        v2.write_i32(cpos.x).unwrap();
+        v2.write_i32(cpos.y).unwrap();
+        v2.write_bytes(&raw).unwrap();
+
+        // a palette that gained a block in front moves every id by one
+        let mut current = BlockPalette::legacy();
+        current.names.insert(0, "new".to_string());
+        let expected = mapped_chunk(4, |block| Block(block.0 + 1));
+        for buf in [v1, v2] {
+            let read = Region::read(&mut buf.as_slice(), &current).unwrap();
+            let chunk = Chunk::decompress(&read.chunks[&cpos], &BlockRemap::identity()).unwrap();
+            assert_same_blocks(&chunk, &expected);
+        }
+    }
+
+    #[test]
+    fn truncated_chunk_data_never_decodes_other_blocks() {
+        let chunk = test_chunk(2);
+        let data = chunk.compress().unwrap();
+        // raw deflate has no checksum, the last bytes may only end the stream
+        for len in 0..data.len() {
+            if let Ok(read) = Chunk::decompress(&data[..len], &BlockRemap::identity()) {
+                assert_eq!(read.blocks(), chunk.blocks());
+            }
+        }
+        assert!(Chunk::decompress(&[], &BlockRemap::identity()).is_err());
+    }
+}
@@ -0,0 +1,490 @@
+use super::{
+    map::{Block, BlockSet},
+    pipeline::ChunkRegion,
+    units::*,
+};
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::{fs, io, path::Path};
+
+/// structures shipped with the game
+pub const DEFAULT_STRUCTURES: &str = include_str!("../../assets/structures.ron");
+
+/// where a structure is placed in its column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StructurePlacement {
+    /// on top of the ground, moved by `offset`
+    Surface,
+    /// at `offset` from the bottom of the world, only where the ground covers it
+    Underground,
+}
+
+/// a template of blocks placed by the generator, `None` keeps the generated block
+#[derive(Debug, Clone, PartialEq)]
+pub struct Structure {
+    pub name: String,
+    /// side of a placement region in chunks, every region holds at most one start
+    pub spacing: i32,
+    /// chance of a region to hold a start
+    pub chance: f64,
+    pub placement: StructurePlacement,
+    pub offset: i32,
+    pub width: usize,
+    pub depth: usize,
+    pub height: usize,
+    /// blocks in x, y, z order like `Schematic::blocks`
+    pub blocks: Vec<Option<Block>>,
+}
+/// a placed `Structure` with its lowest corner at `origin`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StructureStart {
+    /// index into `StructureSet::structures`
+    pub structure: usize,
+    pub origin: WorldBlockPos,
+    /// clockwise quarter turns around the z axis
+    pub rotation: u8,
+}
+/// every structure the generator can place
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StructureSet {
+    pub structures: Vec<Structure>,
+}
+
+/// one structure of a structure file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StructureDef {
+    name: String,
+    spacing: i32,
+    chance: f64,
+    placement: StructurePlacement,
+    #[serde(default)]
+    offset: i32,
+    /// block names of the characters in `layers`, spaces keep the generated block
+    palette: FxHashMap<char, String>,
+    /// rows of characters from the bottom layer up
+    layers: Vec<Vec<String>>,
+}
+
+impl Structure {
+    /// size in x and y after `rotation` quarter turns
+    #[inline(always)]
+    pub fn rotated_size(&self, rotation: u8) -> (usize, usize) {
+        if rotation.is_multiple_of(2) {
+            (self.width, self.depth)
+        } else {
+            (self.depth, self.width)
+        }
+    }
+    /// returns the block at a local position of the structure turned by `rotation`
+    #[inline(always)]
+    pub fn get_rotated(&self, rotation: u8, x: usize, y: usize, z: usize) -> Option<Block> {
+        let (w, d) = (self.width, self.depth);
+        // map the rotated position back to the template
+        let (sx, sy) = match rotation % 4 {
+            0 => (x, y),
+            1 => (y, d - 1 - x),
+            2 => (w - 1 - x, d - 1 - y),
+            _ => (w - 1 - y, x),
+        };
+        self.blocks[z * (w * d) + sy * w + sx]
+    }
+}
+
+impl StructureSet {
+    /// structures of `DEFAULT_STRUCTURES`
+    pub fn normal(blockset: &BlockSet) -> Self {
+        Self::from_ron(DEFAULT_STRUCTURES, blockset)
+            .expect("default structure definitions are invalid")
+    }
+    /// loads structure templates from a RON file
+    pub fn load(path: impl AsRef<Path>, blockset: &BlockSet) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Self::from_ron(&text, blockset)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+    }
+    /// parses structure templates with the block names of `blockset`
+    pub fn from_ron(text: &str, blockset: &BlockSet) -> io::Result<Self> {
+        let defs: Vec<StructureDef> =
+            ron::from_str(text).map_err(|err| invalid(err.to_string()))?;
+        let structures = defs
+            .into_iter()
+            .map(|def| {
+                let name = def.name.clone();
+                Structure::from_def(def, blockset)
+                    .map_err(|err| invalid(format!("structure {name:?}: {err}")))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { structures })
+    }
+
+    /// returns the start of `structure` in the placement region `region` if it has one
+    pub fn start(
+        &self,
+        structure: usize,
+        region: ChunkPos,
+        seed: u32,
+        ground_height: &impl Fn(i32, i32) -> Option<i32>,
+    ) -> Option<StructureStart> {
+        let def = &self.structures[structure];
+        let mut rng = region_rng(seed, structure, region);
+        if (rng() >> 11) as f64 / (1u64 << 53) as f64 >= def.chance {
+            return None;
+        }
+        let spacing = def.spacing as u64;
+        let cx = region.x * def.spacing + (rng() % spacing) as i32;
+        let cy = region.y * def.spacing + (rng() % spacing) as i32;
+        let x = cx * CHUNK_SIZE as i32 + (rng() % CHUNK_SIZE as u64) as i32;
+        let y = cy * CHUNK_SIZE as i32 + (rng() % CHUNK_SIZE as u64) as i32;
+        let rotation = (rng() % 4) as u8;
+        // the column in the middle decides the height of the whole structure
+        let (w, d) = def.rotated_size(rotation);
+        let ground = ground_height(x + w as i32 / 2, y + d as i32 / 2)?;
+        let z = match def.placement {
+            StructurePlacement::Surface => ground + def.offset,
+            StructurePlacement::Underground => {
+                if def.offset + def.height as i32 >= ground {
+                    return None;
+                }
+                def.offset
+            }
+        };
+        Some(StructureStart {
+            structure,
+            origin: WorldBlockPos { x, y, z },
+            rotation,
+        })
+    }
+    /// places the parts of every structure that reach into the chunk of `region`, starts
+    /// only depend on the seed and `ground_height` so neighbors are never waited on
+    pub fn place(
+        &self,
+        region: &mut ChunkRegion,
+        seed: u32,
+        ground_height: impl Fn(i32, i32) -> Option<i32>,
+    ) {
+        let cpos = region.cpos;
+        for (idx, def) in self.structures.iter().enumerate() {
+            // regions whose starts could reach this chunk, they span every level
+            let reach = (def.width.max(def.depth) / CHUNK_SIZE) as i32 + 1;
+            let min = ChunkPos {
+                x: (cpos.x - reach).div_euclid(def.spacing),
+                y: (cpos.y - reach).div_euclid(def.spacing),
+                z: 0,
+            };
+            let max = ChunkPos {
+                x: cpos.x.div_euclid(def.spacing),
+                y: cpos.y.div_euclid(def.spacing),
+                z: 0,
+            };
+            for ry in min.y..=max.y {
+                for rx in min.x..=max.x {
+                    let placement = ChunkPos { x: rx, y: ry, z: 0 };
+                    if let Some(start) = self.start(idx, placement, seed, &ground_height) {
+                        self.paste(&start, region);
+                    }
+                }
+            }
+        }
+    }
+    /// pastes the part of a started structure inside the chunk of `region`
+    pub fn paste(&self, start: &StructureStart, region: &mut ChunkRegion) {
+        let def = &self.structures[start.structure];
+        let (w, d) = def.rotated_size(start.rotation);
+        let chunk_origin = ChunkBlockPos { x: 0, y: 0, z: 0 }.to_world(region.cpos);
+        let x0 = (chunk_origin.x - start.origin.x).max(0);
+        let y0 = (chunk_origin.y - start.origin.y).max(0);
+        let x1 = (chunk_origin.x + CHUNK_SIZE as i32 - start.origin.x).min(w as i32);
+        let y1 = (chunk_origin.y + CHUNK_SIZE as i32 - start.origin.y).min(d as i32);
+        for z in 0..def.height {
+            let wz = start.origin.z + z as i32;
+            if wz.div_euclid(CHUNK_HEIGHT as i32) != region.cpos.z {
+                continue;
+            }
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let Some(block) = def.get_rotated(start.rotation, x as usize, y as usize, z)
+                    else {
+                        continue;
+                    };
+                    let pos = ChunkBlockPos {
+                        x: (start.origin.x + x - chunk_origin.x) as usize,
+                        y: (start.origin.y + y - chunk_origin.y) as usize,
+                        z: wz.rem_euclid(CHUNK_HEIGHT as i32) as usize,
+                    };
+                    region.set(pos, block);
+                }
+            }
+        }
+    }
+}
+
+impl Structure {
+    fn from_def(def: StructureDef, blockset: &BlockSet) -> io::Result<Self> {
+        if def.spacing < 1 {
+            return Err(invalid("spacing has to be at least 1"));
+        }
+        if !(0.0..=1.0).contains(&def.chance) {
+            return Err(invalid("chance has to be between 0 and 1"));
+        }
+        let mut palette: FxHashMap<char, Block> = FxHashMap::default();
+        for (c, name) in def.palette.iter() {
+            let block = blockset
+                .block(name)
+                .ok_or_else(|| invalid(format!("unknown block {name:?} for {c:?}")))?;
+            palette.insert(*c, block);
+        }
+        let height = def.layers.len();
+        let depth = def.layers.first().map_or(0, |layer| layer.len());
+        let width = def
+            .layers
+            .first()
+            .and_then(|layer| layer.first())
+            .map_or(0, |row| row.chars().count());
+        if width == 0 || depth == 0 {
+            return Err(invalid("has no blocks"));
+        }
+        let mut blocks = Vec::with_capacity(width * depth * height);
+        for (z, layer) in def.layers.iter().enumerate() {
+            if layer.len() != depth {
+                return Err(invalid(format!(
+                    "layer {z} has {} rows instead of {depth}",
+                    layer.len()
+                )));
+            }
+            for (y, row) in layer.iter().enumerate() {
+                if row.chars().count() != width {
+                    return Err(invalid(format!(
+                        "row {y} of layer {z} is not {width} characters wide"
+                    )));
+                }
+                for c in row.chars() {
+                    blocks.push(match c {
+                        ' ' => None,
+                        c => Some(*palette.get(&c).ok_or_else(|| {
+                            invalid(format!("{c:?} in layer {z} is not in the palette"))
+                        })?),
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            name: def.name,
+            spacing: def.spacing,
+            chance: def.chance,
+            placement: def.placement,
+            offset: def.offset,
+            width,
+            depth,
+            height,
+            blocks,
+        })
+    }
+}
+
+/// deterministic random numbers of a placement region
+fn region_rng(seed: u32, structure: usize, region: ChunkPos) -> impl FnMut() -> u64 {
+    let mut state = (seed as u64) << 32 ^ (structure as u64).wrapping_mul(0x2545_f491_4f6c_dd1d);
+    state ^= (region.x as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    state ^= (region.y as u32 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    move || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut h = state;
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^ (h >> 31)
+    }
+}
+
+#[inline(always)]
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::generator::{TerrainGenerator, gen_area};
+
+    /// rock at the bottom and air above with `structures` on top
+    struct FlatGenerator {
+        structures: StructureSet,
+        seed: u32,
+    }
+    impl TerrainGenerator for FlatGenerator {
+        type ChunkData = ();
+        fn seed(&self) -> u32 {
+            self.seed
+        }
+        fn set_seed(&mut self, seed: u32) {
+            self.seed = seed;
+        }
+        fn get_chunk_data(&self, _cpos: ChunkPos) {}
+        fn gen_block(&self, wpos: WorldBlockPos, _chunk_data: &()) -> Block {
+            if wpos.z == 0 { Block::ROCK } else { Block::AIR }
+        }
+        fn ground_height(&self, _x: i32, _y: i32) -> Option<i32> {
+            Some(1)
+        }
+        fn structures(&self) -> Option<&StructureSet> {
+            Some(&self.structures)
+        }
+    }
+
+    fn structure(fields: &str) -> io::Result<StructureSet> {
+        StructureSet::from_ron(&format!("[({fields})]"), &BlockSet::normal())
+    }
+    /// blocks x and y of a start cover
+    fn bounds(set: &StructureSet, start: &StructureStart) -> (i32, i32, i32, i32) {
+        let (w, d) = set.structures[start.structure].rotated_size(start.rotation);
+        let o = start.origin;
+        (o.x, o.y, o.x + w as i32, o.y + d as i32)
+    }
+
+    #[test]
+    fn structures_line_up_across_chunks() {
+        let structures = structure(
+            r#"name: "wall", spacing: 4, chance: 0.5, placement: surface,
+            palette: {'r': "rock", 's': "sand"},
+            layers: [
+                ["rsssssssssssssssssss", "sssssssssssssssssssr", "rsrsrsrsrsrsrsrsrsrs"],
+                ["ssssssssssrrrrrrrrrr", "rrrrrrrrrrrrrrrrrrrr", "s                  s"],
+            ]"#,
+        )
+        .unwrap();
+        let ground = |_: i32, _: i32| Some(1);
+        // a seed whose start in the first region no other start overlaps
+        let (seed, start) = (0..)
+            .find_map(|seed| {
+                let start = structures.start(0, ChunkPos::default(), seed, &ground)?;
+                let (x0, y0, x1, y1) = bounds(&structures, &start);
+                let alone = around(0, 0, 1).all(|(rx, ry)| {
+                    let region = ChunkPos { x: rx, y: ry, z: 0 };
+                    (rx, ry) == (0, 0)
+                        || structures
+                            .start(0, region, seed, &ground)
+                            .is_none_or(|other| {
+                                let (ox0, oy0, ox1, oy1) = bounds(&structures, &other);
+                                ox1 <= x0 || x1 <= ox0 || oy1 <= y0 || y1 <= oy0
+                            })
+                });
+                alone.then_some((seed, start))
+            })
+            .unwrap();
+        let generator = FlatGenerator { structures, seed };
+        let def = &generator.structures.structures[0];
+        let (x0, y0, x1, y1) = bounds(&generator.structures, &start);
+        let first = ChunkPos::from(WorldBlockPos { x: x0, y: y0, z: 1 });
+        let last = ChunkPos::from(WorldBlockPos {
+            x: x1 - 1,
+            y: y1 - 1,
+            z: 1,
+        });
+        assert_ne!(first, last);
+        for cy in first.y..=last.y {
+            for cx in first.x..=last.x {
+                // every chunk on its own like a game that only loads one of them
+                let cpos = ChunkPos { x: cx, y: cy, z: 0 };
+                let chunk = &gen_area(&generator, cpos, cpos)[&cpos];
+                for z in 0..def.height {
+                    for y in y0..y1 {
+                        for x in x0..x1 {
+                            let wpos = WorldBlockPos {
+                                x,
+                                y,
+                                z: start.origin.z + z as i32,
+                            };
+                            if ChunkPos::from(wpos) != cpos {
+                                continue;
+                            }
+                            let (lx, ly) = ((x - x0) as usize, (y - y0) as usize);
+                            let expected = def
+                                .get_rotated(start.rotation, lx, ly, z)
+                                .unwrap_or(Block::AIR);
+                            assert_eq!(chunk.get(wpos.into()), Some(expected), "{wpos:?}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotations_turn_clockwise() {
+        // 1 2 3
+        // 4 5 6
+        let def = Structure {
+            name: "sign".to_string(),
+            spacing: 1,
+            chance: 1.0,
+            placement: StructurePlacement::Surface,
+            offset: 0,
+            width: 3,
+            depth: 2,
+            height: 1,
+            blocks: (1..=6).map(|b| Some(Block(b))).collect(),
+        };
+        let rotated = |rotation| {
+            let (w, d) = def.rotated_size(rotation);
+            (0..d)
+                .map(|y| {
+                    (0..w)
+                        .map(|x| def.get_rotated(rotation, x, y, 0).unwrap().0)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rotated(0), [[1, 2, 3], [4, 5, 6]]);
+        assert_eq!(rotated(1), [[4, 1], [5, 2], [6, 3]]);
+        assert_eq!(rotated(2), [[6, 5, 4], [3, 2, 1]]);
+        assert_eq!(rotated(3), [[3, 6], [2, 5], [1, 4]]);
+    }
+
+    #[test]
+    fn invalid_definitions_fail() {
+        let cases = [
+            (
+                r##"spacing: 0, chance: 1.0, palette: {}, layers: [["  "]]"##,
+                "spacing",
+            ),
+            (
+                r##"spacing: 1, chance: 1.5, palette: {}, layers: [["  "]]"##,
+                "chance",
+            ),
+            (
+                r##"spacing: 1, chance: 1.0, palette: {'#': "gold"}, layers: [["#"]]"##,
+                "unknown block",
+            ),
+            (
+                r##"spacing: 1, chance: 1.0, palette: {}, layers: []"##,
+                "no blocks",
+            ),
+            (
+                r##"spacing: 1, chance: 1.0, palette: {}, layers: [["  "], ["  ", "  "]]"##,
+                "layer 1 has 2 rows",
+            ),
+            (
+                r##"spacing: 1, chance: 1.0, palette: {}, layers: [["  ", " "]]"##,
+                "row 1 of layer 0",
+            ),
+            (
+                r##"spacing: 1, chance: 1.0, palette: {}, layers: [[" #"]]"##,
+                "not in the palette",
+            ),
+        ];
+        for (fields, message) in cases {
+            let err =
+                structure(&format!(r##"name: "bad", placement: surface, {fields}"##)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let text = err.to_string();
+            assert!(text.contains("\"bad\"") && text.contains(message), "{text}");
+        }
+    }
+
+    fn around(x: i32, y: i32, distance: i32) -> impl Iterator<Item = (i32, i32)> {
+        (y - distance..=y + distance)
+            .flat_map(move |ny| (x - distance..=x + distance).map(move |nx| (nx, ny)))
+    }
+}
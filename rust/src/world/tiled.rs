@@ -0,0 +1,822 @@
+use super::{
+    map::{Block, BlockMap, BlockSet},
+    schematic::{Schematic, SchematicTransform},
+    units::*,
+};
+use crate::components::{Body, Dimension};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use hecs::{Entity, World};
+use raylib::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+/// tiled stores flip flags in the upper bits of a gid
+const GID_MASK: u32 = 0x0fff_ffff;
+
+/// a tile layer or object layer map made in Tiled
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TiledMap {
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub layers: Vec<TiledLayer>,
+    pub objects: Vec<TiledObject>,
+    /// block names of tileset tiles with a `block` property by gid
+    pub tile_blocks: FxHashMap<u32, String>,
+}
+/// a tile layer placed at z level `z`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TiledLayer {
+    pub name: String,
+    pub z: usize,
+    pub chunks: Vec<TiledChunk>,
+}
+/// rectangle of gids in tiles, finite maps have one chunk at 0, 0
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TiledChunk {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub gids: Vec<u32>,
+}
+/// an object of an object layer in pixels
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TiledObject {
+    pub name: String,
+    /// the class or type of the object
+    pub kind: String,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub z: usize,
+    pub properties: FxHashMap<String, String>,
+}
+
+/// turns Tiled gids into `Block`s, gid 0 is always `Air`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TileMapping {
+    pub blocks: FxHashMap<u32, Block>,
+}
+
+/// blocks and entities of an imported `TiledMap`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TiledImport {
+    pub schematic: Schematic,
+    /// objects in block units relative to the schematic origin
+    pub objects: Vec<(TiledObject, Body)>,
+}
+
+impl TileMapping {
+    /// mapping from a json object of gids to block names like `{"1": "grass"}`
+    pub fn load(path: impl AsRef<Path>, blockset: &BlockSet) -> io::Result<Self> {
+        let table: FxHashMap<String, String> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let mut mapping = Self::default();
+        for (gid, name) in table {
+            let gid = gid
+                .parse()
+                .map_err(|err| invalid(format!("gid {gid:?}: {err}")))?;
+            let block = blockset
+                .block(&name)
+                .ok_or_else(|| invalid(format!("unknown block {name:?} for gid {gid}")))?;
+            mapping.blocks.insert(gid, block);
+        }
+        Ok(mapping)
+    }
+    /// mapping from the `block` properties of the tileset tiles of `map`
+    pub fn from_tiled(map: &TiledMap, blockset: &BlockSet) -> Self {
+        let mut mapping = Self::default();
+        for (gid, name) in map.tile_blocks.iter() {
+            match blockset.block(name) {
+                Some(block) => {
+                    mapping.blocks.insert(*gid, block);
+                }
+                None => eprintln!("warning: unknown block {name:?} on tiled tile {gid}"),
+            }
+        }
+        mapping
+    }
+    /// adds every entry of `other`, replacing existing gids
+    pub fn extend(&mut self, other: &TileMapping) {
+        self.blocks.extend(other.blocks.iter());
+    }
+    #[inline(always)]
+    pub fn get(&self, gid: u32) -> Option<Block> {
+        match gid & GID_MASK {
+            0 => Some(Block::AIR),
+            gid => self.blocks.get(&gid).copied(),
+        }
+    }
+}
+
+impl TiledMap {
+    /// loads a `.tmx` or `.tmj`/`.json` map, external tilesets are read next to it
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("tmx") => Self::from_tmx(&text, dir),
+            _ => Self::from_json(&text, dir),
+        }
+    }
+
+    /// turns the map into a `Schematic` with each tile layer at its z level,
+    /// the schematic origin is the upper left tile of all layers
+    pub fn import(&self, mapping: &TileMapping) -> TiledImport {
+        let mut min = (i32::MAX, i32::MAX);
+        let mut max = (i32::MIN, i32::MIN);
+        let mut height = 0;
+        for layer in self.layers.iter() {
+            height = height.max(layer.z + 1);
+            for chunk in layer.chunks.iter() {
+                min = (min.0.min(chunk.x), min.1.min(chunk.y));
+                max = (
+                    max.0.max(chunk.x + chunk.width as i32),
+                    max.1.max(chunk.y + chunk.height as i32),
+                );
+            }
+        }
+        if min.0 > max.0 {
+            min = (0, 0);
+            max = (0, 0);
+        }
+
+        let mut schematic =
+            Schematic::new_empty((max.0 - min.0) as usize, (max.1 - min.1) as usize, height);
+        let mut unknown = FxHashSet::default();
+        for layer in self.layers.iter() {
+            for chunk in layer.chunks.iter() {
+                for (i, gid) in chunk.gids.iter().enumerate() {
+                    let block = mapping.get(*gid).unwrap_or_else(|| {
+                        if unknown.insert(*gid & GID_MASK) {
+                            eprintln!("warning: no block for tiled gid {}", gid & GID_MASK);
+                        }
+                        Block::UNKNOWN
+                    });
+                    let x = chunk.x - min.0 + (i as u32 % chunk.width) as i32;
+                    let y = chunk.y - min.1 + (i as u32 / chunk.width) as i32;
+                    schematic.set(x as usize, y as usize, layer.z, block);
+                }
+            }
+        }
+
+        let objects = self
+            .objects
+            .iter()
+            .map(|object| {
+                let mut size = Vector3::new(
+                    object.width / self.tile_width as f32,
+                    object.height / self.tile_height as f32,
+                    1.0,
+                );
+                // point objects get the size of one block
+                if size.x <= 0.0 || size.y <= 0.0 {
+                    size = Vector3::one();
+                }
+                let body = Body {
+                    pos: Vector3::new(
+                        object.x / self.tile_width as f32 - min.0 as f32,
+                        object.y / self.tile_height as f32 - min.1 as f32,
+                        object.z as f32,
+                    ),
+                    size,
+                };
+                (object.clone(), body)
+            })
+            .collect();
+        TiledImport { schematic, objects }
+    }
+
+    fn from_json(text: &str, dir: &Path) -> io::Result<Self> {
+        let json: JsonMap = serde_json::from_str(text)?;
+        let mut map = TiledMap {
+            tile_width: json.tilewidth.max(1),
+            tile_height: json.tileheight.max(1),
+            ..Default::default()
+        };
+        for tileset in json.tilesets.iter() {
+            match &tileset.source {
+                Some(source) => {
+                    let text = fs::read_to_string(dir.join(source))?;
+                    let external: JsonTileset = serde_json::from_str(&text)?;
+                    map.add_tile_blocks(tileset.firstgid, &external.tiles);
+                }
+                None => map.add_tile_blocks(tileset.firstgid, &tileset.tiles),
+            }
+        }
+        let mut next_z = 0;
+        map.add_json_layers(&json.layers, &mut next_z)?;
+        Ok(map)
+    }
+    fn add_tile_blocks(&mut self, first_gid: u32, tiles: &[JsonTile]) {
+        for tile in tiles.iter() {
+            if let Some(block) = tile.properties.iter().find(|p| p.name == "block") {
+                self.tile_blocks
+                    .insert(first_gid + tile.id, json_string(&block.value));
+            }
+        }
+    }
+    fn add_json_layers(&mut self, layers: &[JsonLayer], next_z: &mut usize) -> io::Result<()> {
+        for layer in layers.iter().filter(|layer| layer.visible) {
+            let z = json_z(&layer.properties);
+            match layer.kind.as_str() {
+                "tilelayer" => {
+                    let z = z.unwrap_or(*next_z);
+                    *next_z = z + 1;
+                    let encoding = (layer.encoding.as_deref(), layer.compression.as_deref());
+                    let mut chunks = Vec::new();
+                    if let Some(data) = &layer.data {
+                        chunks.push(TiledChunk {
+                            x: 0,
+                            y: 0,
+                            width: layer.width,
+                            height: layer.height,
+                            gids: data.gids(encoding)?,
+                        });
+                    }
+                    for chunk in layer.chunks.iter() {
+                        chunks.push(TiledChunk {
+                            x: chunk.x,
+                            y: chunk.y,
+                            width: chunk.width,
+                            height: chunk.height,
+                            gids: chunk.data.gids(encoding)?,
+                        });
+                    }
+                    self.add_layer(layer.name.clone(), z, chunks)?;
+                }
+                "objectgroup" => {
+                    let z = z.unwrap_or(next_z.saturating_sub(1));
+                    for object in layer.objects.iter() {
+                        let mut properties = FxHashMap::default();
+                        for property in object.properties.iter() {
+                            properties.insert(property.name.clone(), json_string(&property.value));
+                        }
+                        // tile objects are anchored at their bottom left corner
+                        let y = if object.gid.is_some() {
+                            object.y - object.height
+                        } else {
+                            object.y
+                        };
+                        self.objects.push(TiledObject {
+                            name: object.name.clone(),
+                            kind: object.class.clone().unwrap_or(object.kind.clone()),
+                            x: object.x,
+                            y,
+                            width: object.width,
+                            height: object.height,
+                            z: json_z(&object.properties).unwrap_or(z),
+                            properties,
+                        });
+                    }
+                }
+                "group" => self.add_json_layers(&layer.layers, next_z)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn from_tmx(text: &str, dir: &Path) -> io::Result<Self> {
+        let doc = roxmltree::Document::parse(text).map_err(|err| invalid(err.to_string()))?;
+        let root = doc.root_element();
+        let mut map = TiledMap {
+            tile_width: tmx_attr::<u32>(root, "tilewidth")?.max(1),
+            tile_height: tmx_attr::<u32>(root, "tileheight")?.max(1),
+            ..Default::default()
+        };
+        for tileset in root.children().filter(|n| n.has_tag_name("tileset")) {
+            let first_gid = tmx_attr(tileset, "firstgid")?;
+            match tileset.attribute("source") {
+                Some(source) => {
+                    let text = fs::read_to_string(dir.join(source))?;
+                    let external = roxmltree::Document::parse(&text)
+                        .map_err(|err| invalid(err.to_string()))?;
+                    map.add_tmx_tile_blocks(first_gid, external.root_element())?;
+                }
+                None => map.add_tmx_tile_blocks(first_gid, tileset)?,
+            }
+        }
+        let mut next_z = 0;
+        map.add_tmx_layers(root, &mut next_z)?;
+        Ok(map)
+    }
+    fn add_tmx_tile_blocks(&mut self, first_gid: u32, tileset: roxmltree::Node) -> io::Result<()> {
+        for tile in tileset.children().filter(|n| n.has_tag_name("tile")) {
+            if let Some(block) = tmx_properties(tile).remove("block") {
+                self.tile_blocks
+                    .insert(first_gid + tmx_attr::<u32>(tile, "id")?, block);
+            }
+        }
+        Ok(())
+    }
+    fn add_tmx_layers(&mut self, parent: roxmltree::Node, next_z: &mut usize) -> io::Result<()> {
+        for layer in parent.children().filter(|n| n.is_element()) {
+            if layer.attribute("visible") == Some("0") {
+                continue;
+            }
+            let properties = tmx_properties(layer);
+            let z = properties.get("z").and_then(|z| z.parse().ok());
+            match layer.tag_name().name() {
+                "layer" => {
+                    let z = z.unwrap_or(*next_z);
+                    *next_z = z + 1;
+                    let Some(data) = layer.children().find(|n| n.has_tag_name("data")) else {
+                        continue;
+                    };
+                    let mut chunks = Vec::new();
+                    let tmx_chunks: Vec<_> = data
+                        .children()
+                        .filter(|n| n.has_tag_name("chunk"))
+                        .collect();
+                    if tmx_chunks.is_empty() {
+                        chunks.push(TiledChunk {
+                            x: 0,
+                            y: 0,
+                            width: tmx_attr(layer, "width")?,
+                            height: tmx_attr(layer, "height")?,
+                            gids: tmx_gids(data, data)?,
+                        });
+                    }
+                    for chunk in tmx_chunks {
+                        chunks.push(TiledChunk {
+                            x: tmx_attr(chunk, "x")?,
+                            y: tmx_attr(chunk, "y")?,
+                            width: tmx_attr(chunk, "width")?,
+                            height: tmx_attr(chunk, "height")?,
+                            gids: tmx_gids(data, chunk)?,
+                        });
+                    }
+                    self.add_layer(layer.attribute("name").unwrap_or("").into(), z, chunks)?;
+                }
+                "objectgroup" => {
+                    let z = z.unwrap_or(next_z.saturating_sub(1));
+                    for object in layer.children().filter(|n| n.has_tag_name("object")) {
+                        let properties = tmx_properties(object);
+                        let width = tmx_attr_or(object, "width", 0.0)?;
+                        let height = tmx_attr_or(object, "height", 0.0)?;
+                        let mut y = tmx_attr_or(object, "y", 0.0)?;
+                        // tile objects are anchored at their bottom left corner
+                        if object.attribute("gid").is_some() {
+                            y -= height;
+                        }
+                        self.objects.push(TiledObject {
+                            name: object.attribute("name").unwrap_or("").into(),
+                            kind: object
+                                .attribute("class")
+                                .or(object.attribute("type"))
+                                .unwrap_or("")
+                                .into(),
+                            x: tmx_attr_or(object, "x", 0.0)?,
+                            y,
+                            width,
+                            height,
+                            z: properties
+                                .get("z")
+                                .and_then(|z| z.parse().ok())
+                                .unwrap_or(z),
+                            properties,
+                        });
+                    }
+                }
+                "group" => self.add_tmx_layers(layer, next_z)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn add_layer(&mut self, name: String, z: usize, chunks: Vec<TiledChunk>) -> io::Result<()> {
+        for chunk in chunks.iter() {
+            let tiles = chunk
+                .width
+                .checked_mul(chunk.height)
+                .map(|tiles| tiles as usize);
+            if tiles != Some(chunk.gids.len()) {
+                return Err(invalid(format!(
+                    "layer {name:?} has {} tiles instead of {}x{}",
+                    chunk.gids.len(),
+                    chunk.width,
+                    chunk.height
+                )));
+            }
+        }
+        self.layers.push(TiledLayer { name, z, chunks });
+        Ok(())
+    }
+}
+
+impl TiledImport {
+    /// pastes the blocks with their upper left corner at `origin` into the
+    /// `BlockMap` called `map` and spawns an entity with a `Body` for each object
+    pub fn paste(&self, world: &mut World, map: &str, origin: WorldBlockPos) -> Vec<Entity> {
+        for block_map in world
+            .query_mut::<&mut BlockMap>()
+            .into_iter()
+            .filter(|block_map| block_map.name == map)
+        {
+            self.schematic
+                .paste(block_map, origin, SchematicTransform::default());
+        }
+        let offset = Vector3::new(origin.x as f32, origin.y as f32, origin.z as f32);
+        self.objects
+            .iter()
+            .map(|(_, body)| {
+                world.spawn((
+                    Body {
+                        pos: body.pos + offset,
+                        size: body.size,
+                    },
+                    Dimension::new(map),
+                ))
+            })
+            .collect()
+    }
+}
+
+#[inline(always)]
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// decodes base64 tile data with an optional compression into gids
+fn decode_gids(text: &str, compression: Option<&str>) -> io::Result<Vec<u32>> {
+    let bytes = STANDARD
+        .decode(text.trim())
+        .map_err(|err| invalid(format!("invalid base64 tile data: {err}")))?;
+    let mut raw = Vec::new();
+    match compression.unwrap_or("") {
+        "" => raw = bytes,
+        "zlib" => {
+            ZlibDecoder::new(bytes.as_slice()).read_to_end(&mut raw)?;
+        }
+        "gzip" => {
+            GzDecoder::new(bytes.as_slice()).read_to_end(&mut raw)?;
+        }
+        other => return Err(invalid(format!("unsupported tile compression {other:?}"))),
+    }
+    Ok(raw
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+fn json_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+fn json_z(properties: &[JsonProperty]) -> Option<usize> {
+    let z = properties.iter().find(|p| p.name == "z")?;
+    json_string(&z.value).parse().ok()
+}
+
+fn tmx_attr<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> io::Result<T> {
+    let value = node
+        .attribute(name)
+        .ok_or_else(|| invalid(format!("missing {name:?} on <{}>", node.tag_name().name())))?;
+    value
+        .parse()
+        .map_err(|_| invalid(format!("invalid {name:?} {value:?}")))
+}
+fn tmx_attr_or<T: std::str::FromStr>(
+    node: roxmltree::Node,
+    name: &str,
+    default: T,
+) -> io::Result<T> {
+    match node.attribute(name) {
+        Some(_) => tmx_attr(node, name),
+        None => Ok(default),
+    }
+}
+fn tmx_properties(node: roxmltree::Node) -> FxHashMap<String, String> {
+    let mut properties = FxHashMap::default();
+    for properties_node in node.children().filter(|n| n.has_tag_name("properties")) {
+        for property in properties_node
+            .children()
+            .filter(|n| n.has_tag_name("property"))
+        {
+            let Some(name) = property.attribute("name") else {
+                continue;
+            };
+            let value = property
+                .attribute("value")
+                .or(property.text())
+                .unwrap_or("");
+            properties.insert(name.into(), value.into());
+        }
+    }
+    properties
+}
+/// reads the gids of a `<data>` or `<chunk>` node with the encoding of `data`
+fn tmx_gids(data: roxmltree::Node, node: roxmltree::Node) -> io::Result<Vec<u32>> {
+    let text = node
+        .children()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect::<String>();
+    match data.attribute("encoding") {
+        Some("csv") => text
+            .split(',')
+            .map(str::trim)
+            .filter(|gid| !gid.is_empty())
+            .map(|gid| {
+                gid.parse()
+                    .map_err(|_| invalid(format!("invalid gid {gid:?}")))
+            })
+            .collect(),
+        Some("base64") => decode_gids(&text, data.attribute("compression")),
+        Some(other) => Err(invalid(format!("unsupported tile encoding {other:?}"))),
+        None => node
+            .children()
+            .filter(|n| n.has_tag_name("tile"))
+            .map(|tile| tmx_attr_or(tile, "gid", 0))
+            .collect(),
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonMap {
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+    #[serde(default)]
+    tilesets: Vec<JsonTilesetRef>,
+}
+#[derive(Deserialize)]
+struct JsonTilesetRef {
+    firstgid: u32,
+    source: Option<String>,
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+#[derive(Deserialize)]
+struct JsonTileset {
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+#[derive(Deserialize)]
+struct JsonTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+#[derive(Deserialize)]
+struct JsonProperty {
+    name: String,
+    value: serde_json::Value,
+}
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "json_true")]
+    visible: bool,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    data: Option<JsonData>,
+    #[serde(default)]
+    chunks: Vec<JsonChunk>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+#[derive(Deserialize)]
+struct JsonChunk {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    data: JsonData,
+}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonData {
+    Gids(Vec<u32>),
+    Base64(String),
+}
+impl JsonData {
+    fn gids(&self, (encoding, compression): (Option<&str>, Option<&str>)) -> io::Result<Vec<u32>> {
+        match self {
+            JsonData::Gids(gids) => Ok(gids.clone()),
+            JsonData::Base64(text) if encoding == Some("base64") => decode_gids(text, compression),
+            JsonData::Base64(_) => Err(invalid(format!(
+                "unsupported tile encoding {:?}",
+                encoding.unwrap_or("")
+            ))),
+        }
+    }
+}
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    class: Option<String>,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+fn json_true() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// grass, rock flipped horizontally, air and a gid without a block
+    const GIDS_CSV: &str = "1, 2147483650, 0, 7";
+    const GIDS_BASE64: &str = "AQAAAAIAAIAAAAAABwAAAA==";
+    const GIDS_ZLIB: &str = "eJxjZGBgYGJgaABSDOxADAAE1ACL";
+    /// rock and grass flipped vertically
+    const ROW_ZLIB: &str = "eJxjYmBgYGRgcAAAAFwARA==";
+
+    fn json(layer: &str) -> TiledMap {
+        let text = format!(
+            r#"{{
+                "tilewidth": 16,
+                "tileheight": 16,
+                "tilesets": [{{"firstgid": 1, "tiles": [
+                    {{"id": 0, "properties": [{{"name": "block", "type": "string", "value": "grass"}}]}},
+                    {{"id": 1, "properties": [{{"name": "block", "type": "string", "value": "rock"}}]}}
+                ]}}],
+                "layers": [
+                    {{"type": "tilelayer", "name": "ground", {layer}}},
+                    {{"type": "objectgroup", "objects": [
+                        {{"name": "door", "type": "portal", "x": 32, "y": 16, "width": 16, "height": 32,
+                            "properties": [{{"name": "target", "type": "string", "value": "underground"}}]}},
+                        {{"name": "chest", "gid": 1, "x": 0, "y": 32, "width": 16, "height": 16}}
+                    ]}}
+                ]
+            }}"#
+        );
+        TiledMap::from_json(&text, Path::new("")).unwrap()
+    }
+    fn tmx(layer: &str) -> TiledMap {
+        let text = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <map tilewidth="16" tileheight="16">
+                <tileset firstgid="1">
+                    <tile id="0"><properties><property name="block" value="grass"/></properties></tile>
+                    <tile id="1"><properties><property name="block" value="rock"/></properties></tile>
+                </tileset>
+                <layer name="ground" width="2" height="2">{layer}</layer>
+                <objectgroup>
+                    <object name="door" type="portal" x="32" y="16" width="16" height="32">
+                        <properties><property name="target" value="underground"/></properties>
+                    </object>
+                    <object name="chest" gid="1" x="0" y="32" width="16" height="16"/>
+                </objectgroup>
+            </map>"#
+        );
+        TiledMap::from_tmx(&text, Path::new("")).unwrap()
+    }
+    fn import(map: &TiledMap) -> TiledImport {
+        map.import(&TileMapping::from_tiled(map, &BlockSet::normal()))
+    }
+    /// blocks of the schematic row by row on level 0
+    fn rows(import: &TiledImport) -> Vec<Vec<Option<Block>>> {
+        let schematic = &import.schematic;
+        (0..schematic.depth)
+            .map(|y| {
+                (0..schematic.width)
+                    .map(|x| schematic.get(x, y, 0))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn encodings_give_the_same_blocks() {
+        let maps = [
+            json(&format!(r#""width": 2, "height": 2, "data": [{GIDS_CSV}]"#)),
+            json(&format!(
+                r#""width": 2, "height": 2, "encoding": "base64", "data": "{GIDS_BASE64}""#
+            )),
+            json(&format!(
+                r#""width": 2, "height": 2, "encoding": "base64", "compression": "zlib",
+                "data": "{GIDS_ZLIB}""#
+            )),
+            tmx(&format!(r#"<data encoding="csv">{GIDS_CSV}</data>"#)),
+            tmx(&format!(r#"<data encoding="base64">{GIDS_BASE64}</data>"#)),
+            tmx(&format!(
+                r#"<data encoding="base64" compression="zlib">{GIDS_ZLIB}</data>"#
+            )),
+        ];
+        for map in maps.iter() {
+            assert_eq!(
+                rows(&import(map)),
+                [
+                    [Some(Block::GRASS), Some(Block::ROCK)],
+                    [Some(Block::AIR), Some(Block::UNKNOWN)],
+                ],
+                "{map:?}"
+            );
+        }
+        assert_eq!(maps[0], maps[3]);
+    }
+
+    #[test]
+    fn infinite_maps_start_at_their_first_chunk() {
+        let from_json = json(&format!(
+            r#""encoding": "base64", "compression": "zlib", "chunks": [
+                {{"x": -2, "y": 0, "width": 2, "height": 1, "data": "{ROW_ZLIB}"}},
+                {{"x": 0, "y": 0, "width": 2, "height": 2, "data": "{GIDS_ZLIB}"}}
+            ]"#
+        ));
+        let from_tmx = tmx(&format!(
+            r#"<data encoding="base64" compression="zlib">
+                <chunk x="-2" y="0" width="2" height="1">{ROW_ZLIB}</chunk>
+                <chunk x="0" y="0" width="2" height="2">{GIDS_ZLIB}</chunk>
+            </data>"#
+        ));
+        assert_eq!(from_json.layers, from_tmx.layers);
+        let import = import(&from_json);
+        let (grass, rock, air) = (Some(Block::GRASS), Some(Block::ROCK), Some(Block::AIR));
+        // tiles outside of every chunk stay air
+        assert_eq!(
+            rows(&import),
+            [
+                [rock, grass, grass, rock],
+                [air, air, air, Some(Block::UNKNOWN)],
+            ]
+        );
+        // objects move with the origin of the schematic
+        assert_eq!(import.objects[0].1.pos, Vector3::new(4.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn flip_flags_are_masked() {
+        let mapping = TileMapping {
+            blocks: [(2, Block::ROCK)].into_iter().collect(),
+        };
+        for flags in [
+            0x8000_0000,
+            0x4000_0000,
+            0x2000_0000,
+            0x1000_0000,
+            0xf000_0000,
+        ] {
+            assert_eq!(mapping.get(2 | flags), Some(Block::ROCK), "{flags:x}");
+            assert_eq!(mapping.get(flags), Some(Block::AIR), "{flags:x}");
+        }
+        assert_eq!(mapping.get(3), None);
+    }
+
+    #[test]
+    fn objects_become_bodies() {
+        let layer = format!(r#"<data encoding="csv">{GIDS_CSV}</data>"#);
+        for map in [
+            tmx(&layer),
+            json(&format!(r#""width": 2, "height": 2, "data": [{GIDS_CSV}]"#)),
+        ] {
+            let import = import(&map);
+            let [(door, door_body), (chest, chest_body)] = &import.objects[..] else {
+                panic!("expected two objects in {map:?}");
+            };
+            assert_eq!((door.name.as_str(), door.kind.as_str()), ("door", "portal"));
+            assert_eq!(door.properties["target"], "underground");
+            assert_eq!(door_body.pos, Vector3::new(2.0, 1.0, 0.0));
+            assert_eq!(door_body.size, Vector3::new(1.0, 2.0, 1.0));
+            // tile objects hang from their bottom left corner
+            assert_eq!(chest.name, "chest");
+            assert_eq!(chest_body.pos, Vector3::new(0.0, 1.0, 0.0));
+            assert_eq!(chest_body.size, Vector3::one());
+        }
+    }
+
+    #[test]
+    fn oversized_layers_fail() {
+        let text = r#"{"tilewidth": 16, "tileheight": 16, "layers": [
+            {"type": "tilelayer", "width": 65536, "height": 65536, "data": []}
+        ]}"#;
+        let err = TiledMap::from_json(text, Path::new("")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
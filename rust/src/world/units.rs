@@ -4,7 +4,7 @@ use raylib::prelude::*;
 pub const TILE_SIZE: usize = 32;
 /// static chunk size
 pub const CHUNK_SIZE: usize = 16;
-/// static chunk size
+/// static chunk height, chunks are stacked for taller worlds
 pub const CHUNK_HEIGHT: usize = 8;
 /// static chunk volume for flat lists
 pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_HEIGHT;
@@ -52,15 +52,17 @@ impl ChunkBlockPos {
         WorldBlockPos {
             x: cpos.x * CHUNK_SIZE as i32 + self.x as i32,
             y: cpos.y * CHUNK_SIZE as i32 + self.y as i32,
-            z: self.z as i32,
+            z: cpos.z * CHUNK_HEIGHT as i32 + self.z as i32,
         }
     }
 }
-/// represents the position of a chunk
+/// represents the position of a chunk, `z` counts chunks of `CHUNK_HEIGHT` stacked
+/// on top of each other
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ChunkPos {
     pub x: i32,
     pub y: i32,
+    pub z: i32,
 }
 /// represents the position of a tile in the world
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
@@ -75,7 +77,7 @@ impl From<WorldBlockPos> for ChunkBlockPos {
         ChunkBlockPos {
             x: w.x.rem_euclid(CHUNK_SIZE as i32) as usize,
             y: w.y.rem_euclid(CHUNK_SIZE as i32) as usize,
-            z: w.z.rem_euclid(CHUNK_SIZE as i32) as usize,
+            z: w.z.rem_euclid(CHUNK_HEIGHT as i32) as usize,
         }
     }
 }
@@ -85,6 +87,7 @@ impl From<WorldBlockPos> for ChunkPos {
         ChunkPos {
             x: w.x.div_euclid(CHUNK_SIZE as i32),
             y: w.y.div_euclid(CHUNK_SIZE as i32),
+            z: w.z.div_euclid(CHUNK_HEIGHT as i32),
         }
     }
 }